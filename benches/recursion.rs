@@ -0,0 +1,70 @@
+//! Measures the evaluation of `examples/recursion.rsp`, whose lambdas are
+//! invoked hundreds of thousands of times, with each backend. It also
+//! compares the cost of a call to a lambda with a small body and to one
+//! whose body is large but mostly not evaluated, which should be the same.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use risp::{semantics::port::OutputPort, Backend, Interpreter};
+
+const RUNS: u32 = 20;
+
+/// The number of calls to each lambda of the body size comparison.
+const CALLS: u32 = 100_000;
+
+/// The number of expressions in the branch of the large body that is skipped.
+const LARGE_BODY: usize = 1_000;
+
+fn main() {
+    let recursion = include_str!("../examples/recursion.rsp");
+    let small = calls("n");
+    let large = calls(&format!(
+        "(if #t n (begin {}))",
+        "(+ n (* n 2) (- n 1)) ".repeat(LARGE_BODY),
+    ));
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        measure("recursion.rsp", recursion, backend);
+        for (name, script) in [("small body", &small), ("large body", &large)] {
+            if let Some(mean) = measure(name, script, backend) {
+                println!("  {:?} per call", mean / CALLS);
+            }
+        }
+    }
+}
+
+/// A script that calls a lambda with the given body `CALLS` times.
+fn calls(body: &str) -> String {
+    format!(
+        "(define f (lambda (n) {body}))
+        (let loop ((i 0)) (if (< i {CALLS}) (begin (f i) (loop (+ i 1))) i))"
+    )
+}
+
+/// Prints the times of evaluating the script and returns their mean.
+fn measure(name: &str, script: &str, backend: Backend) -> Option<Duration> {
+    let mut times = Vec::with_capacity(RUNS as usize);
+    for _ in 0..RUNS {
+        let mut interpreter: Interpreter = Interpreter::new()
+            .with_output(OutputPort::string())
+            .with_backend(backend);
+        let start = Instant::now();
+        if let Err(err) = black_box(interpreter.eval_str(script)) {
+            println!("{}", interpreter.print(err));
+            return None;
+        }
+
+        times.push(start.elapsed());
+    }
+
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / RUNS;
+    println!(
+        "{name} ({backend:?}): mean {mean:?}, median {:?}, min {:?} over {RUNS} runs",
+        times[times.len() / 2],
+        times[0],
+    );
+    Some(mean)
+}
@@ -0,0 +1,119 @@
+//! Registers built-in procedures that capture some state of the host, and
+//! evaluates a script that uses them.
+
+#![allow(clippy::result_large_err)]
+
+use std::{cell::RefCell, rc::Rc};
+
+use risp::{
+    semantics::{
+        built_in::{Arity, EvalBuiltIn},
+        env::{Environments, Roots},
+        env_tree::EnvironmentTree,
+        err::RuntimeError,
+        eval::Evaluator,
+        val::Val,
+    },
+    syntax::{
+        parse::Parser, print::PrintWithSymbols, symb::Symbols, symb_interner::SymbolsInterner,
+    },
+};
+
+fn main() {
+    let mut symbols = SymbolsInterner::new();
+    let mut environment = EnvironmentTree::empty(0);
+    if let Err(x) = EvalBuiltIn::load_prelude(&mut environment, &mut symbols) {
+        println!("Error loading {x} from prelude");
+        return;
+    }
+
+    // A counter owned by the host, which the script increments.
+    let count = Rc::new(RefCell::new(0));
+    let counter = count.clone();
+    let tick = EvalBuiltIn::closure("tick!", Arity::Between(0, 1), move |vs, _| {
+        let step = match vs.into_iter().next() {
+            Some(Val::Numb(n)) => n,
+            Some(_) => return Err(RuntimeError::InvalidArguments()),
+            None => 1,
+        };
+
+        *counter.borrow_mut() += step;
+        Ok(Val::Numb(*counter.borrow()))
+    });
+
+    // A log of messages kept by the host.
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let messages = log.clone();
+    let record = EvalBuiltIn::closure("record!", Arity::Exactly(1), move |vs, _| {
+        match vs.into_iter().next() {
+            Some(Val::Str(s)) => {
+                messages.borrow_mut().push(s.to_string());
+                Ok(Val::Void())
+            }
+            _ => Err(RuntimeError::InvalidArguments()),
+        }
+    });
+
+    // Procedures kept by the host, which are rooted so that the collections
+    // keep their environments until they are called.
+    let roots = Roots::new();
+    let kept = roots.clone();
+    let later = Rc::new(RefCell::new(Vec::new()));
+    let pending = later.clone();
+    let defer = EvalBuiltIn::closure("defer!", Arity::Exactly(1), move |vs, _| {
+        pending
+            .borrow_mut()
+            .extend(vs.into_iter().map(|v| kept.root(v)));
+        Ok(Val::Void())
+    });
+
+    let root = environment.root();
+    for f in [tick, record, defer] {
+        let name = f.name().unwrap_or_default().to_string();
+        let x = symbols.get_or_store(&name);
+        if environment.define(root, x, Val::BuiltIn(f)).is_err() {
+            println!("Error defining {name}");
+            return;
+        }
+    }
+
+    let script = r#"
+        (tick!)
+        (tick! 10)
+        (record! "ticked twice")
+        (record! (number->string (tick!)))
+        (let ((n (tick!))) (defer! (lambda () (record! (number->string (* n 2))))))
+        (tick! 1 2)
+    "#;
+
+    let mut parser = Parser::new(&mut symbols);
+    let program = match parser.parse_all_exps::<bool, i64>(script) {
+        Ok(program) => program,
+        Err(err) => {
+            println!("{err}");
+            return;
+        }
+    };
+
+    let mut evaluator = Evaluator::new(&mut symbols, &mut environment).with_roots(roots);
+    for expression in program {
+        if let Err(err) = evaluator.eval(expression) {
+            match err.print_with(evaluator.symbols()) {
+                Ok(s) => println!("{s}"),
+                Err(_) => println!("Unknown symbol when trying to print an error"),
+            }
+        }
+    }
+
+    for f in later.take() {
+        if let Err(err) = evaluator.apply(f.get(), Vec::new()) {
+            match err.print_with(evaluator.symbols()) {
+                Ok(s) => println!("{s}"),
+                Err(_) => println!("Unknown symbol when trying to print an error"),
+            }
+        }
+    }
+
+    println!("count: {}", count.borrow());
+    println!("log: {:?}", log.borrow());
+}
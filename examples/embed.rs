@@ -0,0 +1,98 @@
+//! Embeds the interpreter to evaluate scripts, exchange global variables and
+//! call procedures from Rust, captures the output of a script and reports the
+//! collections of unreachable environments.
+
+use std::error::Error;
+
+use risp::{
+    semantics::{
+        convert::{FromVal, IntoVal},
+        port::OutputPort,
+    },
+    Interpreter,
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut interpreter: Interpreter = Interpreter::new();
+    interpreter.define_global("limit", 10);
+    let v = interpreter.eval_str("(* limit 2)")?;
+    println!("{}", interpreter.print(v));
+
+    interpreter.register("square", |n: i64| n * n);
+
+    let script = r#"
+        (define squares (lambda (n)
+          (if (<= n 0) '() (cons (square n) (squares (- n 1))))))
+        (define total 0)
+        (define add! (lambda (n) (begin (set! total (+ total n)) total)))
+        (squares limit)
+    "#;
+
+    match interpreter.eval_str(script) {
+        Ok(v) => println!("squares: {}", interpreter.print(v)),
+        Err(err) => println!("{}", interpreter.print(err)),
+    }
+
+    for n in [5, 7] {
+        if let Err(err) = interpreter.call("add!", vec![n.into_val()]) {
+            println!("{}", interpreter.print(err));
+        }
+    }
+
+    match interpreter.get_global("total").map(i64::from_val) {
+        Some(Ok(total)) => println!("total: {total}"),
+        _ => println!("total is not a number"),
+    }
+
+    for script in ["(car '())", "(+ 1", "(undefined)"] {
+        if let Err(err) = interpreter.eval_str(script) {
+            println!("{}", interpreter.print(err));
+        }
+    }
+
+    if let Err(err) = interpreter.call("missing", Vec::new()) {
+        println!("{}", interpreter.print(err));
+    }
+
+    if let Err(err) = interpreter.eval_file("missing.rsp") {
+        println!("{}", interpreter.print(err));
+    }
+
+    // The output of scripts can be captured rather than written to the
+    // standard output.
+    let output = OutputPort::string();
+    let mut captured: Interpreter = Interpreter::new().with_output(output.clone());
+    if let Err(err) = captured.eval_str("(display (list 1 2 3))(newline)") {
+        println!("{}", captured.print(err));
+    }
+
+    println!("captured: {:?}", output.contents().unwrap_or_default());
+
+    // Each lambda assigned to `last` escapes its environment, which is only
+    // dropped once a collection finds it unreachable.
+    let mut collected: Interpreter = Interpreter::new().with_gc_threshold(100);
+    let script = r#"
+        (define last #f)
+        (define keep! (lambda (n) (let ((x n)) (set! last (lambda () x)))))
+        (define loop (lambda (n) (if (<= n 0) (last) (begin (keep! n) (loop (- n 1))))))
+        (loop 1000)
+    "#;
+
+    match collected.eval_str(script) {
+        Ok(v) => println!("last: {}", collected.print(v)),
+        Err(err) => println!("{}", collected.print(err)),
+    }
+
+    let stats = collected.gc_stats();
+    println!(
+        "collections: {}, collected: {}, live: {}",
+        stats.collections, stats.collected, stats.live
+    );
+
+    // The errors can also be displayed, as when propagated with `?` as above.
+    if let Err(err) = interpreter.eval_str("(car 1)") {
+        println!("{err}");
+    }
+
+    Ok(())
+}
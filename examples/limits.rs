@@ -0,0 +1,61 @@
+//! Bounds the evaluation of untrusted scripts with fuel, a timeout and a
+//! cancellation flag set from another thread, on both backends.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use risp::{Backend, Interpreter};
+
+const SPIN: &str = "(define f (lambda () (f))) (f)";
+
+fn run(name: &str, interpreter: &mut Interpreter, script: &str) {
+    match interpreter.eval_str(script) {
+        Ok(v) => println!("{name}: {}", interpreter.print(v)),
+        Err(err) => println!("{name}: {}", interpreter.print(err)),
+    }
+}
+
+fn main() {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        println!("{backend:?}");
+
+        let mut fueled: Interpreter = Interpreter::new().with_backend(backend).with_fuel(10_000);
+        run("fuel", &mut fueled, SPIN);
+        // The handlers of the script cannot catch the error to keep running.
+        run("guarded", &mut fueled, "(guard (e (#t 'caught)) (f))");
+        // Each evaluation has its own fuel, and the environment is still usable.
+        run(
+            "after",
+            &mut fueled,
+            "(define g (lambda (n) (* n 2))) (g 21)",
+        );
+
+        let mut timed: Interpreter = Interpreter::new()
+            .with_backend(backend)
+            .with_timeout(Duration::from_millis(50));
+        run("timeout", &mut timed, SPIN);
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut cancelled: Interpreter = Interpreter::new()
+            .with_backend(backend)
+            .with_cancellation(cancel.clone());
+        let canceller = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                cancel.store(true, Ordering::Relaxed);
+            })
+        };
+
+        run("cancel", &mut cancelled, SPIN);
+        let _ = canceller.join();
+        cancel.store(false, Ordering::Relaxed);
+        run("after", &mut cancelled, "(+ 1 2)");
+    }
+}
@@ -0,0 +1,65 @@
+//! Registers Rust functions whose arguments and results are converted from
+//! and into values, and evaluates a script that uses them.
+
+#![allow(clippy::result_large_err)]
+
+use std::rc::Rc;
+
+use risp::{
+    semantics::{built_in::EvalBuiltIn, env_tree::EnvironmentTree, eval::Evaluator},
+    syntax::{parse::Parser, print::PrintWithSymbols, symb_interner::SymbolsInterner},
+};
+
+fn main() {
+    let mut symbols = SymbolsInterner::new();
+    let mut environment = EnvironmentTree::empty(0);
+    if let Err(x) = EvalBuiltIn::load_prelude(&mut environment, &mut symbols) {
+        println!("Error loading {x} from prelude");
+        return;
+    }
+
+    let script = r#"
+        (display (clamp 15 0 10))(newline)
+        (display (repeat "ab" 3))(newline)
+        (display (sum '(1 2 3 4)))(newline)
+        (display (evens '(3 1 4 1 5 9 2 6)))(newline)
+        (clamp 1 2)
+        (clamp "1" 2 3)
+        (sum '(1 two 3))
+    "#;
+
+    let mut parser = Parser::new(&mut symbols);
+    let program = match parser.parse_all_exps::<bool, i64>(script) {
+        Ok(program) => program,
+        Err(err) => {
+            println!("{err}");
+            return;
+        }
+    };
+
+    let mut evaluator = Evaluator::new(&mut symbols, &mut environment);
+    let registered = [
+        evaluator.register("clamp", |x: i64, lo: i64, hi: i64| x.clamp(lo, hi)),
+        evaluator.register("repeat", |s: Rc<str>, n: i64| {
+            s.repeat(n.try_into().unwrap_or_default())
+        }),
+        evaluator.register("sum", |ns: Vec<i64>| ns.into_iter().sum::<i64>()),
+        evaluator.register("evens", |ns: Vec<i64>| {
+            ns.into_iter().filter(|n| n % 2 == 0).collect::<Vec<_>>()
+        }),
+    ];
+
+    if registered.iter().any(Result::is_err) {
+        println!("Error registering the functions");
+        return;
+    }
+
+    for expression in program {
+        if let Err(err) = evaluator.eval(expression) {
+            match err.print_with(evaluator.symbols()) {
+                Ok(s) => println!("{s}"),
+                Err(_) => println!("Unknown symbol when trying to print an error"),
+            }
+        }
+    }
+}
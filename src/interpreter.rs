@@ -0,0 +1,416 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    fs, io,
+    iter::{Product, Sum},
+    ops::Sub,
+    path::Path,
+    str::FromStr,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    semantics::{
+        built_in::EvalBuiltIn,
+        convert::{IntoBuiltIn, IntoVal},
+        env::{Environments, GcStats, Rooted, Roots, Trace, Tracer},
+        env_frames::{EnvironmentFrames, FrameId},
+        err::RuntimeError,
+        eval::{Evaluator, DEFAULT_MAX_DEPTH},
+        limits::Limits,
+        macros::Renames,
+        port::OutputPort,
+        val::Val,
+        vm::Vm,
+    },
+    syntax::{
+        parse::{ParseError, Parser},
+        print::{PrintError, PrintWithSymbols},
+        symb::Symbols,
+        symb_interner::SymbolsInterner,
+    },
+};
+
+type Symb = <SymbolsInterner as Symbols>::Symb;
+
+/// A built-in procedure of an `Interpreter`.
+pub type BuiltIn<Bool = bool, Numb = i64> = EvalBuiltIn<Bool, Numb, Symb, FrameId, SymbolsInterner>;
+
+/// A value of an `Interpreter`, which can be printed with `Interpreter::print`
+/// and converted with `FromVal`.
+pub type Value<Bool = bool, Numb = i64> = Val<Bool, Numb, Symb, FrameId, BuiltIn<Bool, Numb>>;
+
+/// Errors that can arise when reading, parsing or evaluating with an
+/// `Interpreter`, which can be displayed or printed with `Interpreter::print`.
+#[derive(Debug)]
+pub enum InterpreterError<Bool = bool, Numb = i64> {
+    /// A file that could not be read.
+    Io(String, io::Error),
+    Parse(ParseError),
+    /// An error of the evaluation, along with its message printed with the
+    /// symbols of the interpreter.
+    Runtime(Box<RuntimeError<Symb, Value<Bool, Numb>>>, String),
+}
+
+/// The way an `Interpreter` evaluates expressions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Walks the expressions with an `Evaluator`.
+    #[default]
+    TreeWalker,
+    /// Compiles the expressions to bytecode to execute them with a `Vm`.
+    Bytecode,
+}
+
+/// An interpreter of Risp scripts, which owns the symbols and the environment
+/// with the prelude, to embed the language in an application.
+pub struct Interpreter<Bool = bool, Numb = i64> {
+    symbols: SymbolsInterner,
+    environment: EnvironmentFrames<Symb, Value<Bool, Numb>>,
+    redefine_top_level: bool,
+    max_depth: usize,
+    fuel: Option<usize>,
+    timeout: Option<Duration>,
+    cancel: Option<Arc<AtomicBool>>,
+    output: OutputPort,
+    renames: Renames<Symb, FrameId>,
+    compiled_renames: Renames<Symb, usize>,
+    backend: Backend,
+    roots: Roots<Value<Bool, Numb>>,
+}
+
+impl<Bool, Numb> Interpreter<Bool, Numb>
+where
+    Bool: From<bool> + Into<bool> + PartialEq + Clone,
+    Numb: Sum
+        + Product
+        + Sub<Output = Numb>
+        + PartialEq
+        + PartialOrd
+        + Display
+        + TryFrom<usize>
+        + TryInto<usize>
+        + FromStr
+        + Clone,
+{
+    /// Creates an interpreter whose environment only has the prelude.
+    pub fn new() -> Self {
+        let mut symbols = SymbolsInterner::new();
+        let mut environment = EnvironmentFrames::empty(0);
+        // The prelude defines distinct variables in an empty environment, so
+        // that it cannot fail.
+        let _ = EvalBuiltIn::load_prelude(&mut environment, &mut symbols);
+        Self {
+            symbols,
+            environment,
+            redefine_top_level: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel: None,
+            timeout: None,
+            cancel: None,
+            output: OutputPort::stdout(),
+            renames: Renames::new(),
+            compiled_renames: Renames::new(),
+            backend: Backend::default(),
+            roots: Roots::new(),
+        }
+    }
+
+    /// Allows or forbids `define` to overwrite an existing global variable,
+    /// which is forbidden by default.
+    pub fn with_top_level_redefinition(mut self, allow: bool) -> Self {
+        self.redefine_top_level = allow;
+        self
+    }
+
+    /// Sets the maximum number of frames in the continuation stack, which is
+    /// `DEFAULT_MAX_DEPTH` by default.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the number of steps that each call to `eval_str`, `eval_file` or
+    /// `call` can take, beyond which it fails with `RuntimeError::FuelExhausted`.
+    pub fn with_fuel(mut self, fuel: usize) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Sets the time that each call to `eval_str`, `eval_file` or `call` can
+    /// take, beyond which it fails with `RuntimeError::DeadlineExceeded`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets a flag that can be set from another thread to stop the evaluation
+    /// with `RuntimeError::Cancelled`, which fails the following ones as well
+    /// until the flag is unset.
+    pub fn with_cancellation(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Sets the port where scripts write with `display` and the like, which
+    /// is the standard output by default. A string port can be used to capture
+    /// the output, to be read later with `OutputPort::contents`.
+    pub fn with_output(mut self, output: OutputPort) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Sets the way expressions are evaluated, which is walking them by
+    /// default. Values of one backend cannot be applied by the other one.
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets the minimum number of environments pushed between collections of
+    /// the unreachable ones, which is `DEFAULT_GC_THRESHOLD` by default.
+    pub fn with_gc_threshold(mut self, threshold: usize) -> Self {
+        self.environment = self.environment.with_gc_threshold(threshold);
+        self
+    }
+
+    /// Evaluates the expressions of a script in order, returning the value of
+    /// the last one, or void if there is none, unless one of them fails.
+    pub fn eval_str(
+        &mut self,
+        input: &str,
+    ) -> Result<Value<Bool, Numb>, InterpreterError<Bool, Numb>> {
+        self.eval_script(input, None)
+    }
+
+    /// Evaluates the expressions of a script file as `eval_str`, where runtime
+    /// errors point to the offending expression of the file.
+    pub fn eval_file(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Value<Bool, Numb>, InterpreterError<Bool, Numb>> {
+        let path = path.as_ref().display().to_string();
+        match fs::read_to_string(&path) {
+            Ok(input) => self.eval_script(&input, Some(&path)),
+            Err(err) => Err(InterpreterError::Io(path, err)),
+        }
+    }
+
+    fn eval_script(
+        &mut self,
+        input: &str,
+        file: Option<&str>,
+    ) -> Result<Value<Bool, Numb>, InterpreterError<Bool, Numb>> {
+        let mut parser = match file {
+            Some(file) => Parser::with_file(&mut self.symbols, file),
+            None => Parser::new(&mut self.symbols),
+        };
+
+        let program = parser
+            .parse_all_exps::<Bool, Numb>(input)
+            .map_err(InterpreterError::Parse)?;
+
+        match self.backend {
+            Backend::TreeWalker => {
+                let mut evaluator = self.evaluator();
+                program
+                    .into_iter()
+                    .try_fold(Val::Void(), |_, expression| evaluator.eval(expression))
+            }
+            Backend::Bytecode => {
+                let mut vm = self.vm();
+                program
+                    .into_iter()
+                    .try_fold(Val::Void(), |_, expression| vm.eval(expression))
+            }
+        }
+        .map_err(|err| self.runtime_error(err))
+    }
+
+    /// Defines a global variable, or overwrites it if it is already defined.
+    pub fn define_global(
+        &mut self,
+        name: &str,
+        v: impl IntoVal<Bool, Numb, Symb, FrameId, BuiltIn<Bool, Numb>>,
+    ) {
+        let x = self.symbols.get_or_store(name);
+        let root = self.environment.root();
+        if let Err((x, v)) = self.environment.define(root, x, v.into_val()) {
+            // A variable of the root environment can always be set once defined.
+            let _ = self.environment.set(root, &x, v);
+        }
+    }
+
+    /// Returns the value of a global variable, if defined.
+    pub fn get_global(&self, name: &str) -> Option<Value<Bool, Numb>> {
+        let x = self.symbols.get(name)?;
+        self.environment.get(self.environment.root(), &x).cloned()
+    }
+
+    /// Defines a global built-in procedure from a Rust function, whose
+    /// arguments and result are converted from and into values.
+    pub fn register<Args>(
+        &mut self,
+        name: &str,
+        f: impl IntoBuiltIn<Args, Bool, Numb, Symb, FrameId, SymbolsInterner>,
+    ) {
+        self.define_global(name, Val::BuiltIn(f.into_built_in(name)));
+    }
+
+    /// Applies the procedure of a global variable to some values.
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: Vec<Value<Bool, Numb>>,
+    ) -> Result<Value<Bool, Numb>, InterpreterError<Bool, Numb>> {
+        let f = match self.get_global(name) {
+            Some(f) => f,
+            None => {
+                let x = self.symbols.get_or_store(name);
+                return Err(self.runtime_error(RuntimeError::UndefinedVariable(x)));
+            }
+        };
+
+        match self.backend {
+            Backend::TreeWalker => self.evaluator().apply(f, args),
+            Backend::Bytecode => self.vm().apply(f, args),
+        }
+        .map_err(|err| self.runtime_error(err))
+    }
+
+    /// Roots a value held by the application, as a procedure returned by a
+    /// script, so that the environments it references are not collected until
+    /// the returned handle is dropped, and it can still be applied after later
+    /// evaluations.
+    pub fn root(&self, v: Value<Bool, Numb>) -> Rooted<Value<Bool, Numb>> {
+        self.roots.root(v)
+    }
+
+    /// Returns the roots of the interpreter, which a built-in procedure can
+    /// capture to root the values it keeps beyond its application.
+    pub fn roots(&self) -> Roots<Value<Bool, Numb>> {
+        self.roots.clone()
+    }
+
+    /// Drops the environments that are not reachable from the global variables
+    /// or from the rooted values, which are collected anyway during evaluation
+    /// once enough of them are pushed.
+    pub fn collect_garbage(&mut self) {
+        let mut tracer = Tracer::new(Vec::new());
+        self.roots.trace(&mut tracer);
+        self.environment.collect(tracer);
+    }
+
+    /// Returns the statistics of the collections of unreachable environments.
+    pub fn gc_stats(&self) -> GcStats {
+        self.environment.stats()
+    }
+
+    /// Prints a value or an error with the symbols of the interpreter.
+    pub fn print(&self, p: impl PrintWithSymbols<SymbolsInterner>) -> String {
+        match p.print_with(&self.symbols) {
+            Ok(s) => s,
+            Err(PrintError::UnknownSymbol(s)) => {
+                format!("Unknown symbol when trying to print: {s:?}")
+            }
+        }
+    }
+
+    /// Wraps an error of the evaluation along with its message.
+    fn runtime_error(
+        &self,
+        err: RuntimeError<Symb, Value<Bool, Numb>>,
+    ) -> InterpreterError<Bool, Numb> {
+        let message = self.print(err.clone());
+        InterpreterError::Runtime(Box::new(err), message)
+    }
+
+    fn evaluator(
+        &mut self,
+    ) -> Evaluator<'_, Value<Bool, Numb>, SymbolsInterner, EnvironmentFrames<Symb, Value<Bool, Numb>>>
+    {
+        let limits = self.limits();
+        Evaluator::new(&mut self.symbols, &mut self.environment)
+            .with_top_level_redefinition(self.redefine_top_level)
+            .with_max_depth(self.max_depth)
+            .with_limits(limits)
+            .with_output(self.output.clone())
+            .with_renames(self.renames.clone())
+            .with_roots(self.roots.clone())
+    }
+
+    fn vm(
+        &mut self,
+    ) -> Vm<'_, Value<Bool, Numb>, SymbolsInterner, EnvironmentFrames<Symb, Value<Bool, Numb>>>
+    {
+        let limits = self.limits();
+        Vm::new(&mut self.symbols, &mut self.environment)
+            .with_top_level_redefinition(self.redefine_top_level)
+            .with_max_depth(self.max_depth)
+            .with_limits(limits)
+            .with_output(self.output.clone())
+            .with_renames(self.compiled_renames.clone())
+    }
+
+    /// Returns the bounds of an evaluation that starts now.
+    fn limits(&self) -> Limits {
+        let mut limits = Limits::new();
+        if let Some(fuel) = self.fuel {
+            limits = limits.with_fuel(fuel);
+        }
+
+        if let Some(timeout) = self.timeout {
+            limits = limits.with_deadline(Instant::now() + timeout);
+        }
+
+        if let Some(cancel) = &self.cancel {
+            limits = limits.with_cancellation(cancel.clone());
+        }
+
+        limits
+    }
+}
+
+impl<Bool, Numb> Default for Interpreter<Bool, Numb>
+where
+    Bool: From<bool> + Into<bool> + PartialEq + Clone,
+    Numb: Sum
+        + Product
+        + Sub<Output = Numb>
+        + PartialEq
+        + PartialOrd
+        + Display
+        + TryFrom<usize>
+        + TryInto<usize>
+        + FromStr
+        + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Bool: Into<bool> + Clone, Numb: Display + Clone> PrintWithSymbols<SymbolsInterner>
+    for InterpreterError<Bool, Numb>
+{
+    fn print_with(self, symbols: &SymbolsInterner) -> Result<String, PrintError<Symb>> {
+        match self {
+            InterpreterError::Io(path, err) => Ok(format!("Error reading {path}: {err}")),
+            InterpreterError::Parse(err) => Ok(err.to_string()),
+            InterpreterError::Runtime(err, _) => err.print_with(symbols),
+        }
+    }
+}
+
+impl<Bool, Numb> Display for InterpreterError<Bool, Numb> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpreterError::Io(path, err) => write!(f, "Error reading {path}: {err}"),
+            InterpreterError::Parse(err) => write!(f, "{err}"),
+            InterpreterError::Runtime(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl<Bool: Debug, Numb: Debug> Error for InterpreterError<Bool, Numb> {}
@@ -1,18 +1,39 @@
+#![allow(clippy::type_complexity)]
+
+pub mod interpreter;
+
+pub use interpreter::{Backend, Interpreter};
+
 pub mod syntax {
     pub mod exp;
     pub mod parse;
     pub mod print;
+    pub mod span;
     pub mod symb;
     pub mod symb_interner;
 }
 
 pub mod semantics {
     pub mod built_in;
+    pub mod bytecode;
+    pub mod compile;
+    pub mod condition;
+    pub mod cont;
+    pub mod convert;
     pub mod env;
+    pub mod env_frames;
     pub mod env_tree;
     pub mod err;
     pub mod eval;
+    pub mod limits;
+    pub mod macros;
+    pub mod params;
+    pub mod port;
     pub mod prelude;
+    pub mod quasi;
     pub mod res;
+    pub mod resolve;
+    pub mod syntax_rules;
     pub mod val;
+    pub mod vm;
 }
@@ -1,56 +1,108 @@
-use std::io::{self, Read};
+use std::{
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
+};
 
 use risp::{
-    semantics::{built_in::EvalBuiltIn, env_tree::EnvironmentTree, eval::Evaluator},
-    syntax::{
-        parse::Parser,
-        print::{PrintError, PrintWithSymbols},
-        symb_interner::SymbolsInterner,
-    },
+    interpreter::InterpreterError, semantics::val::Val, syntax::parse::ParseError, Backend,
+    Interpreter,
 };
+use rustyline::{error::ReadlineError, DefaultEditor};
 
 fn main() {
-    let mut buffer = Vec::new();
-    if let Err(err) = io::stdin().read_to_end(&mut buffer) {
-        println!("Error reading input: {err}");
-        return;
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let backend = match args.iter().position(|arg| arg == "--vm") {
+        Some(i) => {
+            args.remove(i);
+            Backend::Bytecode
+        }
+        None => Backend::TreeWalker,
+    };
+
+    let mut interpreter: Interpreter = Interpreter::new().with_backend(backend);
+    if let Some(i) = args.iter().position(|arg| arg == "--max-depth") {
+        let depth = args.get(i + 1).and_then(|depth| depth.parse().ok());
+        match depth {
+            Some(depth) => interpreter = interpreter.with_max_depth(depth),
+            None => {
+                println!("Expected a number of frames after --max-depth");
+                return;
+            }
+        }
+
+        args.drain(i..i + 2);
     }
 
-    let input = match std::str::from_utf8(&buffer) {
-        Ok(input) => input,
-        Err(err) => {
-            println!("Error decoding input: {err}");
-            return;
+    let result = match args.into_iter().next() {
+        Some(path) => interpreter.eval_file(path),
+        None if io::stdin().is_terminal() => return repl(interpreter),
+        None => {
+            let mut input = String::new();
+            if let Err(err) = io::stdin().read_to_string(&mut input) {
+                println!("Error reading input: {err}");
+                return;
+            }
+
+            interpreter.eval_str(&input)
         }
     };
 
-    let mut symbols = SymbolsInterner::new();
-    let mut environment = EnvironmentTree::empty(0);
-    if let Err(x) = EvalBuiltIn::load_prelude(&mut environment, &mut symbols) {
-        println!("Error loading {x} from prelude");
-        return;
-    };
+    if let Err(err) = result {
+        println!("{err}");
+    }
+}
 
-    let mut parser = Parser::new(&mut symbols);
-    let program = match parser.parse_all_exps::<bool, i64>(input) {
-        Ok((_, program)) => program,
+/// Reads, evaluates and prints expressions interactively, keeping the
+/// definitions of previous inputs, until the end of the input.
+fn repl(interpreter: Interpreter) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
         Err(err) => {
-            println!("Error parsing the program: {err}");
+            println!("Error starting the REPL: {err}");
             return;
         }
     };
 
-    let mut evaluator = Evaluator::new(&symbols, &mut environment);
-    for expression in program {
-        if let Err(err) = evaluator.eval(expression) {
-            match err.print_with(&symbols) {
-                Err(PrintError::UnknownSymbol(s)) => {
-                    println!("Unknown symbol when trying to print a runtime error: {s:?}")
-                }
-                Ok(err) => {
-                    println!("Runtime error: {err}")
-                }
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".risp_history"));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    let mut interpreter = interpreter.with_top_level_redefinition(true);
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "risp> " } else { "  ... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Error reading input: {err}");
+                break;
             }
         }
+
+        match interpreter.eval_str(&input) {
+            // An unbalanced parenthesis or string means that the input continues in the next line.
+            Err(InterpreterError::Parse(
+                ParseError::UnclosedList(_) | ParseError::UnclosedString(_),
+            )) => continue,
+            Ok(Val::Void()) => (),
+            Ok(v) => println!("{}", interpreter.print(v)),
+            Err(err) => println!("{err}"),
+        }
+
+        let _ = editor.add_history_entry(input.trim_end());
+        input.clear();
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
     }
 }
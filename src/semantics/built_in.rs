@@ -1,39 +1,206 @@
-use super::{res::EvalResult, val::Val};
+use std::{cell::RefCell, rc::Rc};
+
+use super::{err::RuntimeError, port::OutputPort, res::EvalResult, val::Val};
+
+/// A closure of a built-in procedure, which can capture and mutate some state
+/// of the host application.
+type Closure<Bool, Numb, Symb, Env, Symbs> =
+    dyn FnMut(
+        Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>;
+
+/// The numbers of arguments that a built-in procedure accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    /// Exactly the given number of arguments.
+    Exactly(usize),
+    /// The given number of arguments or more.
+    AtLeast(usize),
+    /// Between the given numbers of arguments, inclusive.
+    Between(usize, usize),
+}
+
+impl Arity {
+    /// Whether the given number of arguments is accepted.
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exactly(m) => n == m,
+            Arity::AtLeast(min) => n >= min,
+            Arity::Between(min, max) => (min..=max).contains(&n),
+        }
+    }
+}
+
+/// A built-in procedure that acts on the continuation of the evaluation, so
+/// that the evaluator and the virtual machine apply it themselves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    /// Applies a procedure to the current continuation, as `call/cc`.
+    CallCc(),
+    /// Calls a thunk after a `before` thunk and before an `after` thunk, which
+    /// are also called when a continuation enters or leaves it.
+    DynamicWind(),
+    /// Raises a value to the innermost handler, whose result it returns.
+    RaiseContinuable(),
+    /// Calls a thunk with a procedure installed as the innermost handler.
+    WithExceptionHandler(),
+    /// Expands a form given as data with the macros where it is applied, as
+    /// `macroexpand`, or only once if false, as `macroexpand-1`.
+    Macroexpand(bool),
+    /// Calls a thunk to return what it writes to the current output port.
+    WithOutputToString(),
+}
+
+/// The function of a built-in procedure.
+enum Function<Bool, Numb, Symb, Env, Symbs> {
+    Pointer(
+        fn(
+            Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+            &mut Symbs,
+        )
+            -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ),
+    Port(
+        fn(
+            Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+            &mut Symbs,
+            &OutputPort,
+        )
+            -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ),
+    Closure(Rc<RefCell<Closure<Bool, Numb, Symb, Env, Symbs>>>),
+    Control(Control),
+}
 
 /// A built-in procedure that produces an `EvalResult` from some given values
 /// and has access to some symbols.
 pub struct EvalBuiltIn<Bool, Numb, Symb, Env, Symbs> {
-    built_in: fn(
-        Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        &Symbs,
-    ) -> EvalResult<Bool, Numb, Symb, Env, Self>,
+    name: Option<Rc<str>>,
+    arity: Arity,
+    built_in: Function<Bool, Numb, Symb, Env, Symbs>,
 }
 
 impl<Bool, Numb, Symb, Env, Symbs> EvalBuiltIn<Bool, Numb, Symb, Env, Symbs> {
-    /// Creates a built-in procedure from a function pointer.
+    /// Creates a built-in procedure from a function pointer, which checks the
+    /// number of its arguments itself.
     pub fn new(
         built_in: fn(
             Vec<Val<Bool, Numb, Symb, Env, Self>>,
-            &Symbs,
+            &mut Symbs,
         ) -> EvalResult<Bool, Numb, Symb, Env, Self>,
     ) -> Self {
-        Self { built_in }
+        Self {
+            name: None,
+            arity: Arity::AtLeast(0),
+            built_in: Function::Pointer(built_in),
+        }
+    }
+
+    /// Creates a built-in procedure from a function pointer that also has
+    /// access to the current output port.
+    pub fn with_port(
+        built_in: fn(
+            Vec<Val<Bool, Numb, Symb, Env, Self>>,
+            &mut Symbs,
+            &OutputPort,
+        ) -> EvalResult<Bool, Numb, Symb, Env, Self>,
+    ) -> Self {
+        Self {
+            name: None,
+            arity: Arity::AtLeast(0),
+            built_in: Function::Port(built_in),
+        }
     }
 
-    /// Apply the built-in procedure.
+    /// Creates a named built-in procedure from a closure, which is only called
+    /// with a number of arguments that its arity accepts. The closure can hold
+    /// some state, which is shared by the clones of the procedure, where the
+    /// values it keeps beyond its application are `Rooted` in the `Roots` of
+    /// the evaluation, so that their environments are not collected.
+    pub fn closure(
+        name: &str,
+        arity: Arity,
+        built_in: impl FnMut(
+                Vec<Val<Bool, Numb, Symb, Env, Self>>,
+                &mut Symbs,
+            ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+            + 'static,
+    ) -> Self {
+        Self {
+            name: Some(Rc::from(name)),
+            arity,
+            built_in: Function::Closure(Rc::new(RefCell::new(built_in))),
+        }
+    }
+
+    /// Creates a named built-in procedure that the evaluator applies itself.
+    pub fn with_control(name: &str, arity: Arity, control: Control) -> Self {
+        Self {
+            name: Some(Rc::from(name)),
+            arity,
+            built_in: Function::Control(control),
+        }
+    }
+
+    /// The name of the built-in procedure, if it was given one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The numbers of arguments that the built-in procedure accepts.
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// What the built-in procedure does to the continuation, if anything.
+    pub fn control(&self) -> Option<Control> {
+        match self.built_in {
+            Function::Control(control) => Some(control),
+            _ => None,
+        }
+    }
+
+    /// Whether the built-in procedure is a closure, which may keep its
+    /// arguments beyond its application.
+    pub fn is_closure(&self) -> bool {
+        matches!(self.built_in, Function::Closure(_))
+    }
+
+    /// Apply the built-in procedure, which may write to the given port.
     pub fn apply(
         &self,
         vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        symbols: &Symbs,
+        symbols: &mut Symbs,
+        output: &OutputPort,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
-        (self.built_in)(vs, symbols)
+        if !self.arity.accepts(vs.len()) {
+            return Err(RuntimeError::ArityMismatch());
+        }
+
+        match &self.built_in {
+            Function::Pointer(f) => f(vs, symbols),
+            Function::Port(f) => f(vs, symbols, output),
+            // A built-in procedure cannot call back into the evaluator, so a
+            // closure is never applied while already being applied.
+            Function::Closure(f) => (f.borrow_mut())(vs, symbols),
+            // It can only be applied with the continuation of an evaluation.
+            Function::Control(_) => Err(RuntimeError::NotAProcedure(Val::BuiltIn(self.clone()))),
+        }
     }
 }
 
 impl<Bool, Numb, Symb, Env, Symbs> Clone for EvalBuiltIn<Bool, Numb, Symb, Env, Symbs> {
     fn clone(&self) -> Self {
         Self {
-            built_in: self.built_in,
+            name: self.name.clone(),
+            arity: self.arity,
+            built_in: match &self.built_in {
+                Function::Pointer(f) => Function::Pointer(*f),
+                Function::Port(f) => Function::Port(*f),
+                Function::Closure(f) => Function::Closure(f.clone()),
+                Function::Control(control) => Function::Control(*control),
+            },
         }
     }
 }
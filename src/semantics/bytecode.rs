@@ -0,0 +1,344 @@
+use std::{cell::RefCell, mem, ops::Deref, rc::Rc};
+
+use crate::syntax::{exp::Exp, span::Span};
+
+use super::{
+    compile::Scope,
+    env::{Trace, Tracer},
+    err::RuntimeError,
+    val::Val,
+};
+
+/// An instruction of the bytecode virtual machine, which operates on a stack
+/// of values within the frame of the code being executed.
+///
+/// A local variable is addressed by the number of frames to go up from the
+/// current one and the index of its slot, while a global one is looked up by
+/// its name in the root environment. Jumps are given the index of their target.
+pub enum Instr<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Pushes a value.
+    Const(Val<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Pushes the value of a local variable, which fails if not defined yet.
+    Local(usize, usize, Symb),
+    /// Pushes the value of a global variable.
+    Global(Symb),
+    /// Pushes the value of a global variable as the operator of an application,
+    /// which fails as an unknown expression if not defined. If it is a macro,
+    /// the operands are expanded with it to push the value of the expansion
+    /// instead, jumping past the application.
+    Operator(Symb, Rc<Operands<Bool, Numb, Symb, Env, BuiltIn>>, usize),
+    /// Pops a value to define a variable in a slot of the current frame, and
+    /// pushes void.
+    DefineLocal(usize, Symb),
+    /// Pops a value to define a global variable, and pushes void.
+    DefineGlobal(Symb),
+    /// Pops a value to assign it to a local variable, and pushes void.
+    SetLocal(usize, usize, Symb),
+    /// Pops a value to assign it to a global variable, and pushes void.
+    SetGlobal(Symb),
+    /// Pushes the value of a variable that the code given to `eval` may have
+    /// defined in a frame, or else of a global variable.
+    Named(Symb),
+    /// Pops a value to define a variable in a new slot of the current frame,
+    /// as the code given to `eval` does, and pushes void.
+    DefineNamed(Symb),
+    /// Pops a value to assign it to a variable that the code given to `eval`
+    /// may have defined in a frame, or else to a global variable, and pushes
+    /// void.
+    SetNamed(Symb),
+    /// Pushes a lambda with some code and the current frame.
+    Lambda(Rc<Code<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Fails unless the value on top of the stack is a procedure that accepts
+    /// the given number of arguments, before they are evaluated.
+    Applicable(usize),
+    /// Pops the given number of arguments and the procedure below them, to
+    /// push the result of applying it.
+    Call(usize),
+    /// Pops the given number of arguments and the procedure below them, to
+    /// apply it in place of the current code.
+    TailCall(usize),
+    /// Pops the value that the current code returns.
+    Return(),
+    Pop(),
+    /// Swaps the two values on top of the stack.
+    Swap(),
+    Jump(usize),
+    /// Pops a value to jump if it is false.
+    JumpIfFalse(usize),
+    /// Jumps if the value on top is false, or pops it otherwise.
+    JumpIfFalseOrPop(usize),
+    /// Jumps if the value on top is not false, or pops it otherwise.
+    JumpIfTrueOrPop(usize),
+    /// Pops the value on top to jump if it is false, or keeps it otherwise.
+    KeepOrJump(usize),
+    /// Jumps over the default value of an optional parameter at a slot of the
+    /// current frame if it was given.
+    Default(usize, usize),
+    /// Enters a new frame with the given number of slots, whose first ones
+    /// are bound to the given number of popped values.
+    Enter(usize, usize),
+    /// Goes back to the enclosing frame the given number of times.
+    Leave(usize),
+    /// Pops the given number of values unquoted within a template of
+    /// `quasiquote`, to push the template built with them.
+    Quasiquote(Exp<Bool, Numb, Symb>, usize),
+    /// Pops the key of a `case` if it is one of the given data, or jumps
+    /// keeping it otherwise.
+    Case(Vec<Exp<Bool, Numb, Symb>>, usize),
+    /// Pops a procedure to push a macro that applies it to the operands of the
+    /// forms it expands.
+    Macro(),
+    /// Expands some operands with the macro of a local variable, to push the
+    /// value of the expansion within the scopes of the current frame.
+    Expand(
+        usize,
+        usize,
+        Symb,
+        Rc<Operands<Bool, Numb, Symb, Env, BuiltIn>>,
+    ),
+    /// Pops a value to evaluate it as an expression within the scopes of the
+    /// current frame.
+    Eval(Rc<Vec<Scope<Bool, Numb, Symb, Env, BuiltIn>>>),
+    /// Pops a form given as data to push its expansion by a direct application
+    /// of `macroexpand`, or of `macroexpand-1` if only once, with the macros of
+    /// the given scopes.
+    Macroexpand(bool, Rc<Vec<Scope<Bool, Numb, Symb, Env, BuiltIn>>>),
+    /// Pops the procedure of the clauses of a `guard`, to execute the code of
+    /// its body within the current frame with a handler that abandons it to
+    /// call the clauses.
+    Guard(Rc<Code<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Raises again the error that the clauses of a `guard` did not handle.
+    Reraise(),
+    /// Takes a step of the execution, as the evaluation of a list does for
+    /// the `Evaluator`, which fails once the `Limits` are reached.
+    Step(),
+    /// Fails with an error found when compiling the expression.
+    Fail(RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>),
+}
+
+/// The operands of a form whose operator is only known when evaluating, to
+/// expand them if it is a macro.
+pub struct Operands<Bool, Numb, Symb, Env, BuiltIn> {
+    /// The reversed operands, as they were read.
+    pub(crate) ls: Vec<Exp<Bool, Numb, Symb>>,
+    pub(crate) span: Option<Span>,
+    /// The scopes of the application, where its expansion is evaluated.
+    pub(crate) scopes: Vec<Scope<Bool, Numb, Symb, Env, BuiltIn>>,
+    /// Whether the operator may be a variable that the code given to `eval`
+    /// defined.
+    pub(crate) named: bool,
+}
+
+/// The compiled code of a lambda, or of an expression to evaluate.
+pub struct Code<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) instrs: Vec<Instr<Bool, Numb, Symb, Env, BuiltIn>>,
+    /// The location of the expression that each instruction comes from.
+    pub(crate) spans: Vec<Option<Span>>,
+    /// The scopes of the applications within local macros, by the index of
+    /// their call, in order.
+    pub(crate) scoped: Vec<(usize, Rc<Vec<Scope<Bool, Numb, Symb, Env, BuiltIn>>>)>,
+    /// The number of required parameters, whose slots come first.
+    pub(crate) required: usize,
+    /// The number of optional parameters, whose slots follow the required ones.
+    pub(crate) optional: usize,
+    /// Whether the slot after the optional parameters collects the remaining
+    /// arguments.
+    pub(crate) rest: bool,
+    /// The number of slots of the frame of an invocation.
+    pub(crate) size: usize,
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> Code<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Returns code without instructions, which is never executed.
+    pub(crate) fn empty() -> Self {
+        Self {
+            instrs: Vec::new(),
+            spans: Vec::new(),
+            scoped: Vec::new(),
+            required: 0,
+            optional: 0,
+            rest: false,
+            size: 0,
+        }
+    }
+
+    /// Returns the scopes of the application whose call precedes an index, if
+    /// it is within local macros.
+    pub(crate) fn scopes_before(&self, pc: usize) -> &[Scope<Bool, Numb, Symb, Env, BuiltIn>] {
+        match self.scoped.binary_search_by_key(&pc, |(at, _)| at + 1) {
+            Ok(i) => &self.scoped[i].1,
+            Err(_) => &[],
+        }
+    }
+
+    /// Whether the parameters accept the given number of arguments.
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.required && (self.rest || n <= self.required + self.optional)
+    }
+}
+
+/// The slots of the variables of a scope at runtime, within the frame of the
+/// enclosing scope.
+pub struct Frame<Bool, Numb, Symb, Env, BuiltIn> {
+    /// The values of the variables, which are `None` until defined.
+    pub(crate) slots: RefCell<Vec<Option<Val<Bool, Numb, Symb, Env, BuiltIn>>>>,
+    /// The variables that the code given to `eval` defined, with their slot.
+    pub(crate) names: RefCell<Vec<(Symb, usize)>>,
+    pub(crate) parent: Option<FrameRef<Bool, Numb, Symb, Env, BuiltIn>>,
+}
+
+/// A shared reference to a `Frame`, which empties the slots of the frame when
+/// dropped if only the lambdas of those slots still reference it, as the ones
+/// bound by `letrec`, a named `let` or an internal `define`, so that the cycles
+/// that they form with the frame do not outlive it.
+pub struct FrameRef<Bool, Numb, Symb, Env, BuiltIn>(Rc<Frame<Bool, Numb, Symb, Env, BuiltIn>>);
+
+impl<Bool, Numb, Symb, Env, BuiltIn> FrameRef<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Creates a frame of some slots within another one, whose first slots are
+    /// bound to the given values.
+    pub(crate) fn new(
+        size: usize,
+        vs: Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+        parent: Option<Self>,
+    ) -> Self {
+        let mut slots = Vec::with_capacity(size.max(vs.len()));
+        slots.extend(vs.into_iter().map(Some));
+        slots.resize_with(size, || None);
+        Self(Rc::new(Frame {
+            slots: RefCell::new(slots),
+            names: RefCell::new(Vec::new()),
+            parent,
+        }))
+    }
+
+    /// Creates the frame of an invocation of some code within the frame of its
+    /// lambda, binding its parameters to the arguments, where omitted optional
+    /// parameters are left undefined until their default value.
+    pub(crate) fn invoke(
+        code: &Code<Bool, Numb, Symb, Env, BuiltIn>,
+        parent: Option<Self>,
+        args: Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    ) -> Result<Self, RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>> {
+        if !code.accepts(args.len()) {
+            return Err(RuntimeError::ArityMismatch());
+        }
+
+        let params = code.required + code.optional;
+        let mut slots = Vec::with_capacity(code.size);
+        let mut args = args.into_iter();
+        slots.extend(args.by_ref().take(params).map(Some));
+        slots.resize_with(params, || None);
+        if code.rest {
+            slots.push(Some(Val::list(args.collect())));
+        }
+
+        slots.resize_with(code.size, || None);
+        Ok(Self(Rc::new(Frame {
+            slots: RefCell::new(slots),
+            names: RefCell::new(Vec::new()),
+            parent,
+        })))
+    }
+
+    /// Returns the frame the given number of levels up from this one.
+    pub(crate) fn up(&self, depth: usize) -> Option<&Self> {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame.parent.as_ref()?;
+        }
+
+        Some(frame)
+    }
+}
+
+impl<Bool, Numb, Symb: Eq + Copy, Env, BuiltIn> FrameRef<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Returns the number of levels to go up from this frame and the slot of
+    /// the innermost variable of a name that the code given to `eval` defined.
+    pub(crate) fn named(&self, x: Symb) -> Option<(usize, usize)> {
+        let mut frame = Some(self);
+        let mut depth = 0;
+        while let Some(f) = frame {
+            if let Some((_, i)) = f.names.borrow().iter().find(|(y, _)| *y == x) {
+                return Some((depth, *i));
+            }
+
+            frame = f.parent.as_ref();
+            depth += 1;
+        }
+
+        None
+    }
+
+    /// Defines a variable of a name in a new slot of this frame, unless the
+    /// code given to `eval` already defined it.
+    pub(crate) fn define_named(&self, x: Symb, v: Val<Bool, Numb, Symb, Env, BuiltIn>) -> bool {
+        let mut names = self.names.borrow_mut();
+        if names.iter().any(|(y, _)| *y == x) {
+            return false;
+        }
+
+        let mut slots = self.slots.borrow_mut();
+        names.push((x, slots.len()));
+        slots.push(Some(v));
+        true
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> Clone for FrameRef<Bool, Numb, Symb, Env, BuiltIn> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> Deref for FrameRef<Bool, Numb, Symb, Env, BuiltIn> {
+    type Target = Frame<Bool, Numb, Symb, Env, BuiltIn>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env> for FrameRef<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Traces the values of the slots of the frame and of the frames around it.
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        let mut frame = Some(self);
+        while let Some(f) = frame {
+            if !tracer.visit(&f.0) {
+                return;
+            }
+
+            f.slots.borrow().trace(tracer);
+            frame = f.parent.as_ref();
+        }
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> Drop for FrameRef<Bool, Numb, Symb, Env, BuiltIn> {
+    fn drop(&mut self) {
+        // A frame referenced elsewhere has more references than slots, and its
+        // slots are not touched while they are being modified.
+        let count = Rc::strong_count(&self.0);
+        if count == 1 {
+            return;
+        }
+
+        let Ok(mut slots) = self.0.slots.try_borrow_mut() else {
+            return;
+        };
+
+        if count > slots.len() + 1 {
+            return;
+        }
+
+        let own = slots
+            .iter()
+            .filter(|v| matches!(v, Some(Val::Closure(_, Some(f))) if Rc::ptr_eq(&f.0, &self.0)))
+            .count();
+        if count == own + 1 {
+            // The lambdas are dropped once the slots are no longer borrowed.
+            let vs = mem::take(&mut *slots);
+            drop(slots);
+            drop(vs);
+        }
+    }
+}
@@ -0,0 +1,1405 @@
+use std::{hash::Hash, rc::Rc};
+
+use crate::syntax::{exp::Exp, span::Span, symb::Symbols};
+
+use super::{
+    built_in::{Control, EvalBuiltIn},
+    bytecode::{Code, FrameRef, Instr, Operands},
+    env::Environments,
+    err::RuntimeError,
+    eval::{parse_bindings, quoted_value},
+    macros::{unrenamed, Macro},
+    params::Params,
+    quasi::unquoted,
+    res::EvalResult,
+    syntax_rules::SyntaxRules,
+    val::Val,
+    vm::Vm,
+};
+
+/// What a symbol is bound to within a scope.
+#[derive(Clone)]
+pub(crate) enum Binding<Bool, Numb, Symb, Env, BuiltIn> {
+    /// A variable at a slot of the frame of the scope.
+    Slot(usize),
+    /// A macro, which is expanded when compiling the forms whose operator it is.
+    Macro(Rc<Macro<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// A macro defined with `define-macro` or `defmacro` within a body, at a
+    /// slot of the frame of the scope, whose procedure may refer to the local
+    /// variables, so that the forms whose operator it is are expanded when
+    /// evaluated.
+    Procedure(usize),
+}
+
+/// The symbols bound by a lambda, a local binding form or a body, which are
+/// known when compiling the code within it.
+#[derive(Clone)]
+pub struct Scope<Bool, Numb, Symb, Env, BuiltIn> {
+    bindings: Vec<(Symb, Binding<Bool, Numb, Symb, Env, BuiltIn>)>,
+    /// The number of slots of the frame of the scope.
+    size: usize,
+    /// Whether the code given to `eval` may define variables in the frame of
+    /// the scope, which are then looked up by name.
+    open: bool,
+}
+
+impl<Bool, Numb, Symb: Eq + Copy, Env, BuiltIn> Scope<Bool, Numb, Symb, Env, BuiltIn> {
+    fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+            size: 0,
+            open: false,
+        }
+    }
+
+    /// Returns the slot of a variable defined within the scope, which is the
+    /// one it already has, if any, so that it is defined only once.
+    fn declare(&mut self, x: Symb) -> usize {
+        match self.bindings.iter().rev().find(|(y, _)| *y == x) {
+            Some((_, Binding::Slot(i))) => *i,
+            _ => self.push(x),
+        }
+    }
+
+    /// Returns a new slot for a variable, which shadows any previous binding
+    /// of the same symbol within the scope.
+    fn push(&mut self, x: Symb) -> usize {
+        self.bindings.push((x, Binding::Slot(self.size)));
+        self.size += 1;
+        self.size - 1
+    }
+
+    /// Whether a macro is bound within the scope.
+    fn has_macros(&self) -> bool {
+        self.bindings
+            .iter()
+            .any(|(_, b)| matches!(b, Binding::Macro(_) | Binding::Procedure(_)))
+    }
+
+    fn get(&self, x: Symb) -> Option<&Binding<Bool, Numb, Symb, Env, BuiltIn>> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(y, _)| *y == x)
+            .map(|(_, b)| b)
+    }
+}
+
+/// Whether a variable that is not bound within some scopes may be defined by
+/// the code given to `eval` in one of their frames.
+fn is_open<Bool, Numb, Symb, Env, BuiltIn>(
+    scopes: &[Scope<Bool, Numb, Symb, Env, BuiltIn>],
+) -> bool {
+    scopes.iter().any(|scope| scope.open)
+}
+
+/// The instructions of some code being compiled, along with the location of
+/// the innermost expression being compiled.
+struct Asm<Bool, Numb, Symb, Env, BuiltIn> {
+    instrs: Vec<Instr<Bool, Numb, Symb, Env, BuiltIn>>,
+    spans: Vec<Option<Span>>,
+    span: Option<Span>,
+    scoped: Vec<(usize, Rc<Vec<Scope<Bool, Numb, Symb, Env, BuiltIn>>>)>,
+}
+
+impl<Bool, Numb, Symb: Copy, Env, BuiltIn> Asm<Bool, Numb, Symb, Env, BuiltIn> {
+    fn new() -> Self {
+        Self {
+            instrs: Vec::new(),
+            spans: Vec::new(),
+            span: None,
+            scoped: Vec::new(),
+        }
+    }
+
+    /// Appends an instruction, returning its index.
+    fn emit(&mut self, instr: Instr<Bool, Numb, Symb, Env, BuiltIn>) -> usize {
+        self.instrs.push(instr);
+        self.spans.push(self.span.clone());
+        self.instrs.len() - 1
+    }
+
+    /// Appends a `Return` if in tail position.
+    fn ret(&mut self, tail: bool) {
+        if tail {
+            self.emit(Instr::Return());
+        }
+    }
+
+    /// Sets the target of the jump at an index to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let next = self.instrs.len();
+        match &mut self.instrs[at] {
+            Instr::Jump(to)
+            | Instr::JumpIfFalse(to)
+            | Instr::JumpIfFalseOrPop(to)
+            | Instr::JumpIfTrueOrPop(to)
+            | Instr::KeepOrJump(to)
+            | Instr::Default(_, to)
+            | Instr::Case(_, to)
+            | Instr::Operator(_, _, to) => *to = next,
+            _ => {}
+        }
+    }
+
+    fn finish(
+        self,
+        required: usize,
+        optional: usize,
+        rest: bool,
+        size: usize,
+    ) -> Rc<Code<Bool, Numb, Symb, Env, BuiltIn>> {
+        Rc::new(Code {
+            instrs: self.instrs,
+            spans: self.spans,
+            scoped: self.scoped,
+            required,
+            optional,
+            rest,
+            size,
+        })
+    }
+}
+
+impl<
+        'a,
+        Bool: Into<bool> + From<bool> + PartialEq + Clone,
+        Numb: PartialEq + Clone,
+        Symb: Eq + Hash + Copy,
+        Env: Eq + Copy,
+        Symbs: Symbols<Symb = Symb>,
+        Envs: Environments<
+            Symb,
+            Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+            Env = Env,
+        >,
+    > Vm<'a, Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>, Symbs, Envs>
+{
+    /// Compiles an expression to be executed within the frame of the innermost
+    /// of the given scopes, or at the top level if there is none.
+    pub(crate) fn compile(
+        &mut self,
+        exp: Exp<Bool, Numb, Symb>,
+        mut scopes: Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> Rc<Code<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        let mut asm = Asm::new();
+        self.compile_exp(exp, true, &mut scopes, &mut asm);
+        asm.finish(0, 0, false, 0)
+    }
+
+    /// Compiles an expression that pushes its value, or returns it if in tail
+    /// position.
+    fn compile_exp(
+        &mut self,
+        exp: Exp<Bool, Numb, Symb>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let outer = asm.span.clone();
+        if let Some(span) = exp.span() {
+            asm.span = Some(span);
+        }
+
+        match exp {
+            Exp::Numb(n, _) => {
+                asm.emit(Instr::Const(Val::Numb(n)));
+                asm.ret(tail);
+            }
+            Exp::Bool(b, _) => {
+                asm.emit(Instr::Const(Val::Bool(b)));
+                asm.ret(tail);
+            }
+            Exp::Str(s, _) => {
+                asm.emit(Instr::Const(Val::Str(s)));
+                asm.ret(tail);
+            }
+            Exp::Quot(b, _) => {
+                asm.emit(Instr::Const(Val::quoted(*b)));
+                asm.ret(tail);
+            }
+            // The positions resolved for the `Evaluator` do not apply to the
+            // frames of the compiled code.
+            Exp::Symb(x, _) | Exp::Local(x, _, _, _) => {
+                match self.locate(scopes, x) {
+                    Some((depth, Binding::Slot(i) | Binding::Procedure(i))) => {
+                        asm.emit(Instr::Local(depth, i, x))
+                    }
+                    Some((_, Binding::Macro(m))) => asm.emit(Instr::Const(Val::Macro(m))),
+                    None if is_open(scopes) => asm.emit(Instr::Named(x)),
+                    None => asm.emit(Instr::Global(x)),
+                };
+
+                asm.ret(tail);
+            }
+            Exp::List(ls, span) => {
+                self.compile_list(Rc::unwrap_or_clone(ls), span, tail, scopes, asm)
+            }
+            // A dotted list is only read as data, and cannot be evaluated.
+            Exp::Dotted(_, _, _) => {
+                asm.emit(Instr::Fail(RuntimeError::InvalidArguments()));
+            }
+        }
+
+        asm.span = outer;
+    }
+
+    fn compile_list(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        span: Option<Span>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        asm.emit(Instr::Step());
+        let s = match ls.pop() {
+            Some(Exp::Symb(s, _)) => s,
+            Some(e) => {
+                self.compile_exp(e, false, scopes, asm);
+                return self.compile_call(ls, tail, scopes, asm);
+            }
+            None => {
+                asm.emit(Instr::Fail(RuntimeError::MissingProcedure()));
+                return;
+            }
+        };
+
+        match self.locate(scopes, s) {
+            Some((depth, Binding::Macro(m))) => {
+                // Procedural macros are applied when compiling, so that their
+                // expansion is compiled in place of the form.
+                let r = self.expansion(&m, s, ls, span, scopes.len() - depth);
+                return self.compile_expansion(r, tail, scopes, asm);
+            }
+            Some((depth, Binding::Slot(i))) => {
+                asm.emit(Instr::Local(depth, i, s));
+                return self.compile_call(ls, tail, scopes, asm);
+            }
+            Some((depth, Binding::Procedure(i))) => {
+                let operands = Operands {
+                    ls,
+                    span,
+                    scopes: scopes.clone(),
+                    named: false,
+                };
+
+                asm.emit(Instr::Expand(depth, i, s, Rc::new(operands)));
+                return asm.ret(tail);
+            }
+            None => {}
+        }
+
+        match self.global(s) {
+            Some(Val::Macro(m)) => {
+                let m = m.clone();
+                let r = self.expansion(&m, s, ls, span, 0);
+                return self.compile_expansion(r, tail, scopes, asm);
+            }
+            // A direct application of `macroexpand` also expands with the
+            // local macros, which are only known when compiling.
+            Some(Val::BuiltIn(f)) if ls.len() == 1 => {
+                if let Some(Control::Macroexpand(repeat)) = f.control() {
+                    let instr = Instr::Macroexpand(repeat, Rc::new(scopes.clone()));
+                    return self.compile_operation(ls, instr, tail, scopes, asm);
+                }
+
+                asm.emit(Instr::Global(s));
+                return self.compile_call(ls, tail, scopes, asm);
+            }
+            Some(_) => {
+                asm.emit(if is_open(scopes) {
+                    Instr::Named(s)
+                } else {
+                    Instr::Global(s)
+                });
+                return self.compile_call(ls, tail, scopes, asm);
+            }
+            None => {}
+        }
+
+        let bad_formed = || Instr::Fail(RuntimeError::BadFormedExpression(s));
+        match self.keyword(scopes, s) {
+            Some("define") => match (ls.pop(), ls.pop(), ls.pop()) {
+                (Some(Exp::Symb(x, _)), Some(e), None) => {
+                    match scopes.last_mut() {
+                        // The variables that the code given to `eval` defines
+                        // get a new slot when evaluated.
+                        Some(scope) if scope.open && scope.get(x).is_none() => {
+                            self.compile_exp(e, false, scopes, asm);
+                            asm.emit(Instr::DefineNamed(x));
+                        }
+                        Some(scope) => {
+                            let i = scope.declare(x);
+                            self.compile_exp(e, false, scopes, asm);
+                            asm.emit(Instr::DefineLocal(i, x));
+                        }
+                        None => {
+                            self.compile_exp(e, false, scopes, asm);
+                            asm.emit(Instr::DefineGlobal(x));
+                        }
+                    }
+
+                    asm.ret(tail);
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("set!") => match (ls.pop(), ls.pop(), ls.pop()) {
+                (Some(Exp::Symb(x, _)), Some(e), None) => {
+                    self.compile_exp(e, false, scopes, asm);
+                    match self.locate(scopes, x) {
+                        Some((depth, Binding::Slot(i))) => asm.emit(Instr::SetLocal(depth, i, x)),
+                        Some((_, Binding::Macro(_) | Binding::Procedure(_))) => {
+                            asm.emit(bad_formed())
+                        }
+                        None if is_open(scopes) => asm.emit(Instr::SetNamed(x)),
+                        None => asm.emit(Instr::SetGlobal(x)),
+                    };
+
+                    asm.ret(tail);
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("quote") => match (ls.pop(), ls.pop()) {
+                (Some(e), None) => {
+                    asm.emit(Instr::Const(Val::quoted(e)));
+                    asm.ret(tail);
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("quasiquote") => match (ls.pop(), ls.pop()) {
+                (Some(e), None) => {
+                    let mut es = Vec::new();
+                    unquoted(&e, 1, self.symbols, &mut es);
+                    let es: Vec<_> = es.into_iter().cloned().collect();
+                    let n = es.len();
+                    for u in es {
+                        self.compile_exp(u, false, scopes, asm);
+                    }
+
+                    asm.emit(Instr::Quasiquote(e, n));
+                    asm.ret(tail);
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("if") => match (ls.pop(), ls.pop(), ls.pop(), ls.pop()) {
+                (Some(c), Some(e1), Some(e2), None) => {
+                    self.compile_exp(c, false, scopes, asm);
+                    let at_else = asm.emit(Instr::JumpIfFalse(0));
+                    self.compile_exp(e1, tail, scopes, asm);
+                    let at_end = (!tail).then(|| asm.emit(Instr::Jump(0)));
+                    asm.patch(at_else);
+                    self.compile_exp(e2, tail, scopes, asm);
+                    if let Some(at_end) = at_end {
+                        asm.patch(at_end);
+                    }
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("begin") => self.compile_sequence(ls, tail, scopes, asm),
+            Some("eval") => match (ls.pop(), ls.pop()) {
+                (Some(e), None) => {
+                    if let Some(scope) = scopes.last_mut() {
+                        scope.open = true;
+                    }
+
+                    self.compile_exp(e, false, scopes, asm);
+                    asm.emit(Instr::Eval(Rc::new(scopes.clone())));
+                    asm.ret(tail);
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("lambda") => match (ls.pop(), ls.pop(), ls.pop()) {
+                (Some(ps), Some(b), None) => match Params::parse(ps, self.symbols) {
+                    Some(ps) => {
+                        let code = self.compile_lambda(ps, vec![b], scopes);
+                        asm.emit(Instr::Lambda(code));
+                        asm.ret(tail);
+                    }
+                    None => {
+                        asm.emit(bad_formed());
+                    }
+                },
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("let") => match ls.pop() {
+                Some(Exp::Symb(name, _)) => self.compile_named_let(name, ls, tail, s, scopes, asm),
+                Some(bs) => self.compile_let(bs, ls, tail, s, scopes, asm),
+                None => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("let*") => match ls.pop() {
+                Some(bs) => self.compile_let_star(bs, ls, tail, s, scopes, asm),
+                None => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("letrec" | "letrec*") => match ls.pop() {
+                Some(bs) => self.compile_letrec(bs, ls, tail, s, scopes, asm),
+                None => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("cond") => self.compile_cond(ls, tail, false, s, scopes, asm),
+            Some("case") => match ls.pop() {
+                Some(key) => {
+                    self.compile_exp(key, false, scopes, asm);
+                    self.compile_case(ls, tail, s, scopes, asm);
+                }
+                None => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("when") => self.compile_when(ls, tail, true, s, scopes, asm),
+            Some("unless") => self.compile_when(ls, tail, false, s, scopes, asm),
+            Some("guard") => self.compile_guard(ls, tail, s, scopes, asm),
+            Some("define-syntax") => match (ls.pop(), ls.pop(), ls.pop()) {
+                (Some(Exp::Symb(x, _)), Some(e), None) => {
+                    match SyntaxRules::parse(e, self.symbols) {
+                        Some(rules) => {
+                            let m = Rc::new(Macro::Rules(rules, self.environment.root()));
+                            self.define_syntax(x, m, tail, scopes, asm);
+                        }
+                        None => {
+                            asm.emit(bad_formed());
+                        }
+                    }
+                }
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("define-macro") => match ls.pop() {
+                Some(Exp::List(header, span)) => match header.split_last() {
+                    Some((&Exp::Symb(x, _), params)) => {
+                        let params = Exp::List(Rc::new(params.to_vec()), span);
+                        match Params::parse(params, self.symbols) {
+                            Some(ps) if !ls.is_empty() => {
+                                self.define_macro(x, ps, ls, tail, scopes, asm)
+                            }
+                            _ => {
+                                asm.emit(bad_formed());
+                            }
+                        }
+                    }
+                    _ => {
+                        asm.emit(bad_formed());
+                    }
+                },
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("defmacro") => match (ls.pop(), ls.pop()) {
+                (Some(Exp::Symb(x, _)), Some(ps)) => match Params::parse(ps, self.symbols) {
+                    Some(ps) if !ls.is_empty() => self.define_macro(x, ps, ls, tail, scopes, asm),
+                    _ => {
+                        asm.emit(bad_formed());
+                    }
+                },
+                _ => {
+                    asm.emit(bad_formed());
+                }
+            },
+            Some("let-syntax" | "letrec-syntax") => {
+                self.compile_let_syntax(ls, tail, s, scopes, asm)
+            }
+            Some("and") => self.compile_and(ls, tail, true, scopes, asm),
+            Some("or") => self.compile_and(ls, tail, false, scopes, asm),
+            Some(_) => {
+                // The operator may be defined by the time the code is executed,
+                // even as a macro, whose expansion is then returned if in tail
+                // position.
+                let operands = Operands {
+                    ls: ls.clone(),
+                    span,
+                    scopes: scopes.clone(),
+                    named: is_open(scopes),
+                };
+
+                let at = asm.emit(Instr::Operator(s, Rc::new(operands), 0));
+                self.compile_call(ls, tail, scopes, asm);
+                asm.patch(at);
+                asm.ret(tail);
+            }
+            None => {
+                asm.emit(Instr::Fail(RuntimeError::UnknownSymbol(s)));
+            }
+        }
+    }
+
+    /// Compiles the reversed arguments of an application, whose procedure is
+    /// already pushed, and the call.
+    fn compile_call(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let n = ls.len();
+        // The operator is checked before evaluating the arguments, unless they
+        // can neither fail nor have effects.
+        let constant = |e: &Exp<Bool, Numb, Symb>| {
+            matches!(
+                e,
+                Exp::Numb(..) | Exp::Bool(..) | Exp::Str(..) | Exp::Quot(..)
+            )
+        };
+        if !ls.iter().all(constant) {
+            asm.emit(Instr::Applicable(n));
+        }
+
+        while let Some(e) = ls.pop() {
+            self.compile_exp(e, false, scopes, asm);
+        }
+
+        let at = asm.emit(if tail {
+            Instr::TailCall(n)
+        } else {
+            Instr::Call(n)
+        });
+        // An application of `macroexpand` also expands with the local macros
+        // when its operator is not known when compiling.
+        if scopes.iter().any(Scope::has_macros) {
+            asm.scoped.push((at, Rc::new(scopes.clone())));
+        }
+    }
+
+    /// Compiles the expansion of a form whose operator is a macro in place of
+    /// the form, which is evaluated as with `eval`.
+    fn compile_expansion(
+        &mut self,
+        r: EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        match r {
+            Ok(Val::Quot(e)) => self.compile_exp(e, tail, scopes, asm),
+            Ok(v @ Val::Pair(_, _)) => match quoted_value(&v, self.symbols) {
+                Some(v) => {
+                    asm.emit(Instr::Const(v));
+                    asm.ret(tail);
+                }
+                None => match v.into_exp() {
+                    Some(e) => self.compile_exp(e, tail, scopes, asm),
+                    None => {
+                        asm.emit(Instr::Fail(RuntimeError::InvalidArguments()));
+                    }
+                },
+            },
+            Ok(v) => {
+                asm.emit(Instr::Const(v));
+                asm.ret(tail);
+            }
+            Err(err) => {
+                asm.emit(Instr::Fail(err));
+            }
+        }
+    }
+
+    /// Compiles the operands of a built-in procedure such as `macroexpand` that
+    /// is applied directly, which are evaluated as arguments, and its instruction.
+    fn compile_operation(
+        &mut self,
+        ls: Vec<Exp<Bool, Numb, Symb>>,
+        instr: Instr<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        for e in ls.into_iter().rev() {
+            self.compile_exp(e, false, scopes, asm);
+        }
+
+        asm.emit(instr);
+        asm.ret(tail);
+    }
+
+    /// Compiles a reversed sequence of expressions, whose value is the one of
+    /// the last expression.
+    fn compile_sequence(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        if ls.is_empty() {
+            asm.emit(Instr::Const(Val::Void()));
+            asm.ret(tail);
+        }
+
+        while let Some(e) = ls.pop() {
+            if ls.is_empty() {
+                self.compile_exp(e, tail, scopes, asm);
+            } else {
+                self.compile_exp(e, false, scopes, asm);
+                asm.emit(Instr::Pop());
+            }
+        }
+    }
+
+    /// Compiles a reversed body within the innermost scope, where the
+    /// variables it defines are declared beforehand, so that the expressions
+    /// before their definition refer to them, as are the ones that the code it
+    /// gives to `eval` may define.
+    fn compile_body(
+        &mut self,
+        ls: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        self.declare_defines(&ls, scopes);
+        self.compile_sequence(ls, tail, scopes, asm);
+    }
+
+    fn declare_defines(
+        &self,
+        ls: &[Exp<Bool, Numb, Symb>],
+        scopes: &mut [Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+    ) {
+        for e in ls.iter().rev() {
+            let Exp::List(form, _) = e else {
+                continue;
+            };
+
+            match form.split_last() {
+                Some((&Exp::Symb(s, _), [_, Exp::Symb(x, _)]))
+                    if self.is_keyword(s, "define", scopes) =>
+                {
+                    if let Some(scope) = scopes.last_mut() {
+                        scope.declare(*x);
+                    }
+                }
+                Some((&Exp::Symb(s, _), forms)) if self.is_keyword(s, "begin", scopes) => {
+                    self.declare_defines(forms, scopes)
+                }
+                Some((&Exp::Symb(s, _), [body @ .., _])) if self.is_keyword(s, "guard", scopes) => {
+                    self.declare_defines(body, scopes)
+                }
+                Some((&Exp::Symb(s, _), _)) if self.is_keyword(s, "eval", scopes) => {
+                    if let Some(scope) = scopes.last_mut() {
+                        scope.open = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Compiles a lambda with some parameters and a reversed body within the
+    /// given scopes.
+    fn compile_lambda(
+        &mut self,
+        ps: Params<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> Rc<Code<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        self.compile_procedure(ps, scopes, |vm, scopes, asm| {
+            vm.compile_body(body, true, scopes, asm)
+        })
+    }
+
+    /// Compiles a procedure with some parameters, whose body is compiled in
+    /// tail position by the given function within the scope of the parameters.
+    fn compile_procedure(
+        &mut self,
+        ps: Params<Bool, Numb, Symb>,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        body: impl FnOnce(
+            &mut Self,
+            &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+            &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        ),
+    ) -> Rc<Code<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        let mut scope = Scope::new();
+        for &x in &ps.required {
+            scope.push(x);
+        }
+
+        for &(x, _) in &ps.optional {
+            scope.push(x);
+        }
+
+        if let Some(x) = ps.rest {
+            scope.push(x);
+        }
+
+        scopes.push(scope);
+        let mut asm = Asm::new();
+        // The default values of the omitted optional parameters are evaluated
+        // in order, so that they can refer to the previous parameters.
+        let (required, optional) = (ps.required.len(), ps.optional.len());
+        for (i, (x, default)) in ps.optional.into_iter().enumerate() {
+            let at = asm.emit(Instr::Default(required + i, 0));
+            match default {
+                Some(e) => self.compile_exp(e, false, scopes, &mut asm),
+                None => {
+                    asm.emit(Instr::Const(Val::Void()));
+                }
+            }
+
+            asm.emit(Instr::DefineLocal(required + i, x));
+            asm.emit(Instr::Pop());
+            asm.patch(at);
+        }
+
+        body(self, scopes, &mut asm);
+        let size = scopes.pop().map_or(0, |scope| scope.size);
+        asm.finish(required, optional, ps.rest.is_some(), size)
+    }
+
+    fn compile_let(
+        &mut self,
+        bs: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let mut bs = match parse_bindings(bs) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        let (n, mut scope) = (bs.len(), Scope::new());
+        while let Some((x, e)) = bs.pop() {
+            self.compile_exp(e, false, scopes, asm);
+            scope.push(x);
+        }
+
+        let at = asm.emit(Instr::Enter(n, n));
+        scopes.push(scope);
+        self.compile_body(body, tail, scopes, asm);
+        self.leave(at, n, tail, scopes, asm);
+    }
+
+    /// Closes the scope of a frame entered at an index, which is left unless
+    /// the body was in tail position.
+    fn leave(
+        &mut self,
+        at: usize,
+        n: usize,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let size = scopes.pop().map_or(0, |scope| scope.size);
+        asm.instrs[at] = Instr::Enter(size, n);
+        if !tail {
+            asm.emit(Instr::Leave(1));
+        }
+    }
+
+    fn compile_named_let(
+        &mut self,
+        name: Symb,
+        mut body: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let bs = match body.pop().and_then(parse_bindings) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        let (mut xs, es): (Vec<Symb>, Vec<Exp<Bool, Numb, Symb>>) = bs.into_iter().unzip();
+        xs.reverse();
+        let ps = Params {
+            required: xs,
+            optional: Vec::new(),
+            rest: None,
+        };
+
+        // The lambda of the loop is bound in its own frame, and invoked with
+        // the initial values evaluated where the name is not visible.
+        let at = asm.emit(Instr::Enter(1, 0));
+        let mut scope = Scope::new();
+        scope.push(name);
+        scopes.push(scope);
+        let code = self.compile_lambda(ps, body, scopes);
+        asm.emit(Instr::Lambda(code));
+        asm.emit(Instr::DefineLocal(0, name));
+        asm.emit(Instr::Pop());
+        asm.emit(Instr::Local(0, 0, name));
+        if let Some(scope) = scopes.last_mut() {
+            scope.bindings.clear();
+        }
+
+        self.compile_call(es, tail, scopes, asm);
+        self.leave(at, 0, tail, scopes, asm);
+    }
+
+    fn compile_let_star(
+        &mut self,
+        bs: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let mut bs = match parse_bindings(bs) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        if bs.is_empty() {
+            let at = asm.emit(Instr::Enter(0, 0));
+            scopes.push(Scope::new());
+            self.compile_body(body, tail, scopes, asm);
+            return self.leave(at, 0, tail, scopes, asm);
+        }
+
+        // Each binding is in a new nested frame, so that it can shadow the
+        // previous ones and be bound again when resuming a continuation.
+        let mut entered = Vec::with_capacity(bs.len());
+        while let Some((x, e)) = bs.pop() {
+            self.compile_exp(e, false, scopes, asm);
+            entered.push(asm.emit(Instr::Enter(1, 1)));
+            let mut scope = Scope::new();
+            scope.push(x);
+            scopes.push(scope);
+        }
+
+        self.compile_body(body, tail, scopes, asm);
+        for &at in entered.iter().rev() {
+            let size = scopes.pop().map_or(0, |scope| scope.size);
+            asm.instrs[at] = Instr::Enter(size, 1);
+        }
+
+        if !tail {
+            asm.emit(Instr::Leave(entered.len()));
+        }
+    }
+
+    fn compile_letrec(
+        &mut self,
+        bs: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let mut bs = match parse_bindings(bs) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        // The bindings are evaluated in order within the new frame, so that
+        // their lambdas can refer to each other.
+        let at = asm.emit(Instr::Enter(bs.len(), 0));
+        let mut scope = Scope::new();
+        let slots: Vec<_> = bs.iter().rev().map(|&(x, _)| scope.push(x)).collect();
+        scopes.push(scope);
+        for i in slots {
+            if let Some((x, e)) = bs.pop() {
+                self.compile_exp(e, false, scopes, asm);
+                asm.emit(Instr::DefineLocal(i, x));
+                asm.emit(Instr::Pop());
+            }
+        }
+
+        self.compile_body(body, tail, scopes, asm);
+        self.leave(at, 0, tail, scopes, asm);
+    }
+
+    fn compile_let_syntax(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let bs = match ls.pop().and_then(parse_bindings) {
+            Some(bs) if !ls.is_empty() => bs,
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        let mut scope = Scope::new();
+        for (x, e) in bs {
+            match SyntaxRules::parse(e, self.symbols) {
+                Some(rules) => scope.bindings.push((
+                    x,
+                    Binding::Macro(Rc::new(Macro::Rules(rules, self.environment.root()))),
+                )),
+                None => {
+                    asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    return;
+                }
+            }
+        }
+
+        let at = asm.emit(Instr::Enter(0, 0));
+        scopes.push(scope);
+        self.compile_body(ls, tail, scopes, asm);
+        self.leave(at, 0, tail, scopes, asm);
+    }
+
+    /// Compiles the reversed clauses of a `cond`, or of a `guard` that raises
+    /// the error again if no clause applies.
+    fn compile_cond(
+        &mut self,
+        mut clauses: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        guard: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let mut ends = Vec::new();
+        let exhaustive = loop {
+            let mut body = match clauses.pop() {
+                None => break false,
+                Some(Exp::List(body, _)) => Rc::unwrap_or_clone(body),
+                Some(_) => {
+                    asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    break true;
+                }
+            };
+
+            match body.pop() {
+                Some(Exp::Symb(x, _)) if self.is_keyword(x, "else", scopes) => {
+                    if clauses.is_empty() && !body.is_empty() {
+                        self.compile_sequence(body, tail, scopes, asm);
+                    } else {
+                        asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    }
+
+                    break true;
+                }
+                Some(test) => {
+                    self.compile_exp(test, false, scopes, asm);
+                    match &body[..] {
+                        [] => ends.push(asm.emit(Instr::JumpIfTrueOrPop(0))),
+                        [f, Exp::Symb(x, _)] if self.is_keyword(*x, "=>", scopes) => {
+                            let at = asm.emit(Instr::KeepOrJump(0));
+                            self.compile_exp(f.clone(), false, scopes, asm);
+                            asm.emit(Instr::Swap());
+                            asm.emit(if tail {
+                                Instr::TailCall(1)
+                            } else {
+                                Instr::Call(1)
+                            });
+
+                            if !tail {
+                                ends.push(asm.emit(Instr::Jump(0)));
+                            }
+
+                            asm.patch(at);
+                        }
+                        _ => {
+                            let at = asm.emit(Instr::JumpIfFalse(0));
+                            self.compile_sequence(body, tail, scopes, asm);
+                            if !tail {
+                                ends.push(asm.emit(Instr::Jump(0)));
+                            }
+
+                            asm.patch(at);
+                        }
+                    }
+                }
+                None => {
+                    asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    break true;
+                }
+            }
+        };
+
+        if !exhaustive {
+            if guard {
+                asm.emit(Instr::Reraise());
+            } else {
+                asm.emit(Instr::Const(Val::Void()));
+                asm.ret(tail);
+            }
+        }
+
+        for &at in &ends {
+            asm.patch(at);
+        }
+
+        // The values of the tests of clauses without body are returned here.
+        if !ends.is_empty() {
+            asm.ret(tail);
+        }
+    }
+
+    /// Compiles the reversed clauses of a `case`, whose key is already pushed.
+    fn compile_case(
+        &mut self,
+        mut clauses: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let mut ends = Vec::new();
+        let exhaustive = loop {
+            let mut body = match clauses.pop() {
+                None => break false,
+                Some(Exp::List(body, _)) if body.len() > 1 => Rc::unwrap_or_clone(body),
+                Some(_) => {
+                    asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    break true;
+                }
+            };
+
+            match body.pop() {
+                Some(Exp::Symb(x, _)) if self.is_keyword(x, "else", scopes) => {
+                    if clauses.is_empty() {
+                        asm.emit(Instr::Pop());
+                        self.compile_sequence(body, tail, scopes, asm);
+                    } else {
+                        asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    }
+
+                    break true;
+                }
+                Some(Exp::List(data, _)) => {
+                    let at = asm.emit(Instr::Case(data.to_vec(), 0));
+                    self.compile_sequence(body, tail, scopes, asm);
+                    if !tail {
+                        ends.push(asm.emit(Instr::Jump(0)));
+                    }
+
+                    asm.patch(at);
+                }
+                _ => {
+                    asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    break true;
+                }
+            }
+        };
+
+        if !exhaustive {
+            asm.emit(Instr::Pop());
+            asm.emit(Instr::Const(Val::Void()));
+            asm.ret(tail);
+        }
+
+        for at in ends {
+            asm.patch(at);
+        }
+    }
+
+    /// Compiles `when`, or `unless` if the body is not expected to be
+    /// evaluated when the test is true.
+    fn compile_when(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        expected: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let c = match ls.pop() {
+            Some(c) if !ls.is_empty() => c,
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        self.compile_exp(c, false, scopes, asm);
+        let at = asm.emit(Instr::JumpIfFalse(0));
+        let at_end = if expected {
+            self.compile_sequence(ls, tail, scopes, asm);
+            let at_end = (!tail).then(|| asm.emit(Instr::Jump(0)));
+            asm.patch(at);
+            asm.emit(Instr::Const(Val::Void()));
+            asm.ret(tail);
+            at_end
+        } else {
+            asm.emit(Instr::Const(Val::Void()));
+            asm.ret(tail);
+            let at_end = (!tail).then(|| asm.emit(Instr::Jump(0)));
+            asm.patch(at);
+            self.compile_sequence(ls, tail, scopes, asm);
+            at_end
+        };
+
+        if let Some(at_end) = at_end {
+            asm.patch(at_end);
+        }
+    }
+
+    /// Compiles `and`, or `or` if the value that ends the evaluation is not
+    /// expected to be false.
+    fn compile_and(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        and: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        if ls.is_empty() {
+            asm.emit(Instr::Const(Val::Bool(Bool::from(and))));
+            asm.ret(tail);
+            return;
+        }
+
+        let mut ends = Vec::with_capacity(ls.len());
+        while let Some(e) = ls.pop() {
+            if ls.is_empty() {
+                self.compile_exp(e, tail, scopes, asm);
+            } else {
+                self.compile_exp(e, false, scopes, asm);
+                ends.push(asm.emit(if and {
+                    Instr::JumpIfFalseOrPop(0)
+                } else {
+                    Instr::JumpIfTrueOrPop(0)
+                }));
+            }
+        }
+
+        for &at in &ends {
+            asm.patch(at);
+        }
+
+        if !ends.is_empty() {
+            asm.ret(tail);
+        }
+    }
+
+    /// Compiles a `guard`, whose reversed body and clauses are compiled as
+    /// procedures, the latter with the raised value bound to a variable.
+    fn compile_guard(
+        &mut self,
+        mut body: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        s: Symb,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let (x, clauses) = match body.pop() {
+            Some(Exp::List(clauses, _)) if !body.is_empty() => match clauses.split_last() {
+                Some((&Exp::Symb(x, _), clauses)) => (x, clauses.to_vec()),
+                _ => {
+                    asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                    return;
+                }
+            },
+            _ => {
+                asm.emit(Instr::Fail(RuntimeError::BadFormedExpression(s)));
+                return;
+            }
+        };
+
+        // The body is executed within the current frame, where it defines its
+        // variables.
+        self.declare_defines(&body, scopes);
+        let mut code = Asm::new();
+        code.span = asm.span.clone();
+        self.compile_sequence(body, true, scopes, &mut code);
+        let body = code.finish(0, 0, false, 0);
+        let handler = Params {
+            required: vec![x],
+            optional: Vec::new(),
+            rest: None,
+        };
+
+        let code = self.compile_procedure(handler, scopes, |vm, scopes, asm| {
+            vm.compile_cond(clauses, true, true, s, scopes, asm)
+        });
+
+        asm.emit(Instr::Lambda(code));
+        asm.emit(Instr::Guard(body));
+        asm.ret(tail);
+    }
+
+    /// Compiles the definition of a macro, which is bound within the innermost
+    /// scope when compiling, or defined as a global variable when executed.
+    fn define_syntax(
+        &mut self,
+        x: Symb,
+        m: Rc<Macro<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        tail: bool,
+        scopes: &mut [Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        match scopes.last_mut() {
+            Some(scope) => {
+                scope.bindings.push((x, Binding::Macro(m)));
+                asm.emit(Instr::Const(Val::Void()));
+            }
+            None => {
+                asm.emit(Instr::Const(Val::Macro(m)));
+                asm.emit(Instr::DefineGlobal(x));
+            }
+        }
+
+        asm.ret(tail);
+    }
+
+    /// Compiles the definition of a macro whose expansion is the value of a
+    /// lambda with the given parameters and reversed body, applied to the
+    /// operands as data. Within a body, the lambda is a local variable, so
+    /// that it can refer to the other ones.
+    fn define_macro(
+        &mut self,
+        x: Symb,
+        ps: Params<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        tail: bool,
+        scopes: &mut Vec<Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        asm: &mut Asm<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) {
+        let code = self.compile_lambda(ps, body, scopes);
+        match scopes.last_mut() {
+            Some(scope) => {
+                let i = scope.size;
+                scope.size += 1;
+                scope.bindings.push((x, Binding::Procedure(i)));
+                asm.emit(Instr::Lambda(code));
+                asm.emit(Instr::Macro());
+                asm.emit(Instr::DefineLocal(i, x));
+                asm.ret(tail);
+            }
+            None => {
+                let m = Rc::new(Macro::Procedure(Val::Closure(code, None)));
+                self.define_syntax(x, m, tail, scopes, asm);
+            }
+        }
+    }
+
+    /// Returns the number of scopes to go up and the binding of a symbol, or
+    /// of the symbol that a macro renamed into it within the scopes where the
+    /// macro is defined if not bound, unless global.
+    fn locate(
+        &self,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+        x: Symb,
+    ) -> Option<(
+        usize,
+        Binding<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    )> {
+        for (depth, scope) in scopes.iter().rev().enumerate() {
+            if let Some(b) = scope.get(x) {
+                return Some((depth, b.clone()));
+            }
+        }
+
+        if self.environment.get(self.environment.root(), &x).is_some() {
+            return None;
+        }
+
+        let (x, defined) = self.origin(scopes.len(), x)?;
+        let (depth, b) = self.locate(&scopes[..defined], x)?;
+        Some((depth + scopes.len() - defined, b))
+    }
+
+    /// Returns the symbol that a macro renamed into the given one, along with
+    /// the number of the given scopes where the macro is defined.
+    fn origin(&self, visible: usize, x: Symb) -> Option<(Symb, usize)> {
+        match self.renames.origin(x) {
+            Some((x, defined)) if defined <= visible => Some((x, defined)),
+            _ => Some((unrenamed(x, self.symbols)?, visible)),
+        }
+    }
+
+    /// Returns the macro that a symbol is bound to within some scopes, whose
+    /// innermost frame is given, if any, along with the number of scopes where
+    /// it is defined.
+    pub(super) fn macro_of(
+        &self,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+        frame: Option<&FrameRef<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        x: Symb,
+    ) -> Option<(
+        Rc<Macro<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        usize,
+    )> {
+        match self.locate(scopes, x) {
+            Some((depth, Binding::Macro(m))) => Some((m, scopes.len() - depth)),
+            Some((depth, Binding::Procedure(i))) => {
+                let frame = frame?.up(depth)?;
+                let v = frame.slots.borrow().get(i).cloned().flatten();
+                match v {
+                    Some(Val::Macro(m)) => Some((m, 0)),
+                    _ => None,
+                }
+            }
+            Some((_, Binding::Slot(_))) => None,
+            None => match self.global(x) {
+                Some(Val::Macro(m)) => Some((m.clone(), 0)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the name of a symbol that is not bound, or of the symbol that
+    /// a macro renamed into it if not bound where the macro is defined.
+    fn keyword(
+        &self,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+        x: Symb,
+    ) -> Option<&str> {
+        match self.symbols.resolve(x)? {
+            name if !name.starts_with("#{") => Some(name),
+            _ => {
+                let (x, defined) = self.origin(scopes.len(), x)?;
+                let scopes = &scopes[..defined];
+                match self.locate(scopes, x) {
+                    None if self.environment.get(self.environment.root(), &x).is_none() => {
+                        self.keyword(scopes, x)
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Whether a symbol is the given special form or auxiliary keyword, which
+    /// it is not when bound.
+    fn is_keyword(
+        &self,
+        x: Symb,
+        keyword: &str,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+    ) -> bool {
+        self.locate(scopes, x).is_none()
+            && self.environment.get(self.environment.root(), &x).is_none()
+            && self.keyword(scopes, x) == Some(keyword)
+    }
+}
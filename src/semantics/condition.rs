@@ -0,0 +1,82 @@
+use std::rc::Rc;
+
+use crate::syntax::{exp::Exp, symb::Symbols};
+
+use super::{err::RuntimeError, val::Val};
+
+/// A condition object describing an error, as raised by `error` or by the
+/// evaluation itself.
+#[derive(Clone)]
+pub struct Condition<Bool, Numb, Symb, Env, BuiltIn> {
+    /// The kind of the error, as `error` or `undefined-variable`.
+    pub kind: Symb,
+    /// A description of the error.
+    pub message: Rc<str>,
+    /// A list of values related to the error.
+    pub irritants: Val<Bool, Numb, Symb, Env, BuiltIn>,
+}
+
+/// Returns the value raised by an error, which is a condition object for
+/// the errors of the evaluation itself.
+pub(crate) fn raised<Bool: Clone, Numb: Clone, Symbs: Symbols, Env: Clone, BuiltIn: Clone>(
+    err: &RuntimeError<Symbs::Symb, Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>>,
+    symbols: &mut Symbs,
+) -> Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>
+where
+    Symbs::Symb: Copy,
+{
+    let symbol = |x| Val::Quot(Exp::Symb(x, None));
+    let (kind, message, irritants) = match err {
+        RuntimeError::AlreadyDefined(x) => ("already-defined", "Already defined", vec![symbol(*x)]),
+        RuntimeError::ArityMismatch() => ("arity-mismatch", "Arity mismatch", Vec::new()),
+        RuntimeError::BadFormedExpression(s) => (
+            "bad-formed-expression",
+            "Bad formed expression",
+            vec![symbol(*s)],
+        ),
+        RuntimeError::Cancelled() => ("cancelled", "Cancelled", Vec::new()),
+        RuntimeError::CouldNotPushEnvironment() => (
+            "could-not-push-environment",
+            "Could not push environment",
+            Vec::new(),
+        ),
+        RuntimeError::DeadlineExceeded() => ("deadline-exceeded", "Deadline exceeded", Vec::new()),
+        RuntimeError::DepthLimitExceeded(_) => {
+            ("depth-limit-exceeded", "Depth limit exceeded", Vec::new())
+        }
+        RuntimeError::ElementTypeMismatch(_, _, expected, v) => (
+            "type-mismatch",
+            "Type mismatch",
+            vec![Val::Str((*expected).into()), v.clone()],
+        ),
+        RuntimeError::FuelExhausted() => ("fuel-exhausted", "Fuel exhausted", Vec::new()),
+        RuntimeError::InvalidArguments() => ("invalid-arguments", "Invalid arguments", Vec::new()),
+        RuntimeError::Located(_, err) => return raised(err, symbols),
+        RuntimeError::MissingProcedure() => ("missing-procedure", "Missing procedure", Vec::new()),
+        RuntimeError::NotAProcedure(v) => ("not-a-procedure", "Not a procedure", vec![v.clone()]),
+        RuntimeError::OutputFailed(e) => (
+            "output-failed",
+            "Output failed",
+            vec![Val::Str(e.as_str().into())],
+        ),
+        RuntimeError::Raised(v) => return v.clone(),
+        RuntimeError::TypeMismatch(_, expected, v) => (
+            "type-mismatch",
+            "Type mismatch",
+            vec![Val::Str((*expected).into()), v.clone()],
+        ),
+        RuntimeError::UndefinedVariable(x) => {
+            ("undefined-variable", "Undefined variable", vec![symbol(*x)])
+        }
+        RuntimeError::UnknownExpression(s) => {
+            ("unknown-expression", "Unknown expression", vec![symbol(*s)])
+        }
+        RuntimeError::UnknownSymbol(s) => ("unknown-symbol", "Unknown symbol", vec![symbol(*s)]),
+    };
+
+    Val::Cond(Rc::new(Condition {
+        kind: symbols.get_or_store(kind),
+        message: message.into(),
+        irritants: Val::list(irritants),
+    }))
+}
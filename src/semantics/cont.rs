@@ -0,0 +1,347 @@
+use std::rc::Rc;
+
+use crate::syntax::{exp::Exp, span::Span};
+
+use super::{
+    env::{Trace, Tracer},
+    err::RuntimeError,
+    port::OutputPort,
+    val::Val,
+};
+
+/// The rest of a computation, as captured by `call/cc`, which can be resumed
+/// any number of times.
+#[derive(Clone)]
+pub struct Continuation<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) stack: Vec<Suspended<Bool, Numb, Symb, Env, BuiltIn>>,
+    pub(crate) winders: Winders<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) handlers: Handlers<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) output: OutputPort,
+}
+
+/// A frame of the continuation stack, along with the state of the evaluation
+/// that it suspends.
+#[derive(Clone)]
+pub(crate) struct Suspended<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) frame: Frame<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) at: Env,
+    pub(crate) span: Option<Span>,
+    pub(crate) owned: Vec<Env>,
+    pub(crate) escapes: usize,
+}
+
+/// The `before` and `after` thunks of the `dynamic-wind` calls whose thunk
+/// is being evaluated, from the innermost one.
+pub type Winders<Bool, Numb, Symb, Env, BuiltIn> =
+    Option<Rc<Winder<Bool, Numb, Symb, Env, BuiltIn>>>;
+
+/// The thunks of a `dynamic-wind` call, within those of the enclosing ones.
+pub struct Winder<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) before: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) after: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) parent: Winders<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) depth: usize,
+}
+
+/// The exception handlers installed by `with-exception-handler` and `guard`,
+/// from the innermost one.
+pub type Handlers<Bool, Numb, Symb, Env, BuiltIn> =
+    Option<Rc<Handler<Bool, Numb, Symb, Env, BuiltIn>>>;
+
+/// An exception handler, within the enclosing ones.
+pub struct Handler<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) catch: Catch<Bool, Numb, Symb, Env, BuiltIn>,
+    pub(crate) parent: Handlers<Bool, Numb, Symb, Env, BuiltIn>,
+}
+
+/// The way an exception handler deals with a raised value.
+pub(crate) enum Catch<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Calls a procedure with the raised value.
+    Procedure(Val<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Abandons the continuation up to the frame of a `guard` at an index of
+    /// the stack, with the winders of its body.
+    Guard(usize, Winders<Bool, Numb, Symb, Env, BuiltIn>),
+}
+
+/// A frame of the continuation of an evaluation, which awaits the value of a
+/// subexpression to resume the evaluation of its enclosing expression.
+///
+/// Reversed lists of expressions or values are popped from their end. The
+/// variants that are larger than the common ones are boxed, as a frame is kept
+/// for each level of non tail recursion.
+#[derive(Clone)]
+pub enum Frame<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Applies the value as a procedure to the reversed arguments.
+    Operator(Vec<Exp<Bool, Numb, Symb>>),
+    /// Collects the value as the next argument of a procedure, which comes
+    /// first in the values, before the remaining reversed arguments.
+    Argument(
+        Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+        Vec<Exp<Bool, Numb, Symb>>,
+    ),
+    /// Collects the value of the next expression unquoted within the template
+    /// of a `quasiquote`, before the remaining reversed ones.
+    Quasiquote(
+        Box<Exp<Bool, Numb, Symb>>,
+        Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+        Vec<Exp<Bool, Numb, Symb>>,
+    ),
+    /// Binds the value to an optional parameter.
+    Default(Box<DefaultFrame<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Defines a variable with the value.
+    Define(Symb),
+    /// Assigns the value to a variable.
+    Set(Symb),
+    /// Continues with the last of the two reversed expressions if the value is
+    /// true, or the first one otherwise.
+    If(Vec<Exp<Bool, Numb, Symb>>),
+    /// Evaluates the value as an expression.
+    Eval(),
+    /// Restores an output port after the thunk of `with-output-to-string`,
+    /// to return what the thunk wrote instead of its value.
+    Output(OutputPort),
+    /// Takes the value as the expansion of a macro by `macroexpand-1`, or by
+    /// `macroexpand` to expand it again if it is still the use of a macro.
+    Expand(bool),
+    /// Discards the value and continues with the reversed body in an environment.
+    Body(Vec<Exp<Bool, Numb, Symb>>, Env),
+    /// Continues with the reversed operands of `and` if the value is true.
+    And(Vec<Exp<Bool, Numb, Symb>>),
+    /// Continues with the reversed operands of `or` if the value is false.
+    Or(Vec<Exp<Bool, Numb, Symb>>),
+    /// Takes the value as the test of the last of the reversed `cond` clauses,
+    /// to be evaluated in an environment along with the error to raise again
+    /// if no clause of a `guard` applies.
+    Cond {
+        clauses: Vec<Exp<Bool, Numb, Symb>>,
+        s: Symb,
+        err: Option<Box<RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>>,
+        at: Env,
+    },
+    /// Applies the value as a procedure to the value of a `cond` test.
+    CondArrow(Box<Val<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Takes the value as the key of a `case` with the reversed clauses.
+    Case(Vec<Exp<Bool, Numb, Symb>>, Symb),
+    /// Continues with the reversed body if the value is the expected one.
+    When(Vec<Exp<Bool, Numb, Symb>>, bool),
+    /// Binds the value to a variable of a `let`.
+    Let(Box<LetFrame<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Binds the value to a variable of a `let*` in a new nested environment,
+    /// before the remaining reversed bindings.
+    LetStar {
+        x: Symb,
+        bs: Vec<(Symb, Exp<Bool, Numb, Symb>)>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    },
+    /// Binds the value to a variable of a `letrec` in its environment, before
+    /// the remaining reversed bindings.
+    Letrec {
+        x: Symb,
+        bs: Vec<(Symb, Exp<Bool, Numb, Symb>)>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    },
+    /// Discards the value of the `before` thunk of a `dynamic-wind`, to then
+    /// call its thunk and finally its `after` thunk.
+    WindBefore(
+        Box<(
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+        )>,
+    ),
+    /// Takes the value of the thunk of a `dynamic-wind`, to then call its `after` thunk.
+    WindAfter(Box<Val<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Discards the value of a thunk called when resuming a continuation, to
+    /// then call the remaining reversed thunks, each with its winders, and
+    /// finally return a value with the winders of the continuation.
+    Wind(
+        Vec<(
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        )>,
+        Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        Box<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    ),
+    /// Takes the value of the thunk of `with-exception-handler`, to then
+    /// uninstall its handler.
+    WithHandler(),
+    /// Discards the value of a handler called for a non continuable error, to
+    /// then raise the error again to the enclosing handlers.
+    Reraise(Box<RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>),
+    /// Takes the value of a handler called by `raise-continuable`, to then
+    /// install again the handlers.
+    Continue(Handlers<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Takes the value of the body of a `guard` with a variable and reversed
+    /// clauses, to then uninstall its handler.
+    Guard(Symb, Vec<Exp<Bool, Numb, Symb>>, Symb),
+    /// Binds a variable to the value raised within the body of a `guard`, to
+    /// then evaluate its reversed clauses or raise the error again.
+    Catch {
+        x: Symb,
+        clauses: Vec<Exp<Bool, Numb, Symb>>,
+        s: Symb,
+        err: Box<RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>,
+    },
+    /// Discards the value to return the given one.
+    Value(Box<Val<Bool, Numb, Symb, Env, BuiltIn>>),
+}
+
+/// The binding of an optional parameter to a default value, before the remaining
+/// reversed optional parameters, rest parameter and reversed arguments, to finally
+/// evaluate the body of a lambda in its invocation environment.
+#[derive(Clone)]
+pub struct DefaultFrame<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) x: Symb,
+    pub(crate) optional: Vec<(Symb, Option<Exp<Bool, Numb, Symb>>)>,
+    pub(crate) rest: Option<Symb>,
+    pub(crate) args: Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    pub(crate) body: Exp<Bool, Numb, Symb>,
+    pub(crate) at: Env,
+}
+
+/// The binding of a variable of a `let`, after the values of the previous
+/// bindings and before the remaining reversed bindings.
+#[derive(Clone)]
+pub struct LetFrame<Bool, Numb, Symb, Env, BuiltIn> {
+    pub(crate) x: Symb,
+    pub(crate) vs: Vec<(Symb, Val<Bool, Numb, Symb, Env, BuiltIn>)>,
+    pub(crate) bs: Vec<(Symb, Exp<Bool, Numb, Symb>)>,
+    pub(crate) body: Vec<Exp<Bool, Numb, Symb>>,
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env>
+    for Continuation<Bool, Numb, Symb, Env, BuiltIn>
+{
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        self.stack.trace(tracer);
+        self.winders.trace(tracer);
+        self.handlers.trace(tracer);
+    }
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env>
+    for Suspended<Bool, Numb, Symb, Env, BuiltIn>
+{
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        tracer.env(self.at);
+        for &at in &self.owned {
+            tracer.env(at);
+        }
+
+        self.frame.trace(tracer);
+    }
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env> for Winder<Bool, Numb, Symb, Env, BuiltIn> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        self.before.trace(tracer);
+        self.after.trace(tracer);
+        self.parent.trace(tracer);
+    }
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env> for Handler<Bool, Numb, Symb, Env, BuiltIn> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        match &self.catch {
+            Catch::Procedure(f) => f.trace(tracer),
+            Catch::Guard(_, winders) => winders.trace(tracer),
+        }
+
+        self.parent.trace(tracer);
+    }
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env> for Frame<Bool, Numb, Symb, Env, BuiltIn> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        match self {
+            Frame::Argument(vs, _) | Frame::Quasiquote(_, vs, _) => vs.trace(tracer),
+            Frame::Default(d) => {
+                d.args.trace(tracer);
+                tracer.env(d.at);
+            }
+            Frame::Body(_, at) | Frame::LetStar { at, .. } | Frame::Letrec { at, .. } => {
+                tracer.env(*at)
+            }
+            Frame::Cond { err, at, .. } => {
+                err.trace(tracer);
+                tracer.env(*at);
+            }
+            Frame::Let(l) => {
+                for (_, v) in &l.vs {
+                    v.trace(tracer);
+                }
+            }
+            Frame::WindBefore(thunks) => {
+                let (before, thunk, after) = &**thunks;
+                before.trace(tracer);
+                thunk.trace(tracer);
+                after.trace(tracer);
+            }
+            Frame::Wind(thunks, winders, v) => {
+                for (thunk, winders) in thunks {
+                    thunk.trace(tracer);
+                    winders.trace(tracer);
+                }
+
+                winders.trace(tracer);
+                v.trace(tracer);
+            }
+            Frame::Reraise(err) | Frame::Catch { err, .. } => err.trace(tracer),
+            Frame::Continue(handlers) => handlers.trace(tracer),
+            Frame::CondArrow(v) | Frame::WindAfter(v) | Frame::Value(v) => v.trace(tracer),
+            Frame::Operator(_)
+            | Frame::Define(_)
+            | Frame::Set(_)
+            | Frame::If(_)
+            | Frame::Eval()
+            | Frame::Output(_)
+            | Frame::Expand(_)
+            | Frame::And(_)
+            | Frame::Or(_)
+            | Frame::Case(_, _)
+            | Frame::When(_, _)
+            | Frame::WithHandler()
+            | Frame::Guard(_, _, _) => (),
+        }
+    }
+}
+
+/// Returns the thunks to call, reversed and each with its winders, to go from
+/// some winders to others, which are the `after` thunks of the winders that
+/// are left and the `before` thunks of the winders that are entered.
+pub(crate) fn transitions<Bool, Numb, Symb, Env, BuiltIn: Clone>(
+    from: &Winders<Bool, Numb, Symb, Env, BuiltIn>,
+    to: &Winders<Bool, Numb, Symb, Env, BuiltIn>,
+) -> Vec<(
+    Val<Bool, Numb, Symb, Env, BuiltIn>,
+    Winders<Bool, Numb, Symb, Env, BuiltIn>,
+)>
+where
+    Val<Bool, Numb, Symb, Env, BuiltIn>: Clone,
+{
+    let depth = |w: &Winders<_, _, _, _, _>| w.as_ref().map_or(0, |w| w.depth);
+    let (mut left, mut entered) = (from.clone(), to.clone());
+    let (mut afters, mut befores) = (Vec::new(), Vec::new());
+    loop {
+        match (left, entered) {
+            (Some(l), Some(e)) if Rc::ptr_eq(&l, &e) => break,
+            (Some(l), e) if l.depth >= depth(&e) => {
+                afters.push((l.after.clone(), l.parent.clone()));
+                (left, entered) = (l.parent.clone(), e);
+            }
+            (l, Some(e)) => {
+                befores.push((e.before.clone(), e.parent.clone()));
+                (left, entered) = (l, e.parent.clone());
+            }
+            _ => break,
+        }
+    }
+
+    // The winders are left from the innermost one and entered from the outermost one.
+    let mut thunks = afters;
+    thunks.extend(befores.into_iter().rev());
+    thunks.reverse();
+    thunks
+}
@@ -0,0 +1,365 @@
+use std::rc::Rc;
+
+use super::{
+    built_in::{Arity, EvalBuiltIn},
+    err::RuntimeError,
+    val::Val,
+};
+
+/// A Rust type that can be obtained from some values, as the argument of a
+/// registered function.
+pub trait FromVal<Bool, Numb, Symb, Env, BuiltIn>: Sized {
+    /// The kind of value expected, to describe a mismatch.
+    const EXPECTED: &'static str;
+
+    /// Converts a value, or returns it back if it has another type.
+    fn from_val(
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, Val<Bool, Numb, Symb, Env, BuiltIn>>;
+
+    /// Converts the argument at a position, from zero, of a registered
+    /// function, or describes the mismatch.
+    fn from_arg(
+        i: usize,
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>> {
+        Self::from_val(v).map_err(|v| RuntimeError::TypeMismatch(i, Self::EXPECTED, v))
+    }
+}
+
+/// A Rust type that can be converted into a value, as the result of a
+/// registered function.
+pub trait IntoVal<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Converts into a value.
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn>;
+
+    /// Converts into the result of a registered function, which only fails
+    /// when it holds an error.
+    fn into_result(
+        self,
+    ) -> Result<
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    >
+    where
+        Self: Sized,
+    {
+        Ok(self.into_val())
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> FromVal<Bool, Numb, Symb, Env, BuiltIn>
+    for Val<Bool, Numb, Symb, Env, BuiltIn>
+{
+    const EXPECTED: &'static str = "value";
+
+    fn from_val(v: Self) -> Result<Self, Self> {
+        Ok(v)
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> IntoVal<Bool, Numb, Symb, Env, BuiltIn>
+    for Val<Bool, Numb, Symb, Env, BuiltIn>
+{
+    fn into_val(self) -> Self {
+        self
+    }
+}
+
+/// Implements the conversions of a numeric type, for values whose numbers
+/// have this type.
+macro_rules! impl_numb {
+    ($($t:ty),*) => {$(
+        impl<Bool, Symb, Env, BuiltIn> FromVal<Bool, $t, Symb, Env, BuiltIn> for $t {
+            const EXPECTED: &'static str = "number";
+
+            fn from_val(
+                v: Val<Bool, $t, Symb, Env, BuiltIn>,
+            ) -> Result<Self, Val<Bool, $t, Symb, Env, BuiltIn>> {
+                match v {
+                    Val::Numb(n) => Ok(n),
+                    v => Err(v),
+                }
+            }
+        }
+
+        impl<Bool, Symb, Env, BuiltIn> IntoVal<Bool, $t, Symb, Env, BuiltIn> for $t {
+            fn into_val(self) -> Val<Bool, $t, Symb, Env, BuiltIn> {
+                Val::Numb(self)
+            }
+        }
+    )*};
+}
+
+impl_numb!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+impl<Numb, Symb, Env, BuiltIn> FromVal<bool, Numb, Symb, Env, BuiltIn> for bool {
+    const EXPECTED: &'static str = "boolean";
+
+    fn from_val(
+        v: Val<bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, Val<bool, Numb, Symb, Env, BuiltIn>> {
+        match v {
+            Val::Bool(b) => Ok(b),
+            v => Err(v),
+        }
+    }
+}
+
+impl<Numb, Symb, Env, BuiltIn> IntoVal<bool, Numb, Symb, Env, BuiltIn> for bool {
+    fn into_val(self) -> Val<bool, Numb, Symb, Env, BuiltIn> {
+        Val::Bool(self)
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> FromVal<Bool, Numb, Symb, Env, BuiltIn> for Rc<str> {
+    const EXPECTED: &'static str = "string";
+
+    fn from_val(
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, Val<Bool, Numb, Symb, Env, BuiltIn>> {
+        match v {
+            Val::Str(s) => Ok(s),
+            v => Err(v),
+        }
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> IntoVal<Bool, Numb, Symb, Env, BuiltIn> for Rc<str> {
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        Val::Str(self)
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> FromVal<Bool, Numb, Symb, Env, BuiltIn> for String {
+    const EXPECTED: &'static str = "string";
+
+    fn from_val(
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, Val<Bool, Numb, Symb, Env, BuiltIn>> {
+        match v {
+            Val::Str(s) => Ok(s.to_string()),
+            v => Err(v),
+        }
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> IntoVal<Bool, Numb, Symb, Env, BuiltIn> for String {
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        Val::Str(self.into())
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> IntoVal<Bool, Numb, Symb, Env, BuiltIn> for &str {
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        Val::Str(self.into())
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn> IntoVal<Bool, Numb, Symb, Env, BuiltIn> for () {
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        Val::Void()
+    }
+}
+
+impl<
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+        Env: Clone,
+        BuiltIn: Clone,
+        T: FromVal<Bool, Numb, Symb, Env, BuiltIn>,
+    > FromVal<Bool, Numb, Symb, Env, BuiltIn> for Vec<T>
+{
+    const EXPECTED: &'static str = "list";
+
+    /// Converts a list whose values all have the type of its elements.
+    fn from_val(
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, Val<Bool, Numb, Symb, Env, BuiltIn>> {
+        match v.clone().into_vec() {
+            Ok(vs) => vs
+                .into_iter()
+                .map(T::from_val)
+                .collect::<Result<_, _>>()
+                .map_err(|_| v),
+            Err(_) => Err(v),
+        }
+    }
+
+    /// Converts a list as an argument, where a mismatch names the position
+    /// and the expected type of the first element that has another one.
+    fn from_arg(
+        i: usize,
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Result<Self, RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>> {
+        match v.clone().into_vec() {
+            Ok(vs) => vs
+                .into_iter()
+                .enumerate()
+                .map(|(j, v)| {
+                    T::from_val(v)
+                        .map_err(|v| RuntimeError::ElementTypeMismatch(i, j, T::EXPECTED, v))
+                })
+                .collect(),
+            Err(_) => Err(RuntimeError::TypeMismatch(i, Self::EXPECTED, v)),
+        }
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn, T: IntoVal<Bool, Numb, Symb, Env, BuiltIn>>
+    IntoVal<Bool, Numb, Symb, Env, BuiltIn> for Vec<T>
+{
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        Val::list(self.into_iter().map(T::into_val).collect())
+    }
+
+    /// Converts a list that fails with the error of its first element that
+    /// holds one.
+    fn into_result(
+        self,
+    ) -> Result<
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    > {
+        self.into_iter()
+            .map(T::into_result)
+            .collect::<Result<_, _>>()
+            .map(Val::list)
+    }
+}
+
+impl<Bool, Numb, Symb, Env, BuiltIn, T: IntoVal<Bool, Numb, Symb, Env, BuiltIn>>
+    IntoVal<Bool, Numb, Symb, Env, BuiltIn>
+    for Result<T, RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>
+{
+    /// Converts the value of a success, while a failure, which only makes
+    /// sense as the result of a registered function, is void.
+    fn into_val(self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        match self {
+            Ok(t) => t.into_val(),
+            Err(_) => Val::Void(),
+        }
+    }
+
+    /// Converts the value of a success, or fails with the error of a failure.
+    fn into_result(
+        self,
+    ) -> Result<
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    > {
+        self.and_then(T::into_result)
+    }
+}
+
+/// A Rust function that can be registered as a built-in procedure, whose
+/// arguments are converted from values with `FromVal` and whose result is
+/// converted into a value with `IntoVal`, where `Args` is the tuple of the
+/// types of its arguments.
+pub trait IntoBuiltIn<Args, Bool, Numb, Symb, Env, Symbs> {
+    /// Wraps the function into a named built-in procedure that checks the
+    /// number and the types of its arguments.
+    fn into_built_in(self, name: &str) -> EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>;
+}
+
+impl<
+        Bool,
+        Numb,
+        Symb,
+        Env,
+        Symbs,
+        F: FnMut() -> R + 'static,
+        R: IntoVal<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    > IntoBuiltIn<(), Bool, Numb, Symb, Env, Symbs> for F
+{
+    fn into_built_in(mut self, name: &str) -> EvalBuiltIn<Bool, Numb, Symb, Env, Symbs> {
+        EvalBuiltIn::closure(name, Arity::Exactly(0), move |_, _| self().into_result())
+    }
+}
+
+/// Implements `IntoBuiltIn` for the functions with the given types of
+/// arguments, along with the variables that hold them.
+macro_rules! impl_into_built_in {
+    ($n:expr; $($t:ident $x:ident),*) => {
+        impl<
+                Bool,
+                Numb,
+                Symb,
+                Env,
+                Symbs,
+                F: FnMut($($t),*) -> R + 'static,
+                R: IntoVal<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+                $($t: FromVal<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,)*
+            > IntoBuiltIn<($($t,)*), Bool, Numb, Symb, Env, Symbs> for F
+        {
+            fn into_built_in(mut self, name: &str) -> EvalBuiltIn<Bool, Numb, Symb, Env, Symbs> {
+                EvalBuiltIn::closure(name, Arity::Exactly($n), move |vs, _| {
+                    let mut vs = vs.into_iter().enumerate();
+                    $(
+                        let $x = match vs.next() {
+                            Some((i, v)) => $t::from_arg(i, v)?,
+                            None => return Err(RuntimeError::ArityMismatch()),
+                        };
+                    )*
+
+                    self($($x),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_built_in!(1; A a);
+impl_into_built_in!(2; A a, B b);
+impl_into_built_in!(3; A a, B b, C c);
+impl_into_built_in!(4; A a, B b, C c, D d);
+impl_into_built_in!(5; A a, B b, C c, D d, E e);
+impl_into_built_in!(6; A a, B b, C c, D d, E e, G g);
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::Value, semantics::err::RuntimeError, Interpreter};
+
+    /// Evaluates a script and prints its result or its error.
+    fn eval(interpreter: &mut Interpreter, script: &str) -> String {
+        match interpreter.eval_str(script) {
+            Ok(v) => interpreter.print(v),
+            Err(err) => interpreter.print(err),
+        }
+    }
+
+    #[test]
+    fn list_mismatches_name_the_element() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("sum", |xs: Vec<i64>| xs.into_iter().sum::<i64>());
+        assert_eq!(eval(&mut interpreter, "(sum (list 1 2 3))"), "6");
+        assert_eq!(
+            eval(&mut interpreter, "(sum (list 1 \"2\" 3))"),
+            "<input>:1:1: Type mismatch: expected a number as element 2 of argument 1, got \"2\""
+        );
+        assert_eq!(
+            eval(&mut interpreter, "(sum 1)"),
+            "<input>:1:1: Type mismatch: expected a list as argument 1, got 1"
+        );
+    }
+
+    #[test]
+    fn results_fail_with_their_error() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("checked", |x: i64| -> Result<i64, RuntimeError<_, Value>> {
+            match x {
+                0.. => Ok(x),
+                _ => Err(RuntimeError::Raised(Value::Str("negative".into()))),
+            }
+        });
+        assert_eq!(eval(&mut interpreter, "(checked 1)"), "1");
+        assert_eq!(
+            eval(
+                &mut interpreter,
+                "(guard (e (#t (list 'caught e))) (checked (- 0 1)))"
+            ),
+            "'(caught \"negative\")"
+        );
+    }
+}
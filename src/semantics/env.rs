@@ -1,3 +1,9 @@
+use std::{cell::RefCell, collections::HashSet, ops::Deref, rc::Rc};
+
+/// The default minimum number of environments pushed between collections of
+/// the unreachable ones.
+pub const DEFAULT_GC_THRESHOLD: usize = 10_000;
+
 /// Types that can be used to manage environments, which are variable-value associations with inheritance.
 pub trait Environments<Var, Val> {
     /// A reference to an environment.
@@ -15,9 +21,242 @@ pub trait Environments<Var, Val> {
     /// Find the `Val` associated to a `Var` for a given environment.
     fn get(&self, at: Self::Env, x: &Var) -> Option<&Val>;
 
+    /// Find the `Val` associated to a `Var` at a position of the environment that is
+    /// the given number of levels up from a given one, as resolved before evaluation.
+    ///
+    /// Returns `None` when not found there, so that the `Var` is looked up with `get`,
+    /// which is always the case if the environments do not keep the order of their `Var`s.
+    fn get_at(&self, _at: Self::Env, _x: &Var, _depth: usize, _index: usize) -> Option<&Val> {
+        None
+    }
+
     /// Associates a `Val` to a `Var` for a given environment, only the `Var` was
     /// not previously associated in the same environment.
     ///
     /// Returns the ownership of the given paremeters on failure.
     fn define(&mut self, at: Self::Env, x: Var, v: Val) -> Result<(), (Var, Val)>;
+
+    /// Associates a `Val` to a `Var` in the nearest environment where the `Var` is
+    /// already associated, starting from the given one.
+    ///
+    /// Returns the ownership of the given `Val` on failure.
+    fn set(&mut self, at: Self::Env, x: &Var, v: Val) -> Result<(), Val>;
+
+    /// Whether an environment is the given ancestor or one of its descendants.
+    fn within(&self, at: Self::Env, ancestor: Self::Env) -> bool;
+
+    /// Whether enough environments have been pushed since the last collection
+    /// to collect the unreachable ones again.
+    fn should_collect(&self) -> bool {
+        false
+    }
+
+    /// Drops the environments that are not reachable from the root one or from
+    /// those found by a `Tracer`, where the parent of a reachable environment
+    /// and the environments referenced by the values of its variables are
+    /// reachable too.
+    fn collect(&mut self, _tracer: Tracer<Self::Env>) {}
+
+    /// Returns the statistics of the collections so far.
+    fn stats(&self) -> GcStats {
+        GcStats::default()
+    }
+}
+
+/// Statistics of the collections of unreachable environments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// The number of collections so far.
+    pub collections: usize,
+    /// The number of environments dropped by the collections so far.
+    pub collected: usize,
+    /// The number of environments that are not dropped, including the root one.
+    pub live: usize,
+    /// The number of environments pushed since the last collection.
+    pub allocated: usize,
+}
+
+/// Values that may reference environments, which must not be collected while
+/// the values are reachable.
+pub trait Trace<Env> {
+    /// Adds the environments referenced by the value to a `Tracer`.
+    fn trace(&self, tracer: &mut Tracer<Env>);
+}
+
+/// The environments found while tracing some values, along with the shared
+/// values already traced, which are not traced again.
+pub struct Tracer<Env> {
+    envs: Vec<Env>,
+    visited: HashSet<*const ()>,
+}
+
+impl<Env> Tracer<Env> {
+    /// Creates a `Tracer` that starts with the given environments.
+    pub fn new(envs: Vec<Env>) -> Self {
+        Self {
+            envs,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Adds a reachable environment.
+    pub fn env(&mut self, at: Env) {
+        self.envs.push(at);
+    }
+
+    /// Takes the next environment to mark, if any.
+    pub fn pop(&mut self) -> Option<Env> {
+        self.envs.pop()
+    }
+
+    /// Whether a shared value is traced for the first time, in which case it
+    /// is no longer the first one.
+    pub fn visit<T>(&mut self, rc: &Rc<T>) -> bool {
+        self.visited.insert(Rc::as_ptr(rc) as *const ())
+    }
+}
+
+impl<Env, T: Trace<Env>> Trace<Env> for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        for t in self {
+            t.trace(tracer);
+        }
+    }
+}
+
+impl<Env, T: Trace<Env>> Trace<Env> for Box<T> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        (**self).trace(tracer);
+    }
+}
+
+impl<Env, T: Trace<Env>> Trace<Env> for Rc<T> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        if tracer.visit(self) {
+            (**self).trace(tracer);
+        }
+    }
+}
+
+impl<Env, T: Trace<Env>> Trace<Env> for Option<T> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        if let Some(t) = self {
+            t.trace(tracer);
+        }
+    }
+}
+
+/// Values held by the application, which the collections trace as long as
+/// their `Rooted` handles are not dropped, as the environments of lambdas
+/// returned by a script or kept by a built-in procedure.
+pub struct Roots<T>(Rc<RefCell<RootSlots<T>>>);
+
+struct RootSlots<T> {
+    values: Vec<Option<T>>,
+    free: Vec<usize>,
+}
+
+impl<T: Clone> Roots<T> {
+    /// Creates an empty set of roots.
+    pub fn new() -> Self {
+        Self(Rc::new(RefCell::new(RootSlots {
+            values: Vec::new(),
+            free: Vec::new(),
+        })))
+    }
+
+    /// Roots a value until the returned handle and its clones are dropped.
+    pub fn root(&self, v: T) -> Rooted<T> {
+        let mut slots = self.0.borrow_mut();
+        let index = match slots.free.pop() {
+            Some(i) => {
+                slots.values[i] = Some(v.clone());
+                i
+            }
+            None => {
+                slots.values.push(Some(v.clone()));
+                slots.values.len() - 1
+            }
+        };
+
+        Rooted {
+            roots: self.clone(),
+            index,
+            value: v,
+        }
+    }
+
+    /// The number of values rooted.
+    pub fn len(&self) -> usize {
+        let slots = self.0.borrow();
+        slots.values.len() - slots.free.len()
+    }
+
+    /// Whether no value is rooted.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> Default for Roots<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for Roots<T> {
+    /// Shares the same roots.
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Env, T: Trace<Env>> Trace<Env> for Roots<T> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        if tracer.visit(&self.0) {
+            self.0.borrow().values.trace(tracer);
+        }
+    }
+}
+
+/// A value rooted in some `Roots`, which dereferences to the value.
+pub struct Rooted<T: Clone> {
+    roots: Roots<T>,
+    index: usize,
+    value: T,
+}
+
+impl<T: Clone> Rooted<T> {
+    /// Returns a copy of the value, which is no longer rooted by it.
+    pub fn get(&self) -> T {
+        self.value.clone()
+    }
+}
+
+impl<T: Clone> Deref for Rooted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> Clone for Rooted<T> {
+    /// Roots the value again, so that it stays rooted until both are dropped.
+    fn clone(&self) -> Self {
+        self.roots.root(self.value.clone())
+    }
+}
+
+impl<T: Clone> Drop for Rooted<T> {
+    fn drop(&mut self) {
+        // The value is dropped once the slots are no longer borrowed, as it
+        // may hold handles of the same roots.
+        let v = {
+            let mut slots = self.roots.0.borrow_mut();
+            slots.free.push(self.index);
+            slots.values[self.index].take()
+        };
+        drop(v);
+    }
 }
@@ -0,0 +1,327 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    hash::Hash,
+};
+
+use super::env::{Environments, GcStats, Trace, Tracer, DEFAULT_GC_THRESHOLD};
+
+/// An implementation of `Environments` where each environment other than the
+/// root one is a vector of variables in the order they are defined, so that
+/// a variable can be found at the position resolved before evaluation, while
+/// the root environment is a `HashMap`, as global variables are looked up by
+/// name since they can be defined at any time.
+pub struct EnvironmentFrames<Var, Val> {
+    globals: HashMap<Var, Val>,
+    /// The frames of the environments, where the root one is the first and
+    /// has no variables, and whose slots are reused once dropped.
+    frames: Vec<Frame<Var, Val>>,
+    free: Vec<usize>,
+    /// The minimum number of environments pushed between collections.
+    threshold: usize,
+    stats: GcStats,
+}
+
+/// A reference to an environment of `EnvironmentFrames`, which is no longer
+/// valid once the environment is dropped, even if its slot is reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FrameId {
+    index: usize,
+    generation: usize,
+}
+
+struct Frame<Var, Val> {
+    vars: Vec<(Var, Val)>,
+    /// The number of times the slot of the frame has been dropped.
+    generation: usize,
+    live: bool,
+    parent: Option<usize>,
+    /// The children of the frame are linked as siblings, so that one can be
+    /// unlinked without looking for it.
+    first_child: Option<usize>,
+    previous: Option<usize>,
+    next: Option<usize>,
+}
+
+impl<Var, Val> Frame<Var, Val> {
+    fn new(capacity: usize, generation: usize, parent: Option<usize>) -> Self {
+        Self {
+            vars: Vec::with_capacity(capacity),
+            generation,
+            live: true,
+            parent,
+            first_child: None,
+            previous: None,
+            next: None,
+        }
+    }
+}
+
+impl<Var, Val> EnvironmentFrames<Var, Val> {
+    /// Creates an empty `EnvironmentFrames` with the given initial capacity for the root environment.
+    pub fn empty(capacity: usize) -> Self {
+        Self {
+            globals: HashMap::with_capacity(capacity),
+            frames: vec![Frame::new(0, 0, None)],
+            free: Vec::new(),
+            threshold: DEFAULT_GC_THRESHOLD,
+            stats: GcStats {
+                live: 1,
+                ..GcStats::default()
+            },
+        }
+    }
+
+    /// Sets the minimum number of environments pushed between collections of
+    /// the unreachable ones, which is `DEFAULT_GC_THRESHOLD` by default.
+    pub fn with_gc_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Returns the index of the frame of an environment, unless dropped.
+    fn index(&self, at: FrameId) -> Option<usize> {
+        self.frames
+            .get(at.index)
+            .filter(|f| f.live && f.generation == at.generation)
+            .map(|_| at.index)
+    }
+}
+
+impl<Var: Eq + Hash + Clone, Val: Trace<FrameId>> Environments<Var, Val>
+    for EnvironmentFrames<Var, Val>
+{
+    type Env = FrameId;
+
+    fn root(&self) -> Self::Env {
+        FrameId {
+            index: 0,
+            generation: 0,
+        }
+    }
+
+    /// Drops the given environment and its children, unless it is the root one.
+    fn drop(&mut self, at: Self::Env) {
+        let i = match self.index(at) {
+            Some(i) if i != 0 => i,
+            _ => return,
+        };
+
+        let (parent, previous, next) = {
+            let f = &self.frames[i];
+            (f.parent, f.previous, f.next)
+        };
+
+        match previous {
+            Some(p) => self.frames[p].next = next,
+            None => {
+                if let Some(p) = parent {
+                    self.frames[p].first_child = next;
+                }
+            }
+        }
+
+        if let Some(n) = next {
+            self.frames[n].previous = previous;
+        }
+
+        let mut dropped = vec![i];
+        while let Some(i) = dropped.pop() {
+            let f = &mut self.frames[i];
+            let mut child = f.first_child.take();
+            f.vars = Vec::new();
+            f.live = false;
+            f.generation += 1;
+            self.free.push(i);
+            self.stats.live -= 1;
+            while let Some(c) = child {
+                dropped.push(c);
+                child = self.frames[c].next;
+            }
+        }
+    }
+
+    fn push(&mut self, at: Self::Env, capacity: usize) -> Option<Self::Env> {
+        let parent = self.index(at)?;
+        let first = self.frames[parent].first_child;
+        let index = match self.free.pop() {
+            Some(index) => {
+                let generation = self.frames[index].generation;
+                self.frames[index] = Frame::new(capacity, generation, Some(parent));
+                index
+            }
+            None => {
+                self.frames.push(Frame::new(capacity, 0, Some(parent)));
+                self.frames.len() - 1
+            }
+        };
+
+        self.frames[index].next = first;
+        if let Some(first) = first {
+            self.frames[first].previous = Some(index);
+        }
+
+        self.frames[parent].first_child = Some(index);
+        self.stats.live += 1;
+        self.stats.allocated += 1;
+        Some(FrameId {
+            index,
+            generation: self.frames[index].generation,
+        })
+    }
+
+    fn get(&self, at: Self::Env, x: &Var) -> Option<&Val> {
+        let mut current = self.index(at);
+        while let Some(i) = current {
+            if i == 0 {
+                return self.globals.get(x);
+            }
+
+            let f = &self.frames[i];
+            if let Some((_, v)) = f.vars.iter().find(|(y, _)| y == x) {
+                return Some(v);
+            }
+
+            current = f.parent;
+        }
+
+        None
+    }
+
+    /// Finds the `Val` of a `Var` at the given position of the environment
+    /// the given number of levels up, or anywhere in that environment if it
+    /// was defined at another position, but never in the root environment.
+    fn get_at(&self, at: Self::Env, x: &Var, depth: usize, index: usize) -> Option<&Val> {
+        let mut i = self.index(at)?;
+        for _ in 0..depth {
+            i = self.frames[i].parent?;
+        }
+
+        let vars = &self.frames[i].vars;
+        match vars.get(index) {
+            Some((y, v)) if y == x => Some(v),
+            _ => vars.iter().find(|(y, _)| y == x).map(|(_, v)| v),
+        }
+    }
+
+    fn define(&mut self, at: Self::Env, x: Var, v: Val) -> Result<(), (Var, Val)> {
+        match self.index(at) {
+            None => Err((x, v)),
+            Some(0) => match self.globals.entry(x) {
+                Entry::Occupied(e) => Err((e.key().clone(), v)),
+                Entry::Vacant(e) => {
+                    e.insert(v);
+                    Ok(())
+                }
+            },
+            Some(i) => {
+                let vars = &mut self.frames[i].vars;
+                if vars.iter().any(|(y, _)| *y == x) {
+                    return Err((x, v));
+                }
+
+                vars.push((x, v));
+                Ok(())
+            }
+        }
+    }
+
+    fn set(&mut self, at: Self::Env, x: &Var, v: Val) -> Result<(), Val> {
+        let mut current = self.index(at);
+        while let Some(i) = current {
+            if i == 0 {
+                return match self.globals.get_mut(x) {
+                    Some(old) => {
+                        *old = v;
+                        Ok(())
+                    }
+                    None => Err(v),
+                };
+            }
+
+            let f = &mut self.frames[i];
+            if let Some((_, old)) = f.vars.iter_mut().find(|(y, _)| y == x) {
+                *old = v;
+                return Ok(());
+            }
+
+            current = f.parent;
+        }
+
+        Err(v)
+    }
+
+    fn within(&self, at: Self::Env, ancestor: Self::Env) -> bool {
+        let ancestor = match self.index(ancestor) {
+            Some(ancestor) => ancestor,
+            None => return false,
+        };
+
+        let mut current = self.index(at);
+        while let Some(i) = current {
+            if i == ancestor {
+                return true;
+            }
+
+            current = self.frames[i].parent;
+        }
+
+        false
+    }
+
+    /// Whether the environments pushed since the last collection reach the
+    /// threshold, or the number of the other live environments if greater, so
+    /// that the time spent collecting is proportional to the number of pushes.
+    fn should_collect(&self) -> bool {
+        let previous = self.stats.live.saturating_sub(self.stats.allocated);
+        self.stats.allocated >= self.threshold.max(previous)
+    }
+
+    /// Marks the environments found by the `Tracer` and their ancestors,
+    /// tracing the values of the global variables and the marked environments,
+    /// to then drop the environments that are not marked.
+    fn collect(&mut self, mut tracer: Tracer<Self::Env>) {
+        let mut marked = vec![false; self.frames.len()];
+        marked[0] = true;
+        for v in self.globals.values() {
+            v.trace(&mut tracer);
+        }
+
+        while let Some(at) = tracer.pop() {
+            let mut current = self.index(at);
+            while let Some(i) = current.filter(|&i| !marked[i]) {
+                marked[i] = true;
+                for (_, v) in &self.frames[i].vars {
+                    v.trace(&mut tracer);
+                }
+
+                current = self.frames[i].parent;
+            }
+        }
+
+        // As the ancestors of a marked environment are marked, dropping the
+        // unmarked environments whose parent is marked drops all of them.
+        let unreachable: Vec<FrameId> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(i, f)| f.live && !marked[*i] && f.parent.is_some_and(|p| marked[p]))
+            .map(|(index, f)| FrameId {
+                index,
+                generation: f.generation,
+            })
+            .collect();
+
+        let live = self.stats.live;
+        for at in unreachable {
+            Environments::drop(self, at);
+        }
+
+        self.stats.collections += 1;
+        self.stats.collected += live - self.stats.live;
+        self.stats.allocated = 0;
+    }
+
+    fn stats(&self) -> GcStats {
+        self.stats
+    }
+}
@@ -1,13 +1,19 @@
-use std::{collections::HashMap, hash::Hash};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    hash::Hash,
+};
 
 use slab_tree::{NodeId, RemoveBehavior, Tree};
 
-use super::env::Environments;
+use super::env::{Environments, GcStats, Trace, Tracer, DEFAULT_GC_THRESHOLD};
 
 /// An implementation of `Environments` with an in-memory `Tree`.
 pub struct EnvironmentTree<Var, Val> {
     root_id: NodeId,
     tree: Tree<HashMap<Var, Val>>,
+    /// The minimum number of environments pushed between collections.
+    threshold: usize,
+    stats: GcStats,
 }
 
 impl<Val, Var> EnvironmentTree<Val, Var> {
@@ -15,11 +21,28 @@ impl<Val, Var> EnvironmentTree<Val, Var> {
     pub fn empty(capacity: usize) -> Self {
         let mut tree = Tree::new();
         let root_id = tree.set_root(HashMap::with_capacity(capacity));
-        Self { root_id, tree }
+        Self {
+            root_id,
+            tree,
+            threshold: DEFAULT_GC_THRESHOLD,
+            stats: GcStats {
+                live: 1,
+                ..GcStats::default()
+            },
+        }
+    }
+
+    /// Sets the minimum number of environments pushed between collections of
+    /// the unreachable ones, which is `DEFAULT_GC_THRESHOLD` by default.
+    pub fn with_gc_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
     }
 }
 
-impl<Var: Eq + Hash, Val> Environments<Var, Val> for EnvironmentTree<Var, Val> {
+impl<Var: Eq + Hash + Clone, Val: Trace<NodeId>> Environments<Var, Val>
+    for EnvironmentTree<Var, Val>
+{
     type Env = NodeId;
 
     fn root(&self) -> Self::Env {
@@ -27,19 +50,30 @@ impl<Var: Eq + Hash, Val> Environments<Var, Val> for EnvironmentTree<Var, Val> {
     }
 
     fn drop(&mut self, at: Self::Env) {
+        let dropped = match self.tree.get(at) {
+            Some(n) if at != self.root_id => n.traverse_pre_order().count(),
+            _ => return,
+        };
+
         self.tree.remove(at, RemoveBehavior::DropChildren);
+        self.stats.live -= dropped;
     }
 
     fn push(&mut self, at: Self::Env, capacity: usize) -> Option<Self::Env> {
-        self.tree
+        let pushed = self
+            .tree
             .get_mut(at)
-            .map(|mut n| n.append(HashMap::with_capacity(capacity)).node_id())
+            .map(|mut n| n.append(HashMap::with_capacity(capacity)).node_id())?;
+
+        self.stats.live += 1;
+        self.stats.allocated += 1;
+        Some(pushed)
     }
 
     fn get(&self, at: Self::Env, x: &Var) -> Option<&Val> {
         let mut current = Some(at);
         while let Some(node) = current.and_then(|c| self.tree.get(c)) {
-            if let Some(v) = node.data().get(&x) {
+            if let Some(v) = node.data().get(x) {
                 return Some(v);
             }
 
@@ -52,14 +86,96 @@ impl<Var: Eq + Hash, Val> Environments<Var, Val> for EnvironmentTree<Var, Val> {
     fn define(&mut self, at: Self::Env, x: Var, v: Val) -> Result<(), (Var, Val)> {
         match self.tree.get_mut(at) {
             None => Err((x, v)),
-            Some(mut n) => {
-                if n.data().contains_key(&x) {
-                    Err((x, v))
-                } else {
-                    n.data().insert(x, v);
+            Some(mut n) => match n.data().entry(x) {
+                Entry::Occupied(e) => Err((e.key().clone(), v)),
+                Entry::Vacant(e) => {
+                    e.insert(v);
                     Ok(())
                 }
+            },
+        }
+    }
+
+    fn set(&mut self, at: Self::Env, x: &Var, v: Val) -> Result<(), Val> {
+        let mut current = Some(at);
+        while let Some(node) = current.and_then(|c| self.tree.get(c)) {
+            if node.data().contains_key(x) {
+                break;
             }
+
+            current = node.parent().map(|n| n.node_id());
+        }
+
+        match current.and_then(|c| self.tree.get_mut(c)) {
+            None => Err(v),
+            Some(mut n) => match n.data().get_mut(x) {
+                None => Err(v),
+                Some(old) => {
+                    *old = v;
+                    Ok(())
+                }
+            },
         }
     }
+
+    fn within(&self, at: Self::Env, ancestor: Self::Env) -> bool {
+        let mut current = Some(at);
+        while let Some(node) = current.and_then(|c| self.tree.get(c)) {
+            if node.node_id() == ancestor {
+                return true;
+            }
+
+            current = node.parent().map(|n| n.node_id());
+        }
+
+        false
+    }
+
+    fn should_collect(&self) -> bool {
+        let previous = self.stats.live.saturating_sub(self.stats.allocated);
+        self.stats.allocated >= self.threshold.max(previous)
+    }
+
+    fn collect(&mut self, mut tracer: Tracer<Self::Env>) {
+        let mut marked = HashSet::new();
+        tracer.env(self.root_id);
+        while let Some(at) = tracer.pop() {
+            let mut current = Some(at);
+            while let Some(node) = current
+                .filter(|&c| marked.insert(c))
+                .and_then(|c| self.tree.get(c))
+            {
+                for v in node.data().values() {
+                    v.trace(&mut tracer);
+                }
+
+                current = node.parent().map(|n| n.node_id());
+            }
+        }
+
+        let unreachable: Vec<NodeId> = match self.tree.get(self.root_id) {
+            None => Vec::new(),
+            Some(root) => root
+                .traverse_pre_order()
+                .filter(|n| {
+                    !marked.contains(&n.node_id())
+                        && n.parent().is_some_and(|p| marked.contains(&p.node_id()))
+                })
+                .map(|n| n.node_id())
+                .collect(),
+        };
+
+        let live = self.stats.live;
+        for at in unreachable {
+            Environments::drop(self, at);
+        }
+
+        self.stats.collections += 1;
+        self.stats.collected += live - self.stats.live;
+        self.stats.allocated = 0;
+    }
+
+    fn stats(&self) -> GcStats {
+        self.stats
+    }
 }
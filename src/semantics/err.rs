@@ -1,54 +1,176 @@
-use std::fmt::Debug;
+use std::{
+    convert::Infallible,
+    error::Error,
+    fmt::{Debug, Display},
+};
 
 use crate::syntax::{
     print::{PrintError, PrintWithSymbols},
+    span::Span,
     symb::Symbols,
 };
 
+use super::env::{Trace, Tracer};
+
 /// Errors that can arise during the evaluation of an `Exp`.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum RuntimeError<Symb, Val> {
     AlreadyDefined(Symb),
     ArityMismatch(),
     BadFormedExpression(Symb),
+    /// The evaluation was cancelled by setting its cancellation flag.
+    Cancelled(),
     CouldNotPushEnvironment(),
+    /// The evaluation reached its deadline.
+    DeadlineExceeded(),
+    /// The continuation stack reached its maximum depth.
+    DepthLimitExceeded(usize),
+    /// An element at a position, from zero, of an argument at a position that
+    /// is not of the expected kind.
+    ElementTypeMismatch(usize, usize, &'static str, Val),
+    /// The evaluation took as many steps as its fuel.
+    FuelExhausted(),
     InvalidArguments(),
+    /// An error that arose when evaluating the expression at a `Span`.
+    Located(Span, Box<Self>),
     MissingProcedure(),
     NotAProcedure(Val),
+    /// An output port that could not be written, with the reason.
+    OutputFailed(String),
+    /// A value raised by `raise` or `error` that no handler caught.
+    Raised(Val),
+    /// An argument at a position, from zero, that is not of the expected kind.
+    TypeMismatch(usize, &'static str, Val),
     UndefinedVariable(Symb),
     UnknownExpression(Symb),
     UnknownSymbol(Symb),
 }
 
-impl<Symb: Copy + Debug, Symbs: Symbols<Symb = Symb>, Val: PrintWithSymbols<Symbs>>
+impl<Symb, Val> RuntimeError<Symb, Val> {
+    /// Attaches the given `Span` to the error, unless it is already located.
+    pub fn located(self, span: Option<Span>) -> Self {
+        match (self, span) {
+            (RuntimeError::Located(s, e), _) => RuntimeError::Located(s, e),
+            (e, Some(s)) => RuntimeError::Located(s, Box::new(e)),
+            (e, None) => e,
+        }
+    }
+
+    /// Returns the `Span` of the error, if located.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            RuntimeError::Located(s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Whether the error stops the evaluation because of its `Limits`, in which
+    /// case it is not passed to the exception handlers.
+    pub fn is_limit(&self) -> bool {
+        match self {
+            RuntimeError::Located(_, e) => e.is_limit(),
+            RuntimeError::Cancelled()
+            | RuntimeError::DeadlineExceeded()
+            | RuntimeError::FuelExhausted() => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the error without its location.
+    pub fn unlocated(self) -> Self {
+        match self {
+            RuntimeError::Located(_, e) => e.unlocated(),
+            e => e,
+        }
+    }
+}
+
+impl<Symb, Env, Val: Trace<Env>> Trace<Env> for RuntimeError<Symb, Val> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        match self {
+            RuntimeError::Located(_, e) => e.trace(tracer),
+            RuntimeError::ElementTypeMismatch(_, _, _, v)
+            | RuntimeError::NotAProcedure(v)
+            | RuntimeError::Raised(v)
+            | RuntimeError::TypeMismatch(_, _, v) => v.trace(tracer),
+            _ => (),
+        }
+    }
+}
+
+impl<Symb: Copy + Debug, Val: Clone> RuntimeError<Symb, Val> {
+    /// Renders the message of the error with the given printers of its symbols
+    /// and values.
+    fn render<E>(
+        &self,
+        symbol: &impl Fn(Symb) -> Result<String, E>,
+        value: &impl Fn(Val) -> Result<String, E>,
+    ) -> Result<String, E> {
+        match self {
+            RuntimeError::AlreadyDefined(s) => symbol(*s).map(|s| format!("Already defined: {s}")),
+            RuntimeError::ArityMismatch() => Ok("Arity mismatch".to_string()),
+            RuntimeError::BadFormedExpression(s) => {
+                symbol(*s).map(|s| format!("Bad formed expression: {s}"))
+            }
+            RuntimeError::Cancelled() => Ok("Cancelled".to_string()),
+            RuntimeError::CouldNotPushEnvironment() => Ok("Could not push environment".to_string()),
+            RuntimeError::DeadlineExceeded() => Ok("Deadline exceeded".to_string()),
+            RuntimeError::DepthLimitExceeded(n) => Ok(format!("Depth limit exceeded: {n}")),
+            RuntimeError::ElementTypeMismatch(i, j, expected, v) => value(v.clone()).map(|s| {
+                format!(
+                    "Type mismatch: expected a {expected} as element {} of argument {}, got {s}",
+                    j + 1,
+                    i + 1
+                )
+            }),
+            RuntimeError::FuelExhausted() => Ok("Fuel exhausted".to_string()),
+            RuntimeError::InvalidArguments() => Ok("Invalid arguments".to_string()),
+            RuntimeError::Located(s, e) => e.render(symbol, value).map(|e| format!("{s}: {e}")),
+            RuntimeError::MissingProcedure() => Ok("Missing procedure".to_string()),
+            RuntimeError::NotAProcedure(v) => {
+                value(v.clone()).map(|s| format!("Not a procedure: {s}"))
+            }
+            RuntimeError::OutputFailed(e) => Ok(format!("Output failed: {e}")),
+            RuntimeError::Raised(v) => value(v.clone()).map(|s| format!("Uncaught exception: {s}")),
+            RuntimeError::TypeMismatch(i, expected, v) => value(v.clone()).map(|s| {
+                format!(
+                    "Type mismatch: expected a {expected} as argument {}, got {s}",
+                    i + 1
+                )
+            }),
+            RuntimeError::UndefinedVariable(x) => {
+                symbol(*x).map(|s| format!("Undefined variable: {s}"))
+            }
+            RuntimeError::UnknownExpression(s) => {
+                symbol(*s).map(|s| format!("Unknown expression: {s}"))
+            }
+            RuntimeError::UnknownSymbol(s) => Ok(format!("Unknown symbol: {s:?}")),
+        }
+    }
+}
+
+impl<Symb: Copy + Debug, Symbs: Symbols<Symb = Symb>, Val: Clone + PrintWithSymbols<Symbs>>
     PrintWithSymbols<Symbs> for RuntimeError<Symbs::Symb, Val>
 {
     fn print_with(self, symbols: &Symbs) -> Result<String, PrintError<Symbs::Symb>> {
-        match self {
-            RuntimeError::AlreadyDefined(s) => match symbols.resolve(s) {
-                None => Err(PrintError::UnknownSymbol(s)),
-                Some(s) => Ok(format!("Already defined: {s}")),
-            },
-            RuntimeError::ArityMismatch() => Ok(format!("Arity mismatch")),
-            RuntimeError::BadFormedExpression(s) => match symbols.resolve(s) {
+        self.render(
+            &|s| match symbols.resolve(s) {
                 None => Err(PrintError::UnknownSymbol(s)),
-                Some(s) => Ok(format!("Bad formed expression: {s}")),
-            },
-            RuntimeError::CouldNotPushEnvironment() => Ok(format!("Could not push environment")),
-            RuntimeError::InvalidArguments() => Ok(format!("Invalid arguments")),
-            RuntimeError::MissingProcedure() => Ok(format!("Missing procedure")),
-            RuntimeError::NotAProcedure(v) => v
-                .print_with(symbols)
-                .map(|s| format!("Not a procedure: {s}")),
-            RuntimeError::UndefinedVariable(x) => match symbols.resolve(x) {
-                None => Err(PrintError::UnknownSymbol(x)),
-                Some(s) => Ok(format!("Undefined variable: {s}")),
+                Some(s) => Ok(s.to_string()),
             },
-            RuntimeError::UnknownExpression(s) => match symbols.resolve(s) {
-                None => Err(PrintError::UnknownSymbol(s)),
-                Some(s) => Ok(format!("Unknown expression: {s}")),
-            },
-            RuntimeError::UnknownSymbol(s) => Ok(format!("Unknown symbol: {s:?}")),
-        }
+            &|v: Val| v.print_with(symbols),
+        )
     }
 }
+
+/// Displays the symbols and values of the error as their `Debug` representation,
+/// as their names are unknown without the symbols, which print it instead.
+impl<Symb: Copy + Debug, Val: Clone + Debug> Display for RuntimeError<Symb, Val> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Ok::<_, Infallible>(message) =
+            self.render(&|s| Ok(format!("{s:?}")), &|v| Ok(format!("{v:?}")));
+        f.write_str(&message)
+    }
+}
+
+impl<Symb: Copy + Debug, Val: Clone + Debug> Error for RuntimeError<Symb, Val> {}
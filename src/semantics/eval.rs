@@ -1,28 +1,93 @@
-use std::{hash::Hash, marker::PhantomData};
+use std::{hash::Hash, marker::PhantomData, mem, rc::Rc};
 
-use crate::syntax::{exp::Exp, symb::Symbols};
+use crate::syntax::{exp::Exp, span::Span, symb::Symbols};
 
 use super::{
-    built_in::EvalBuiltIn, env::Environments, err::RuntimeError, res::EvalResult, val::Val,
+    built_in::{Control, EvalBuiltIn},
+    condition::raised,
+    cont::{
+        transitions, Catch, Continuation, DefaultFrame, Frame, Handler, Handlers, LetFrame,
+        Suspended, Winder, Winders,
+    },
+    convert::IntoBuiltIn,
+    env::{Environments, Roots, Trace, Tracer},
+    err::RuntimeError,
+    limits::Limits,
+    macros::{data, expand, unrenamed, Macro, Renames},
+    params::Params,
+    port::OutputPort,
+    quasi::{build, unquoted},
+    res::EvalResult,
+    resolve::{unresolved, Resolver},
+    syntax_rules::SyntaxRules,
+    val::Val,
 };
 
-/// An evaluator of Risp expressions, with read only access to some
-/// symbols and the capability of mutating an environment.
+/// The maximum number of frames in the continuation stack by default, which
+/// allows a recursion of a million levels within half a gigabyte.
+pub const DEFAULT_MAX_DEPTH: usize = 2_000_000;
+
+/// An evaluator of Risp expressions, with the capability of mutating
+/// some symbols and an environment.
 pub struct Evaluator<'a, Val, Symbs: Symbols, Envs: Environments<Symbs::Symb, Val>> {
-    symbols: &'a Symbs,
+    symbols: &'a mut Symbs,
     environment: &'a mut Envs,
+    redefine_top_level: bool,
+    max_depth: usize,
+    limits: Limits,
+    escapes: usize,
+    renames: Renames<Symbs::Symb, Envs::Env>,
+    output: OutputPort,
+    roots: Roots<Val>,
     val: PhantomData<Val>,
 }
 
 enum EvalStep<Bool, Numb, Symb, Env, BuiltIn> {
     Done(EvalResult<Bool, Numb, Symb, Env, BuiltIn>),
     Loop(Exp<Bool, Numb, Symb>, Env),
+    /// Evaluates an expression in an environment, to then resume the frame with its value.
+    Push(
+        Frame<Bool, Numb, Symb, Env, BuiltIn>,
+        Exp<Bool, Numb, Symb>,
+        Env,
+    ),
+    /// Applies a procedure to some values, to then resume the frame with the result.
+    Call(
+        Frame<Bool, Numb, Symb, Env, BuiltIn>,
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    ),
+    /// Applies a procedure to the current continuation.
+    Capture(Val<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Resumes a continuation with a value.
+    Jump(
+        Rc<Continuation<Bool, Numb, Symb, Env, BuiltIn>>,
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ),
+    /// Passes an error to the innermost handler, which may return for a
+    /// continuable one.
+    Raise(
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>,
+        bool,
+    ),
+    /// Calls a thunk with a procedure installed as handler.
+    Handle(
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ),
+    /// Evaluates the reversed body of a `guard` in an environment, with its
+    /// frame installed as handler.
+    Guard(
+        Frame<Bool, Numb, Symb, Env, BuiltIn>,
+        Vec<Exp<Bool, Numb, Symb>>,
+        Env,
+    ),
 }
 
 impl<
         'a,
-        Bool: Into<bool> + From<bool> + Clone,
-        Numb: Clone,
+        Bool: Into<bool> + From<bool> + PartialEq + Clone,
+        Numb: PartialEq + Clone,
         Symb: Eq + Hash + Copy,
         Env: Eq + Copy,
         Symbs: Symbols<Symb = Symb>,
@@ -40,64 +105,383 @@ impl<
     >
 {
     /// Creates a new `Evaluator` with the given symbols and environment.
-    pub fn new(symbols: &'a Symbs, environment: &'a mut Envs) -> Self {
+    pub fn new(symbols: &'a mut Symbs, environment: &'a mut Envs) -> Self {
         Self {
             symbols,
             environment,
+            redefine_top_level: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            limits: Limits::new(),
+            escapes: 0,
+            renames: Renames::new(),
+            output: OutputPort::stdout(),
+            roots: Roots::new(),
             val: PhantomData,
         }
     }
 
-    /// Tries to evaluate an `Exp` into a `Val`.
+    /// Allows or forbids `define` to overwrite an existing variable of the
+    /// root environment, which is forbidden by default.
+    pub fn with_top_level_redefinition(mut self, allow: bool) -> Self {
+        self.redefine_top_level = allow;
+        self
+    }
+
+    /// Sets the maximum number of frames in the continuation stack, beyond
+    /// which the evaluation fails, which is `DEFAULT_MAX_DEPTH` by default.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the port where `display` and the like write, which is the
+    /// standard output by default.
+    pub fn with_output(mut self, output: OutputPort) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Shares the symbols that the expansions of macros renamed with other
+    /// evaluators, which may meet them in the lambdas and data that the
+    /// expansions create.
+    pub fn with_renames(mut self, renames: Renames<Symb, Env>) -> Self {
+        self.renames = renames;
+        self
+    }
+
+    /// Sets the values held by the application, whose environments are kept
+    /// by the collections that happen during evaluation.
+    pub fn with_roots(
+        mut self,
+        roots: Roots<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> Self {
+        self.roots = roots;
+        self
+    }
+
+    /// Sets the bounds of the evaluations, which are unbounded by default,
+    /// where the fuel is shared by the evaluations of the `Evaluator` and
+    /// counts the expressions that are evaluated.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns the bounds of the evaluations, with the fuel that remains.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Returns the symbols of the evaluator, which can grow during evaluation.
+    pub fn symbols(&self) -> &Symbs {
+        self.symbols
+    }
+
+    /// Defines a built-in procedure in the root environment from a Rust
+    /// function, whose arguments and result are converted from and into values.
+    pub fn register<Args>(
+        &mut self,
+        name: &str,
+        f: impl IntoBuiltIn<Args, Bool, Numb, Symb, Env, Symbs>,
+    ) -> Result<
+        (),
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    > {
+        let x = self.symbols.get_or_store(name);
+        let v = Val::BuiltIn(f.into_built_in(name));
+        self.define(self.environment.root(), x, v).map(|_| ())
+    }
+
+    /// Tries to evaluate an `Exp` into a `Val`, once its references to local
+    /// variables are resolved to their position in the environments.
     pub fn eval(
         &mut self,
         exp: Exp<Bool, Numb, Symb>,
     ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
-        self.eval_loop(exp, self.environment.root())
+        let (at, span) = (self.environment.root(), exp.span());
+        let environment = &*self.environment;
+        let global = |x| environment.get(at, &x).map(|v| matches!(v, Val::Macro(_)));
+        let exp = Resolver::new(&*self.symbols, global).resolve(exp);
+        let step = self.eval_step(exp, at);
+        self.eval_loop(step, at, span)
+    }
+
+    /// Tries to apply a procedure to some values in the root environment.
+    pub fn apply(
+        &mut self,
+        f: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let at = self.environment.root();
+        let step = self.apply_procedure(f, args, at);
+        self.eval_loop(step, at, None)
     }
 
+    /// Takes evaluation steps from the first one in an environment, whose
+    /// expression may be located at a `Span`, until the result is known.
     fn eval_loop(
         &mut self,
-        exp: Exp<Bool, Numb, Symb>,
+        mut step: EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
         at: Env,
+        span: Option<Span>,
     ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
-        let (mut next_exp, mut next_at) = (exp, at);
+        // The continuation is kept in the heap rather than in the Rust stack,
+        // so that the depth of non tail recursion is only limited by memory.
+        let mut stack: Vec<
+            Suspended<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        > = Vec::new();
+        let (mut next_at, mut span) = (at, span);
+        let mut owned: Vec<Env> = Vec::new();
+        let mut escapes = self.escapes;
+        let mut winders: Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> =
+            None;
+        let mut handlers: Handlers<
+            Bool,
+            Numb,
+            Symb,
+            Env,
+            EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>,
+        > = None;
+        // The output is redirected only until the evaluation ends, even if it
+        // ends with an error.
+        let output = self.output.clone();
         loop {
-            // Although it has not been formally proven that the following attempts 
+            // Although it has not been formally proven that the following attempts
             // to clean up the environment do not break the intended semantics,
             // here it is commented the informal reasoning.
-            match self.eval_step(next_exp, next_at) {
+            //
+            // The owned environments have been created within the evaluation of the
+            // current expression, either for the invocation of a lambda or for local
+            // bindings, and each of them contains the following ones, up to next_at.
+            // The evaluation of a subexpression suspends them in the stack.
+            //
+            // A lambda created within an owned environment can only escape to other
+            // environments by returning it at this point, maybe within a list, or by
+            // assigning it with set! to a variable or passing it as an argument of a
+            // lambda from outside, which is tracked by counting such escapes. The
+            // capture of a continuation also counts as an escape, as it references
+            // every environment of the stack.
+            match step {
+                EvalStep::Done(Err(err)) if handlers.is_some() && !err.is_limit() => {
+                    step = EvalStep::Raise(err, false);
+                }
                 EvalStep::Done(r) => {
-                    // If at != next_at, then next_at has been created within the execution
-                    // of this loop as the fresh invocation environment of a lambda.
-                    //
-                    // As the only way for a lambda created within the next_at environment
-                    // subtree to escape to other environments is by returning such lambda
-                    // at this point, the next_at environment subtree can be removed when
-                    // the result value is not a lambda.
-                    if at != next_at {
-                        match r {
-                            Ok(Val::Lamb(_, _, _)) => (),
-                            _ => self.environment.drop(next_at),
+                    // As no lambda has escaped, the owned environments can be removed,
+                    // unless they are referenced from the result value.
+                    if let Some(&outermost) = owned.first() {
+                        if self.escapes == escapes && !matches!(&r, Ok(v) if v.has_lamb()) {
+                            self.environment.drop(outermost);
                         }
                     }
 
-                    return r;
+                    let suspended = match stack.pop() {
+                        Some(suspended) => suspended,
+                        None => {
+                            self.output = output;
+                            return r;
+                        }
+                    };
+
+                    (next_at, span) = (suspended.at, suspended.span);
+                    (owned, escapes) = (suspended.owned, suspended.escapes);
+                    step = match r.map(|v| {
+                        self.resume(suspended.frame, v, next_at, &mut winders, &mut handlers)
+                    }) {
+                        Ok(EvalStep::Done(Err(err))) | Err(err) => {
+                            EvalStep::Done(Err(err.located(span.clone())))
+                        }
+                        Ok(step) => step,
+                    };
                 }
                 EvalStep::Loop(exp, continue_at) => {
-                    // If at != next_at, then next_at has been created within the execution
-                    // of this loop as the fresh invocation environment of a lambda. Also,
-                    // if continue_at != next_at, then continue_at is again from a fresh
-                    // lambda invocation.
-                    //
-                    // As the next_at subtree has not returned any value result that could
-                    // be a lambda created within it, and a new environment is going to be
-                    // used, the next_at environment tree can be removed.
-                    if at != next_at && continue_at != next_at {
-                        self.environment.drop(next_at);
+                    // If continue_at != next_at, then continue_at is again a fresh
+                    // environment, so the owned environments that do not contain it
+                    // are not going to be used anymore and can be removed if no lambda
+                    // has escaped. Otherwise, the owned environments are kept forever.
+                    if continue_at != next_at {
+                        if self.escapes == escapes {
+                            if let Some(i) = owned
+                                .iter()
+                                .position(|&o| !self.environment.within(continue_at, o))
+                            {
+                                self.environment.drop(owned[i]);
+                                owned.truncate(i);
+                            }
+                        } else {
+                            owned.clear();
+                            escapes = self.escapes;
+                        }
+
+                        // Most evaluations own a single environment, which is
+                        // suspended with them at each level of a recursion.
+                        if owned.capacity() == 0 {
+                            owned.reserve_exact(1);
+                        }
+
+                        owned.push(continue_at);
+                    }
+
+                    // The expressions do not hold values, so the environments that
+                    // remain reachable are those of the state of the evaluation and
+                    // those of the values held by the application.
+                    if self.environment.should_collect() {
+                        let mut tracer = Tracer::new(owned.clone());
+                        tracer.env(continue_at);
+                        stack.trace(&mut tracer);
+                        winders.trace(&mut tracer);
+                        handlers.trace(&mut tracer);
+                        self.roots.trace(&mut tracer);
+                        self.environment.collect(tracer);
+                    }
+
+                    (next_at, span) = (continue_at, exp.span());
+                    step = self.eval_step(exp, continue_at);
+                }
+                EvalStep::Push(_, _, _) | EvalStep::Call(_, _, _) | EvalStep::Guard(_, _, _)
+                    if stack.len() >= self.max_depth =>
+                {
+                    step = EvalStep::Done(Err(
+                        RuntimeError::DepthLimitExceeded(self.max_depth).located(span.clone())
+                    ));
+                }
+                EvalStep::Push(frame, exp, push_at) => {
+                    stack.push(Suspended {
+                        frame,
+                        at: next_at,
+                        span: span.take(),
+                        owned: mem::take(&mut owned),
+                        escapes,
+                    });
+
+                    (next_at, span) = (push_at, exp.span());
+                    escapes = self.escapes;
+                    step = self.eval_step(exp, push_at);
+                }
+                EvalStep::Call(frame, f, args) => {
+                    stack.push(Suspended {
+                        frame,
+                        at: next_at,
+                        span: span.take(),
+                        owned: mem::take(&mut owned),
+                        escapes,
+                    });
+
+                    escapes = self.escapes;
+                    step = self.apply_procedure(f, args, next_at);
+                }
+                EvalStep::Capture(f) => {
+                    self.escapes += 1;
+                    let k = Continuation {
+                        stack: stack.clone(),
+                        winders: winders.clone(),
+                        handlers: handlers.clone(),
+                        output: self.output.clone(),
+                    };
+
+                    step = self.apply_procedure(f, vec![Val::Cont(Rc::new(k))], next_at);
+                }
+                EvalStep::Jump(k, v) => {
+                    // The current evaluation is abandoned, along with its owned environments.
+                    stack.clone_from(&k.stack);
+                    (owned, escapes) = (Vec::new(), self.escapes);
+                    handlers.clone_from(&k.handlers);
+                    self.output = k.output.clone();
+                    step = self.wind(&mut winders, k.winders.clone(), v);
+                }
+                EvalStep::Raise(err, continuable) => {
+                    let handler = match handlers.clone() {
+                        Some(handler) => handler,
+                        None => {
+                            step = EvalStep::Done(Err(err.located(span.clone())));
+                            continue;
+                        }
+                    };
+
+                    let v = raised(&err, self.symbols);
+                    match &handler.catch {
+                        Catch::Procedure(f) => {
+                            // The handler is called with the enclosing handlers, and
+                            // regardless of the depth limit so that it can handle it.
+                            let frame = if continuable {
+                                Frame::Continue(handlers.take())
+                            } else {
+                                Frame::Reraise(Box::new(err))
+                            };
+
+                            stack.push(Suspended {
+                                frame,
+                                at: next_at,
+                                span: span.clone(),
+                                owned: mem::take(&mut owned),
+                                escapes,
+                            });
+
+                            escapes = self.escapes;
+                            handlers.clone_from(&handler.parent);
+                            step = self.apply_procedure(f.clone(), vec![v], next_at);
+                        }
+                        Catch::Guard(i, to) => {
+                            // The evaluation is abandoned up to the guard, so the owned
+                            // environments of the abandoned frames can be removed as
+                            // when they are done.
+                            let abandoned: Vec<_> = stack.drain(i + 1..).collect();
+                            for s in &abandoned {
+                                // The output is restored as it was before the outermost
+                                // abandoned redirection.
+                                if let Frame::Output(port) = &s.frame {
+                                    self.output = port.clone();
+                                    break;
+                                }
+                            }
+
+                            let abandoned = abandoned.into_iter().map(|s| (s.owned, s.escapes));
+                            for (owned, escapes) in abandoned.chain([(owned, escapes)]) {
+                                if let Some(&outermost) = owned.first() {
+                                    if self.escapes == escapes && !v.has_lamb() {
+                                        self.environment.drop(outermost);
+                                    }
+                                }
+                            }
+
+                            let guard = &mut stack[*i];
+                            if let Frame::Guard(x, clauses, s) =
+                                mem::replace(&mut guard.frame, Frame::WithHandler())
+                            {
+                                let err = Box::new(err);
+                                guard.frame = Frame::Catch { x, clauses, s, err };
+                            }
+
+                            (next_at, span) = (guard.at, guard.span.clone());
+                            (owned, escapes) = (Vec::new(), self.escapes);
+                            handlers.clone_from(&handler.parent);
+                            step = self.wind(&mut winders, to.clone(), v);
+                        }
                     }
+                }
+                EvalStep::Handle(handler, thunk) => {
+                    handlers = Some(Rc::new(Handler {
+                        catch: Catch::Procedure(handler),
+                        parent: handlers.take(),
+                    }));
+
+                    step = EvalStep::Call(Frame::WithHandler(), thunk, Vec::new());
+                }
+                EvalStep::Guard(frame, body, guard_at) => {
+                    handlers = Some(Rc::new(Handler {
+                        catch: Catch::Guard(stack.len(), winders.clone()),
+                        parent: handlers.take(),
+                    }));
+
+                    stack.push(Suspended {
+                        frame,
+                        at: next_at,
+                        span: span.take(),
+                        owned: mem::take(&mut owned),
+                        escapes,
+                    });
 
-                    (next_exp, next_at) = (exp, continue_at)
+                    (next_at, escapes) = (guard_at, self.escapes);
+                    step = self.eval_body(body, guard_at);
                 }
             }
         }
@@ -108,164 +492,1737 @@ impl<
         exp: Exp<Bool, Numb, Symb>,
         at: Env,
     ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match self.eval_atom(exp, at) {
+            Ok(r) => EvalStep::Done(r),
+            // Each list takes a step, so that any loop is bounded.
+            Err((ls, span)) => match self.limits.step() {
+                Ok(()) => match self.eval_list(ls, span.clone(), at) {
+                    EvalStep::Done(Err(err)) => EvalStep::Done(Err(err.located(span))),
+                    step => step,
+                },
+                Err(err) => EvalStep::Done(Err(err.located(span))),
+            },
+        }
+    }
+
+    /// Evaluates an expression that is not a list, or gives back the elements
+    /// and span of the list otherwise.
+    fn eval_atom(
+        &self,
+        exp: Exp<Bool, Numb, Symb>,
+        at: Env,
+    ) -> Result<
+        EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        (Vec<Exp<Bool, Numb, Symb>>, Option<Span>),
+    > {
         match exp {
-            Exp::Numb(n) => EvalStep::Done(Ok(Val::Numb(n))),
-            Exp::Bool(b) => EvalStep::Done(Ok(Val::Bool(b))),
-            Exp::Quot(b) => EvalStep::Done(Ok(match *b {
-                Exp::Numb(n) => Val::Numb(n),
-                Exp::Bool(b) => Val::Bool(b),
-                _ => Val::Quot(*b),
-            })),
-            Exp::Symb(s) => match self.environment.get(at, &s) {
-                Some(v) => EvalStep::Done(Ok(v.clone())),
-                None => EvalStep::Done(Err(RuntimeError::UndefinedVariable(s))),
+            Exp::Numb(n, _) => Ok(Ok(Val::Numb(n))),
+            Exp::Bool(b, _) => Ok(Ok(Val::Bool(b))),
+            Exp::Str(s, _) => Ok(Ok(Val::Str(s))),
+            Exp::Quot(b, _) => Ok(Ok(Val::quoted(*b))),
+            Exp::Symb(s, span) => match self.lookup(at, s) {
+                Some(v) => Ok(Ok(v.clone())),
+                None => Ok(Err(RuntimeError::UndefinedVariable(s).located(span))),
             },
-            Exp::List(mut ls) => match ls.pop() {
-                Some(Exp::Symb(s)) => match self.environment.get(at, &s) {
-                    Some(v) => self.eval_app_procedure(v.clone(), ls, at),
-                    None => match self.symbols.resolve(s) {
-                        Some("define") => {
-                            EvalStep::Done(match (ls.pop(), ls.pop(), ls.pop()) {
-                                (Some(Exp::Symb(x)), Some(e), None) => self
-                                    .eval_loop(e, at)
-                                    .and_then(|v| match self.environment.define(at, x, v) {
-                                        Ok(()) => Ok(Val::Void()),
-                                        Err((x, _)) => Err(RuntimeError::AlreadyDefined(x)),
-                                    }),
-                                _ => Err(RuntimeError::BadFormedExpression(s)),
-                            })
+            Exp::Local(s, depth, index, span) => match self.lookup_local(at, s, depth, index) {
+                Some(v) => Ok(Ok(v.clone())),
+                None => Ok(Err(RuntimeError::UndefinedVariable(s).located(span))),
+            },
+            Exp::List(ls, span) => Err((Rc::unwrap_or_clone(ls), span)),
+            // A dotted list is only read as data, and cannot be evaluated.
+            Exp::Dotted(_, _, span) => Ok(Err(RuntimeError::InvalidArguments().located(span))),
+        }
+    }
+
+    fn eval_list(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        span: Option<Span>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match ls.pop() {
+            Some(Exp::Symb(s, _)) => match self.lookup(at, s) {
+                Some(Val::Macro(m)) => {
+                    // The operands are given to the macro as they were read.
+                    let ls = ls.into_iter().map(unresolved).collect();
+                    match &*m.clone() {
+                        Macro::Rules(rules, defined_at) => {
+                            match expand(rules, ls, span, self.symbols, &self.renames, *defined_at)
+                            {
+                                Some(e) => EvalStep::Loop(e, at),
+                                None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                            }
                         }
-                        Some("quote") => match (ls.pop(), ls.pop()) {
-                            (Some(e), None) => EvalStep::Loop(Exp::Quot(Box::new(e)), at),
-                            _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
-                        },
-                        Some("if") => match (ls.pop(), ls.pop(), ls.pop(), ls.pop()) {
-                            (Some(c), Some(e1), Some(e2), None) => match self.eval_loop(c, at) {
-                                Ok(v) => EvalStep::Loop(if v.into() { e1 } else { e2 }, at),
-                                Err(err) => EvalStep::Done(Err(err)),
-                            },
-                            _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                        // The expansion is evaluated in place of the form.
+                        Macro::Procedure(f) => EvalStep::Call(Frame::Eval(), f.clone(), data(ls)),
+                    }
+                }
+                Some(v) => self.eval_app_procedure(v.clone(), ls, at),
+                None => match self.keyword(at, s) {
+                    Some("define") => match (ls.pop(), ls.pop(), ls.pop()) {
+                        (Some(Exp::Symb(x, _)), Some(e), None) => {
+                            EvalStep::Push(Frame::Define(x), e, at)
+                        }
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("set!") => match (ls.pop(), ls.pop(), ls.pop()) {
+                        (Some(Exp::Symb(x, _)), Some(e), None) => {
+                            EvalStep::Push(Frame::Set(x), e, at)
+                        }
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("quote") => match (ls.pop(), ls.pop()) {
+                        (Some(e), None) => EvalStep::Loop(Exp::Quot(Box::new(e), span), at),
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("quasiquote") => match (ls.pop(), ls.pop()) {
+                        (Some(e), None) => {
+                            let mut es = Vec::new();
+                            unquoted(&e, 1, self.symbols, &mut es);
+                            let es: Vec<_> = es.into_iter().rev().cloned().collect();
+                            self.eval_quasiquote(e, Vec::with_capacity(es.len()), es, at)
+                        }
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("if") => match ls.pop() {
+                        Some(c) if ls.len() == 2 => EvalStep::Push(Frame::If(ls), c, at),
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("begin") => self.eval_body(ls, at),
+                    Some("eval") => match (ls.pop(), ls.pop()) {
+                        (Some(e), None) => EvalStep::Push(Frame::Eval(), e, at),
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("lambda") => EvalStep::Done(match (ls.pop(), ls.pop(), ls.pop()) {
+                        (Some(ps), Some(b), None) => match Params::parse(ps, self.symbols) {
+                            Some(ps) => Ok(Val::Lamb(Rc::new((ps, b)), at)),
+                            None => Err(RuntimeError::BadFormedExpression(s)),
                         },
-                        Some("begin") => EvalStep::Done(
-                            self.eval_args(ls, at)
-                                .map(|mut args| args.pop().unwrap_or_else(Val::Void)),
-                        ),
-                        Some("eval") => match (ls.pop(), ls.pop()) {
-                            (Some(e), None) => match self.eval_loop(e, at) {
-                                Ok(Val::Quot(e)) => EvalStep::Loop(e, at),
-                                Ok(e) => EvalStep::Done(Ok(e)),
-                                Err(err) => EvalStep::Done(Err(err)),
-                            },
+                        _ => Err(RuntimeError::BadFormedExpression(s)),
+                    }),
+                    Some("let") => match ls.pop() {
+                        Some(Exp::Symb(name, _)) => self.eval_named_let(name, ls, at, s),
+                        Some(bs) => self.eval_let(bs, ls, at, s),
+                        None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("let*") => match ls.pop() {
+                        Some(bs) => self.eval_let_star(bs, ls, at, s),
+                        None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("letrec" | "letrec*") => match ls.pop() {
+                        Some(bs) => self.eval_letrec(bs, ls, at, s),
+                        None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("cond") => self.eval_cond(ls, at, s, None),
+                    Some("case") => match ls.pop() {
+                        Some(key) => EvalStep::Push(Frame::Case(ls, s), key, at),
+                        None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("when") => self.eval_when(ls, at, s, true),
+                    Some("unless") => self.eval_when(ls, at, s, false),
+                    Some("guard") => match ls.pop() {
+                        Some(Exp::List(clauses, _)) if !ls.is_empty() => match clauses.split_last()
+                        {
+                            Some((&Exp::Symb(x, _), clauses)) => {
+                                EvalStep::Guard(Frame::Guard(x, clauses.to_vec(), s), ls, at)
+                            }
                             _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
                         },
-                        Some("lambda") => EvalStep::Done(match (ls.pop(), ls.pop(), ls.pop()) {
-                            (Some(Exp::List(ls)), Some(b), None) => {
-                                match ls.into_iter().rev().map(Exp::symb).collect() {
-                                    Some(ps) => Ok(Val::Lamb(ps, b, at.clone())),
-                                    None => Err(RuntimeError::BadFormedExpression(s)),
-                                }
-                            }
-                            _ => Err(RuntimeError::BadFormedExpression(s)),
-                        }),
-                        Some("and") => {
-                            let mut last = Val::Bool(Bool::from(true));
-                            while let Some(e) = ls.pop() {
-                                match self.eval_loop(e, at) {
-                                    Err(err) => return EvalStep::Done(Err(err)),
-                                    Ok(Val::Bool(b)) => {
-                                        if b.into() {
-                                            last = Val::Bool(Bool::from(true));
-                                        } else {
-                                            return EvalStep::Done(Ok(Val::Bool(Bool::from(
-                                                false,
-                                            ))));
-                                        }
-                                    }
-                                    Ok(v) => last = v,
-                                }
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("define-syntax") => match (ls.pop(), ls.pop(), ls.pop()) {
+                        (Some(Exp::Symb(x, _)), Some(e), None) => {
+                            match SyntaxRules::parse(e, self.symbols) {
+                                Some(rules) => EvalStep::Done(self.define(
+                                    at,
+                                    x,
+                                    Val::Macro(Rc::new(Macro::Rules(rules, at))),
+                                )),
+                                None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
                             }
-
-                            EvalStep::Done(Ok(last))
                         }
-                        Some("or") => {
-                            while let Some(e) = ls.pop() {
-                                match self.eval_loop(e, at) {
-                                    Err(err) => return EvalStep::Done(Err(err)),
-                                    Ok(Val::Bool(b)) => {
-                                        if b.into() {
-                                            return EvalStep::Done(Ok(Val::Bool(Bool::from(true))));
-                                        }
-                                    }
-                                    Ok(v) => return EvalStep::Done(Ok(v)),
-                                }
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("define-macro") => match ls.pop() {
+                        Some(Exp::List(header, span)) => match header.split_last() {
+                            Some((&Exp::Symb(x, _), params)) => {
+                                let params = Exp::List(Rc::new(params.to_vec()), span);
+                                self.define_macro(x, params, ls, at, s)
                             }
+                            _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                        },
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("defmacro") => match (ls.pop(), ls.pop()) {
+                        (Some(Exp::Symb(x, _)), Some(ps)) => self.define_macro(x, ps, ls, at, s),
+                        _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                    },
+                    Some("let-syntax" | "letrec-syntax") => self.eval_let_syntax(ls, at, s),
+                    Some("and") => self.eval_and(ls, at),
+                    Some("or") => self.eval_or(ls, at),
+                    Some(_) => EvalStep::Done(Err(RuntimeError::UnknownExpression(s))),
+                    None => EvalStep::Done(Err(RuntimeError::UnknownSymbol(s))),
+                },
+            },
+            Some(Exp::Local(s, depth, index, s_span)) => {
+                match self.lookup_local(at, s, depth, index) {
+                    Some(Val::Macro(_)) | None => {
+                        ls.push(Exp::Symb(s, s_span));
+                        self.eval_list(ls, span, at)
+                    }
+                    Some(v) => self.eval_app_procedure(v.clone(), ls, at),
+                }
+            }
+            Some(e) => EvalStep::Push(Frame::Operator(ls), e, at),
+            None => EvalStep::Done(Err(RuntimeError::MissingProcedure())),
+        }
+    }
 
-                            EvalStep::Done(Ok(Val::Bool(Bool::from(false))))
-                        }
-                        Some(_) => EvalStep::Done(Err(RuntimeError::UnknownExpression(s))),
-                        None => EvalStep::Done(Err(RuntimeError::UnknownSymbol(s))),
+    /// Resumes the evaluation suspended in a frame with the value of its subexpression.
+    fn resume(
+        &mut self,
+        frame: Frame<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        at: Env,
+        winders: &mut Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        handlers: &mut Handlers<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match frame {
+            Frame::Operator(ls) => self.eval_app_procedure(v, ls, at),
+            Frame::Quasiquote(e, mut vs, ls) => {
+                vs.push(v);
+                self.eval_quasiquote(*e, vs, ls, at)
+            }
+            Frame::Argument(mut vs, ls) => {
+                let f = vs.remove(0);
+                vs.push(v);
+                self.eval_args(f, vs, ls, at)
+            }
+            Frame::Default(d) => {
+                let DefaultFrame {
+                    x,
+                    optional,
+                    rest,
+                    args,
+                    body,
+                    at,
+                } = *d;
+                match self.environment.define(at, x, v) {
+                    Ok(()) => self.bind_optional(optional, rest, args, body, at),
+                    Err((x, _)) => EvalStep::Done(Err(RuntimeError::AlreadyDefined(x))),
+                }
+            }
+            Frame::Define(x) => EvalStep::Done(self.define(at, x, v)),
+            Frame::Set(x) => {
+                if v.has_lamb() {
+                    self.escapes += 1;
+                }
+
+                EvalStep::Done(match self.set(at, x, v) {
+                    Ok(()) => Ok(Val::Void()),
+                    Err(_) => Err(RuntimeError::UndefinedVariable(x)),
+                })
+            }
+            // The branches are reversed, so that the first one is the last.
+            Frame::If(mut ls) => EvalStep::Loop(ls.swap_remove(usize::from(!is_false(&v))), at),
+            Frame::Eval() => match v {
+                Val::Quot(e) => EvalStep::Loop(e, at),
+                v @ Val::Pair(_, _) => match quoted_value(&v, self.symbols) {
+                    Some(v) => EvalStep::Done(Ok(v)),
+                    None => match v.into_exp() {
+                        Some(e) => EvalStep::Loop(e, at),
+                        None => EvalStep::Done(Err(RuntimeError::InvalidArguments())),
                     },
                 },
-                Some(e) => match self.eval_loop(e, at) {
-                    Ok(v) => self.eval_app_procedure(v.clone(), ls, at),
-                    Err(err) => EvalStep::Done(Err(err)),
+                v => EvalStep::Done(Ok(v)),
+            },
+            Frame::Output(port) => {
+                let s = self.output.contents().unwrap_or_default();
+                self.output = port;
+                EvalStep::Done(Ok(Val::Str(s.into())))
+            }
+            Frame::Expand(true) => self.macroexpand(v, true, at),
+            Frame::Expand(false) => EvalStep::Done(Ok(v)),
+            Frame::Body(ls, at) => self.eval_body(ls, at),
+            Frame::And(ls) => {
+                if is_false(&v) {
+                    EvalStep::Done(Ok(v))
+                } else {
+                    self.eval_and(ls, at)
+                }
+            }
+            Frame::Or(ls) => match v {
+                Val::Bool(b) => {
+                    if b.into() {
+                        EvalStep::Done(Ok(Val::Bool(Bool::from(true))))
+                    } else {
+                        self.eval_or(ls, at)
+                    }
+                }
+                v => EvalStep::Done(Ok(v)),
+            },
+            Frame::Cond {
+                mut clauses,
+                s,
+                err,
+                at,
+            } => {
+                let clause = clauses.pop();
+                if is_false(&v) {
+                    return self.eval_cond(clauses, at, s, err);
+                }
+
+                // The body is only copied from the clause once its test is true.
+                let mut body = match clause {
+                    Some(Exp::List(body, _)) => Rc::unwrap_or_clone(body),
+                    _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+                };
+
+                body.pop();
+                match (body.pop(), body.pop(), body.pop()) {
+                    (None, _, _) => EvalStep::Done(Ok(v)),
+                    (Some(Exp::Symb(x, _)), Some(f), None) if self.is_keyword(x, "=>", at) => {
+                        EvalStep::Push(Frame::CondArrow(Box::new(v)), f, at)
+                    }
+                    (Some(e1), e2, e3) => {
+                        body.extend(e3);
+                        body.extend(e2);
+                        body.push(e1);
+                        self.eval_body(body, at)
+                    }
+                }
+            }
+            Frame::CondArrow(test) => self.apply_procedure(v, vec![*test], at),
+            Frame::Case(clauses, s) => self.eval_case(v, clauses, at, s),
+            Frame::When(body, expected) => {
+                if is_false(&v) != expected {
+                    self.eval_body(body, at)
+                } else {
+                    EvalStep::Done(Ok(Val::Void()))
+                }
+            }
+            Frame::Let(l) => {
+                let LetFrame {
+                    x,
+                    mut vs,
+                    bs,
+                    body,
+                } = *l;
+                vs.push((x, v));
+                self.bind_let(vs, bs, body, at)
+            }
+            Frame::LetStar { x, bs, body, at } => match self.environment.push(at, 1) {
+                Some(at) => match self.environment.define(at, x, v) {
+                    Ok(()) => self.bind_let_star(bs, body, at),
+                    Err((x, _)) => EvalStep::Done(Err(RuntimeError::AlreadyDefined(x))),
+                },
+                None => EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+            },
+            Frame::Letrec { x, bs, body, at } => match self.environment.define(at, x, v) {
+                Ok(()) => self.bind_letrec(bs, body, at),
+                Err((x, _)) => EvalStep::Done(Err(RuntimeError::AlreadyDefined(x))),
+            },
+            Frame::WindBefore(thunks) => {
+                let (before, thunk, after) = *thunks;
+                let depth = winders.as_ref().map_or(0, |w| w.depth) + 1;
+                *winders = Some(Rc::new(Winder {
+                    before,
+                    after: after.clone(),
+                    parent: winders.take(),
+                    depth,
+                }));
+
+                EvalStep::Call(Frame::WindAfter(Box::new(after)), thunk, Vec::new())
+            }
+            Frame::WindAfter(after) => {
+                *winders = winders.as_ref().and_then(|w| w.parent.clone());
+                EvalStep::Call(Frame::Value(Box::new(v)), *after, Vec::new())
+            }
+            Frame::Wind(thunks, to, v) => self.rewind(winders, thunks, to, *v),
+            Frame::WithHandler() | Frame::Guard(_, _, _) => {
+                *handlers = handlers.as_ref().and_then(|h| h.parent.clone());
+                EvalStep::Done(Ok(v))
+            }
+            Frame::Reraise(err) => EvalStep::Done(Err(*err)),
+            Frame::Continue(to) => {
+                *handlers = to;
+                EvalStep::Done(Ok(v))
+            }
+            Frame::Catch { x, clauses, s, err } => match self.environment.push(at, 1) {
+                Some(at) => match self.environment.define(at, x, v) {
+                    Ok(()) => self.eval_cond(clauses, at, s, Some(err)),
+                    Err((x, _)) => EvalStep::Done(Err(RuntimeError::AlreadyDefined(x))),
                 },
-                None => EvalStep::Done(Err(RuntimeError::MissingProcedure())),
+                None => EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
             },
+            Frame::Value(v) => EvalStep::Done(Ok(*v)),
         }
     }
 
-    fn eval_args(
+    /// Evaluates the remaining reversed expressions unquoted within the template
+    /// of a `quasiquote`, to then build the template with their values.
+    fn eval_quasiquote(
         &mut self,
-        ls: Vec<Exp<Bool, Numb, Symb>>,
+        e: Exp<Bool, Numb, Symb>,
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
         at: Env,
-    ) -> Result<
-        Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
-        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
-    > {
-        ls.into_iter()
-            .rev()
-            .map(|e| self.eval_loop(e, at))
-            .collect()
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        while let Some(u) = ls.pop() {
+            match self.eval_atom(u, at) {
+                Ok(Ok(v)) => vs.push(v),
+                Ok(Err(err)) => return EvalStep::Done(Err(err)),
+                Err((u, span)) => {
+                    return EvalStep::Push(
+                        Frame::Quasiquote(Box::new(e), vs, ls),
+                        Exp::List(Rc::new(u), span),
+                        at,
+                    )
+                }
+            }
+        }
+
+        EvalStep::Done(build(e, 1, self.symbols, &mut vs.into_iter()))
     }
 
-    fn eval_app_procedure(
+    /// Defines a variable, which may overwrite an existing one at the top level.
+    fn define(
         &mut self,
+        at: Env,
+        x: Symb,
         v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
-        ls: Vec<Exp<Bool, Numb, Symb>>,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match self.environment.define(at, x, v) {
+            Ok(()) => Ok(Val::Void()),
+            Err((x, v)) if self.redefine_top_level && at == self.environment.root() => {
+                match self.environment.set(at, &x, v) {
+                    Ok(()) => Ok(Val::Void()),
+                    Err(_) => Err(RuntimeError::AlreadyDefined(x)),
+                }
+            }
+            Err((x, _)) => Err(RuntimeError::AlreadyDefined(x)),
+        }
+    }
+
+    /// Returns the value of a variable, or of the symbol that a macro renamed
+    /// into it if not bound, as when the template refers to a global variable.
+    fn lookup(
+        &self,
+        at: Env,
+        x: Symb,
+    ) -> Option<&Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        match self.environment.get(at, &x) {
+            Some(v) => Some(v),
+            None => {
+                let (x, at) = self.origin(at, x)?;
+                self.lookup(at, x)
+            }
+        }
+    }
+
+    /// Returns the symbol that a macro renamed into the given one, along with
+    /// the environment where the macro is defined, which is the one to look it
+    /// up from, or the given one if not known.
+    fn origin(&self, at: Env, x: Symb) -> Option<(Symb, Env)> {
+        match self.renames.origin(x) {
+            Some((x, defined_at)) if self.environment.within(at, defined_at) => {
+                Some((x, defined_at))
+            }
+            _ => Some((unrenamed(x, self.symbols)?, at)),
+        }
+    }
+
+    /// Returns the name of a symbol that is not bound, which may be a special
+    /// form or an auxiliary keyword, or the one of the symbol that a macro
+    /// renamed into it unless bound where the macro is defined.
+    fn keyword(&self, at: Env, x: Symb) -> Option<&str> {
+        match self.symbols.resolve(x)? {
+            name if !name.starts_with("#{") => Some(name),
+            _ => match self.origin(at, x)? {
+                (x, at) if self.environment.get(at, &x).is_none() => self.keyword(at, x),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the value of a variable at the position it was resolved to, or
+    /// looks it up by name if not there, as when it is not defined yet.
+    fn lookup_local(
+        &self,
+        at: Env,
+        x: Symb,
+        depth: usize,
+        index: usize,
+    ) -> Option<&Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        match self.environment.get_at(at, &x, depth, index) {
+            Some(v) => Some(v),
+            None => self.lookup(at, x),
+        }
+    }
+
+    /// Assigns a value to a variable, or to the symbol that a macro renamed into
+    /// it if not bound.
+    fn set(
+        &mut self,
+        at: Env,
+        x: Symb,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> Result<(), Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        match self.environment.set(at, &x, v) {
+            Err(v) => match self.origin(at, x) {
+                Some((x, at)) => self.set(at, x, v),
+                None => Err(v),
+            },
+            ok => ok,
+        }
+    }
+
+    /// Defines a macro whose expansion is the value of a lambda with the given
+    /// parameters and reversed body, applied to the operands as data.
+    fn define_macro(
+        &mut self,
+        x: Symb,
+        ps: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
         at: Env,
+        s: Symb,
     ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
-        match v {
-            Val::BuiltIn(f) => EvalStep::Done(
-                self.eval_args(ls, at)
-                    .and_then(|vs| f.apply(vs, self.symbols)),
-            ),
-            Val::Lamb(ps, b, at_lambda) => {
-                if ps.len() != ls.len() {
-                    EvalStep::Done(Err(RuntimeError::ArityMismatch()))
-                } else {
-                    match self.eval_args(ls, at) {
-                        Err(err) => EvalStep::Done(Err(err)),
-                        Ok(args) => match self.environment.push(at_lambda, ps.len()) {
-                            None => EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
-                            Some(at) => {
-                                match ps
-                                    .into_iter()
-                                    .zip(args)
-                                    .map(|(x, v)| self.environment.define(at, x, v))
-                                    .collect()
-                                {
-                                    Ok(()) => EvalStep::Loop(b, at),
-                                    Err((x, _)) => {
-                                        EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)))
-                                    }
-                                }
-                            }
-                        },
+        let ps = match Params::parse(ps, self.symbols) {
+            Some(ps) if !body.is_empty() => ps,
+            _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        };
+
+        let f = Val::Lamb(Rc::new((ps, self.body_exp(body))), at);
+        EvalStep::Done(self.define(at, x, Val::Macro(Rc::new(Macro::Procedure(f)))))
+    }
+
+    /// Expands a form given as data once, or until it is no longer the use of
+    /// a macro, to return the expansion as data without evaluating it.
+    fn macroexpand(
+        &mut self,
+        mut v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        repeat: bool,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        loop {
+            let (mut ls, span) = match v.clone().into_exp() {
+                Some(Exp::List(ls, span)) => (Rc::unwrap_or_clone(ls), span),
+                _ => return EvalStep::Done(Ok(v)),
+            };
+
+            let (s, m) = match ls.last() {
+                Some(&Exp::Symb(s, _)) => match self.lookup(at, s) {
+                    Some(Val::Macro(m)) => (s, m.clone()),
+                    _ => return EvalStep::Done(Ok(v)),
+                },
+                _ => return EvalStep::Done(Ok(v)),
+            };
+
+            ls.pop();
+            v = match &*m {
+                Macro::Rules(rules, defined_at) => {
+                    match expand(rules, ls, span, self.symbols, &self.renames, *defined_at) {
+                        Some(e) => Val::quoted(e),
+                        None => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
                     }
                 }
+                Macro::Procedure(f) => {
+                    return EvalStep::Call(Frame::Expand(repeat), f.clone(), data(ls))
+                }
+            };
+
+            if !repeat {
+                return EvalStep::Done(Ok(v));
             }
-            v => EvalStep::Done(Err(RuntimeError::NotAProcedure(v))),
+        }
+    }
+
+    /// Evaluates the reversed body of `let-syntax` or `letrec-syntax` with its
+    /// macros.
+    fn eval_let_syntax(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let bs = match ls.pop().and_then(parse_bindings) {
+            Some(bs) if !ls.is_empty() => bs,
+            _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        };
+
+        let at = match self.environment.push(at, bs.len()) {
+            Some(at) => at,
+            None => return EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+        };
+
+        for (x, e) in bs {
+            let rules = match SyntaxRules::parse(e, self.symbols) {
+                Some(rules) => rules,
+                None => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+            };
+
+            let m = Val::Macro(Rc::new(Macro::Rules(rules, at)));
+            if let Err((x, _)) = self.environment.define(at, x, m) {
+                return EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)));
+            }
+        }
+
+        self.eval_body(ls, at)
+    }
+
+    /// Calls the `after` thunks of the winders that are left and the `before`
+    /// thunks of the winders that are entered, to resume a continuation with
+    /// its winders and a value.
+    fn wind(
+        &mut self,
+        winders: &mut Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        to: Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let thunks = transitions(winders, &to);
+        self.rewind(winders, thunks, to, v)
+    }
+
+    /// Calls the remaining reversed thunks, each with its winders, to then
+    /// return a value with the given winders.
+    fn rewind(
+        &mut self,
+        winders: &mut Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        mut thunks: Vec<(
+            Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+            Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        )>,
+        to: Winders<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match thunks.pop() {
+            Some((thunk, during)) => {
+                *winders = during;
+                EvalStep::Call(Frame::Wind(thunks, to, Box::new(v)), thunk, Vec::new())
+            }
+            None => {
+                *winders = to;
+                EvalStep::Done(Ok(v))
+            }
+        }
+    }
+
+    /// Evaluates a reversed body of expressions, leaving the last one, if any,
+    /// to be evaluated in tail position.
+    fn eval_body(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match ls.pop() {
+            None => EvalStep::Done(Ok(Val::Void())),
+            Some(e) if ls.is_empty() => EvalStep::Loop(e, at),
+            Some(e) => EvalStep::Push(Frame::Body(ls, at), e, at),
+        }
+    }
+
+    fn eval_and(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match ls.pop() {
+            None => EvalStep::Done(Ok(Val::Bool(Bool::from(true)))),
+            Some(e) if ls.is_empty() => EvalStep::Loop(e, at),
+            Some(e) => EvalStep::Push(Frame::And(ls), e, at),
+        }
+    }
+
+    fn eval_or(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match ls.pop() {
+            None => EvalStep::Done(Ok(Val::Bool(Bool::from(false)))),
+            Some(e) if ls.is_empty() => EvalStep::Loop(e, at),
+            Some(e) => EvalStep::Push(Frame::Or(ls), e, at),
+        }
+    }
+
+    /// Evaluates the reversed clauses of a `cond`, or of a `guard` that raises
+    /// the error again if no clause applies.
+    fn eval_cond(
+        &mut self,
+        clauses: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+        err: Option<
+            Box<
+                RuntimeError<
+                    Symb,
+                    Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+                >,
+            >,
+        >,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let body = match clauses.last() {
+            None => return EvalStep::Done(err.map_or(Ok(Val::Void()), |err| Err(*err))),
+            Some(Exp::List(body, _)) => body,
+            Some(_) => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        };
+
+        match body.last() {
+            Some(&Exp::Symb(x, _)) if self.is_keyword(x, "else", at) => {
+                if clauses.len() == 1 && body.len() > 1 {
+                    let mut body = body.to_vec();
+                    body.pop();
+                    self.eval_body(body, at)
+                } else {
+                    EvalStep::Done(Err(RuntimeError::BadFormedExpression(s)))
+                }
+            }
+            // The clause is kept until its test is evaluated.
+            Some(test) => {
+                let test = test.clone();
+                EvalStep::Push(
+                    Frame::Cond {
+                        clauses,
+                        s,
+                        err,
+                        at,
+                    },
+                    test,
+                    at,
+                )
+            }
+            None => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        }
+    }
+
+    fn eval_case(
+        &mut self,
+        key: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        mut clauses: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        while let Some(clause) = clauses.pop() {
+            let mut body = match clause {
+                Exp::List(body, _) if body.len() > 1 => Rc::unwrap_or_clone(body),
+                _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+            };
+
+            match body.pop() {
+                Some(Exp::Symb(x, _)) if self.is_keyword(x, "else", at) => {
+                    return if clauses.is_empty() {
+                        self.eval_body(body, at)
+                    } else {
+                        EvalStep::Done(Err(RuntimeError::BadFormedExpression(s)))
+                    };
+                }
+                Some(Exp::List(data, _)) => {
+                    if data.iter().any(|d| is_datum(&key, d)) {
+                        return self.eval_body(body, at);
+                    }
+                }
+                _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+            }
+        }
+
+        EvalStep::Done(Ok(Val::Void()))
+    }
+
+    /// Evaluates the body of `when` or `unless` if the test is the expected one.
+    fn eval_when(
+        &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+        expected: bool,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match ls.pop() {
+            Some(c) if !ls.is_empty() => EvalStep::Push(Frame::When(ls, expected), c, at),
+            _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        }
+    }
+
+    /// Whether a symbol is the given auxiliary keyword, which it is not when
+    /// bound to a value.
+    fn is_keyword(&self, x: Symb, keyword: &str, at: Env) -> bool {
+        self.environment.get(at, &x).is_none() && self.keyword(at, x) == Some(keyword)
+    }
+
+    fn eval_let(
+        &mut self,
+        bs: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match parse_bindings(bs) {
+            Some(bs) if !body.is_empty() => {
+                self.bind_let(Vec::with_capacity(bs.len()), bs, body, at)
+            }
+            _ => EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        }
+    }
+
+    /// Evaluates the remaining reversed bindings of a `let`, to then bind all
+    /// their values in a new environment.
+    fn bind_let(
+        &mut self,
+        vs: Vec<(
+            Symb,
+            Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        )>,
+        mut bs: Vec<(Symb, Exp<Bool, Numb, Symb>)>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        if let Some((x, e)) = bs.pop() {
+            let frame = LetFrame { x, vs, bs, body };
+            return EvalStep::Push(Frame::Let(Box::new(frame)), e, at);
+        }
+
+        let at = match self.environment.push(at, vs.len()) {
+            Some(at) => at,
+            None => return EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+        };
+
+        for (x, v) in vs {
+            if let Err((x, _)) = self.environment.define(at, x, v) {
+                return EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)));
+            }
+        }
+
+        self.eval_body(body, at)
+    }
+
+    fn eval_let_star(
+        &mut self,
+        bs: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let bs = match parse_bindings(bs) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        };
+
+        if !bs.is_empty() {
+            return self.bind_let_star(bs, body, at);
+        }
+
+        match self.environment.push(at, 0) {
+            Some(at) => self.eval_body(body, at),
+            None => EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+        }
+    }
+
+    fn bind_let_star(
+        &mut self,
+        mut bs: Vec<(Symb, Exp<Bool, Numb, Symb>)>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        // Each binding is in a new nested environment, so that it can shadow the
+        // previous ones and be bound again when resuming a continuation.
+        match bs.pop() {
+            Some((x, e)) => EvalStep::Push(Frame::LetStar { x, bs, body, at }, e, at),
+            None => self.eval_body(body, at),
+        }
+    }
+
+    fn eval_letrec(
+        &mut self,
+        bs: Exp<Bool, Numb, Symb>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let bs = match parse_bindings(bs) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        };
+
+        // The bindings are evaluated in order within the new environment, so
+        // that their lambdas can refer to each other.
+        match self.environment.push(at, bs.len()) {
+            Some(at) => self.bind_letrec(bs, body, at),
+            None => EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+        }
+    }
+
+    fn bind_letrec(
+        &mut self,
+        mut bs: Vec<(Symb, Exp<Bool, Numb, Symb>)>,
+        body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match bs.pop() {
+            Some((x, e)) => EvalStep::Push(Frame::Letrec { x, bs, body, at }, e, at),
+            None => self.eval_body(body, at),
+        }
+    }
+
+    /// Returns a reversed body as a single expression, within a `begin` if it
+    /// has several ones.
+    fn body_exp(&mut self, mut body: Vec<Exp<Bool, Numb, Symb>>) -> Exp<Bool, Numb, Symb> {
+        if body.len() == 1 {
+            body.remove(0)
+        } else {
+            body.push(Exp::Symb(self.symbols.get_or_store("begin"), None));
+            Exp::List(Rc::new(body), None)
+        }
+    }
+
+    fn eval_named_let(
+        &mut self,
+        name: Symb,
+        mut body: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+        s: Symb,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let bs = match body.pop().and_then(parse_bindings) {
+            Some(bs) if !body.is_empty() => bs,
+            _ => return EvalStep::Done(Err(RuntimeError::BadFormedExpression(s))),
+        };
+
+        let b = self.body_exp(body);
+        let (mut xs, es): (Vec<Symb>, Vec<Exp<Bool, Numb, Symb>>) = bs.into_iter().unzip();
+        xs.reverse();
+        let ps = Params {
+            required: xs,
+            optional: Vec::new(),
+            rest: None,
+        };
+
+        // The lambda of the loop is bound in its own environment, and invoked
+        // with the initial values evaluated in the current one.
+        let at_lambda = match self.environment.push(at, 1) {
+            Some(at) => at,
+            None => return EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+        };
+
+        let lambda = Val::Lamb(Rc::new((ps, b)), at_lambda);
+        if let Err((x, _)) = self.environment.define(at_lambda, name, lambda.clone()) {
+            return EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)));
+        }
+
+        self.eval_app_procedure(lambda, es, at)
+    }
+
+    /// Evaluates the remaining reversed arguments of a procedure, to then apply it.
+    fn eval_args(
+        &mut self,
+        f: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        mut ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        while let Some(e) = ls.pop() {
+            // Only the arguments that are lists need a frame to be evaluated.
+            match self.eval_atom(e, at) {
+                Ok(Ok(v)) => vs.push(v),
+                Ok(Err(err)) => return EvalStep::Done(Err(err)),
+                Err((e, span)) => {
+                    // The frame is kept while the argument is evaluated, which
+                    // can be for each level of a recursion, so it does not keep
+                    // the spare capacity of the arguments.
+                    vs.insert(0, f);
+                    vs.shrink_to_fit();
+                    ls.shrink_to_fit();
+                    return EvalStep::Push(
+                        Frame::Argument(vs, ls),
+                        Exp::List(Rc::new(e), span),
+                        at,
+                    );
+                }
+            }
+        }
+
+        self.apply_procedure(f, vs, at)
+    }
+
+    fn eval_app_procedure(
+        &mut self,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        ls: Vec<Exp<Bool, Numb, Symb>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match v {
+            Val::Lamb(ref f, _) if !f.0.accepts(ls.len()) => {
+                EvalStep::Done(Err(RuntimeError::ArityMismatch()))
+            }
+            Val::BuiltIn(ref f) if !f.arity().accepts(ls.len()) => {
+                EvalStep::Done(Err(RuntimeError::ArityMismatch()))
+            }
+            Val::BuiltIn(_) | Val::Lamb(_, _) | Val::Cont(_) => {
+                self.eval_args(v, Vec::with_capacity(ls.len()), ls, at)
+            }
+            v => EvalStep::Done(Err(RuntimeError::NotAProcedure(v))),
+        }
+    }
+
+    /// Applies a procedure to already evaluated arguments, leaving the body of
+    /// lambdas to be evaluated in tail position.
+    fn apply_procedure(
+        &mut self,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match v {
+            Val::BuiltIn(f) => match f.control() {
+                Some(control) => self.apply_control(control, args, at),
+                None => {
+                    // Lambdas passed as arguments escape when a closure keeps them.
+                    if f.is_closure() && args.iter().any(Val::has_lamb) {
+                        self.escapes += 1;
+                    }
+
+                    EvalStep::Done(f.apply(args, self.symbols, &self.output))
+                }
+            },
+            Val::Cont(k) => match <[_; 1]>::try_from(args) {
+                Ok([v]) => EvalStep::Jump(k, v),
+                Err(_) => EvalStep::Done(Err(RuntimeError::ArityMismatch())),
+            },
+            Val::Lamb(f, _) if !f.0.accepts(args.len()) => {
+                EvalStep::Done(Err(RuntimeError::ArityMismatch()))
+            }
+            Val::Lamb(f, at_lambda) => match self.environment.push(at_lambda, f.0.len()) {
+                None => EvalStep::Done(Err(RuntimeError::CouldNotPushEnvironment())),
+                Some(at_invocation) => {
+                    // Lambdas passed as arguments escape when the invocation
+                    // environment does not descend from the current one.
+                    if !self.environment.within(at_invocation, at) && args.iter().any(Val::has_lamb)
+                    {
+                        self.escapes += 1;
+                    }
+
+                    self.bind_params(&f.0, args, f.1.clone(), at_invocation)
+                }
+            },
+            v => EvalStep::Done(Err(RuntimeError::NotAProcedure(v))),
+        }
+    }
+
+    /// Applies a built-in procedure that acts on the continuation, in an
+    /// environment.
+    fn apply_control(
+        &mut self,
+        control: Control,
+        mut args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match (control, args.pop(), args.pop(), args.pop(), args.pop()) {
+            (Control::CallCc(), Some(f), None, None, None) => EvalStep::Capture(f),
+            (Control::DynamicWind(), Some(after), Some(thunk), Some(before), None) => {
+                EvalStep::Call(
+                    Frame::WindBefore(Box::new((before.clone(), thunk, after))),
+                    before,
+                    Vec::new(),
+                )
+            }
+            (Control::RaiseContinuable(), Some(v), None, None, None) => {
+                EvalStep::Raise(RuntimeError::Raised(v), true)
+            }
+            (Control::WithExceptionHandler(), Some(thunk), Some(handler), None, None) => {
+                EvalStep::Handle(handler, thunk)
+            }
+            (Control::Macroexpand(repeat), Some(v), None, None, None) => {
+                self.macroexpand(v, repeat, at)
+            }
+            (Control::WithOutputToString(), Some(thunk), None, None, None) => {
+                let port = mem::replace(&mut self.output, OutputPort::string());
+                EvalStep::Call(Frame::Output(port), thunk, Vec::new())
+            }
+            _ => EvalStep::Done(Err(RuntimeError::ArityMismatch())),
+        }
+    }
+
+    fn bind_params(
+        &mut self,
+        ps: &Params<Bool, Numb, Symb>,
+        mut args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        body: Exp<Bool, Numb, Symb>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        args.reverse();
+        for &x in &ps.required {
+            if let Some(v) = args.pop() {
+                if let Err((x, _)) = self.environment.define(at, x, v) {
+                    return EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)));
+                }
+            }
+        }
+
+        // Only lambdas with optional parameters need their own copy of them.
+        let mut optional = ps.optional.clone();
+        optional.reverse();
+        self.bind_optional(optional, ps.rest, args, body, at)
+    }
+
+    /// Binds the remaining reversed optional parameters and the rest parameter
+    /// to the remaining reversed arguments, to then evaluate the body.
+    fn bind_optional(
+        &mut self,
+        mut optional: Vec<(Symb, Option<Exp<Bool, Numb, Symb>>)>,
+        rest: Option<Symb>,
+        mut args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+        body: Exp<Bool, Numb, Symb>,
+        at: Env,
+    ) -> EvalStep<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        // Default values are evaluated in the invocation environment, so
+        // that they can refer to the previous parameters.
+        while let Some((x, default)) = optional.pop() {
+            let v = match (args.pop(), default) {
+                (Some(v), _) => v,
+                (None, Some(e)) => {
+                    let frame = DefaultFrame {
+                        x,
+                        optional,
+                        rest,
+                        args,
+                        body,
+                        at,
+                    };
+                    return EvalStep::Push(Frame::Default(Box::new(frame)), e, at);
+                }
+                (None, None) => Val::Void(),
+            };
+
+            if let Err((x, _)) = self.environment.define(at, x, v) {
+                return EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)));
+            }
+        }
+
+        if let Some(x) = rest {
+            args.reverse();
+            if let Err((x, _)) = self.environment.define(at, x, Val::list(args)) {
+                return EvalStep::Done(Err(RuntimeError::AlreadyDefined(x)));
+            }
+        }
+
+        EvalStep::Loop(body, at)
+    }
+}
+
+/// Parses a list of `(x e)` bindings, reversed.
+pub(crate) fn parse_bindings<Bool: Clone, Numb: Clone, Symb: Copy>(
+    bs: Exp<Bool, Numb, Symb>,
+) -> Option<Vec<(Symb, Exp<Bool, Numb, Symb>)>> {
+    match bs {
+        Exp::List(bs, _) => bs
+            .iter()
+            .map(|b| match b {
+                Exp::List(b, _) => match &b[..] {
+                    [e, Exp::Symb(x, _)] => Some((*x, e.clone())),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_false<Bool: Into<bool> + Clone, Numb, Symb, Env, BuiltIn>(
+    v: &Val<Bool, Numb, Symb, Env, BuiltIn>,
+) -> bool {
+    matches!(v, Val::Bool(b) if !b.clone().into())
+}
+
+/// Whether a value is the same as the given datum of a `case` clause.
+pub(crate) fn is_datum<Bool: PartialEq, Numb: PartialEq, Symb: PartialEq, Env, BuiltIn>(
+    v: &Val<Bool, Numb, Symb, Env, BuiltIn>,
+    datum: &Exp<Bool, Numb, Symb>,
+) -> bool {
+    match (v, datum) {
+        (Val::Bool(l), Exp::Bool(r, _)) => l == r,
+        (Val::Numb(l), Exp::Numb(r, _)) => l == r,
+        (Val::Str(l), Exp::Str(r, _)) => l == r,
+        (Val::Quot(Exp::Symb(l, _)), Exp::Symb(r, _)) => l == r,
+        (v, Exp::List(ls, _)) => ls.is_empty() && v.is_null(),
+        _ => false,
+    }
+}
+
+/// Returns the value quoted by a `(quote v)` form given as data, which is
+/// evaluated to the value even if no expression represents it, as is the case
+/// of procedures.
+pub(crate) fn quoted_value<Bool, Numb, Symbs: Symbols, Env, BuiltIn>(
+    v: &Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>,
+    symbols: &Symbs,
+) -> Option<Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>>
+where
+    Symbs::Symb: Copy,
+    Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>: Clone,
+{
+    match v {
+        Val::Pair(p, _) => match (&p.0, &p.1) {
+            (Val::Quot(Exp::Symb(s, _)), Val::Pair(q, _))
+                if symbols.resolve(*s) == Some("quote") && q.1.is_null() =>
+            {
+                Some(q.0.clone())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use crate::{interpreter::Value, Backend, Interpreter};
+
+    /// Evaluates a script with each backend, whose depth is too small for
+    /// loops that are not in tail position, and prints its result.
+    fn eval(script: &str) -> Vec<String> {
+        [Backend::TreeWalker, Backend::Bytecode]
+            .into_iter()
+            .map(|backend| {
+                let mut interpreter: Interpreter = Interpreter::new()
+                    .with_backend(backend)
+                    .with_max_depth(1000);
+                match interpreter.eval_str(script) {
+                    Ok(v) => interpreter.print(v),
+                    Err(err) => interpreter.print(err),
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluates a script with each backend bounded by some limits, and
+    /// prints its result.
+    fn eval_bounded(script: &str, bound: impl Fn(Interpreter) -> Interpreter) -> Vec<String> {
+        [Backend::TreeWalker, Backend::Bytecode]
+            .into_iter()
+            .map(|backend| {
+                let mut interpreter = bound(Interpreter::new().with_backend(backend));
+                match interpreter.eval_str(script) {
+                    Ok(v) => interpreter.print(v),
+                    Err(err) => interpreter.print(err),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn begin_in_tail_position() {
+        let script = "
+            (define loop (lambda (n) (if (= n 0) 'done (begin n (loop (- n 1))))))
+            (loop 1000000)";
+        assert_eq!(eval(script), ["'done", "'done"]);
+    }
+
+    #[test]
+    fn and_in_tail_position() {
+        let script = "
+            (define loop (lambda (n) (if (= n 0) 'done (and #t n (loop (- n 1))))))
+            (loop 1000000)";
+        assert_eq!(eval(script), ["'done", "'done"]);
+    }
+
+    #[test]
+    fn or_in_tail_position() {
+        let script = "
+            (define loop (lambda (n) (if (= n 0) 'done (or #f (loop (- n 1))))))
+            (loop 1000000)";
+        assert_eq!(eval(script), ["'done", "'done"]);
+    }
+
+    #[test]
+    fn non_tail_position_exceeds_depth() {
+        let script = "
+            (define loop (lambda (n) (if (= n 0) 'done (begin (loop (- n 1)) 'done))))
+            (loop 1000000)";
+        for result in eval(script) {
+            assert!(result.contains("Depth limit exceeded"), "{result}");
+        }
+    }
+
+    #[test]
+    fn exceeded_depth_can_be_caught() {
+        let script = "
+            (define len (lambda (n) (if (= n 0) 0 (+ 1 (len (- n 1))))))
+            (guard (e ((error-object? e) (error-object-kind e))) (len 1000000))";
+        assert_eq!(
+            eval(script),
+            ["'depth-limit-exceeded", "'depth-limit-exceeded"]
+        );
+    }
+
+    #[test]
+    fn top_level_redefinition_is_a_policy() {
+        let script = "
+            (define x 1)
+            (define f (lambda () x))
+            (define x 2)
+            (define g (lambda () (set! x (+ x 1))))
+            (g)
+            (list x (f))";
+        for result in eval(script) {
+            assert!(result.ends_with("Already defined: x"), "{result}");
+        }
+
+        let redefine = |i: Interpreter| i.with_top_level_redefinition(true);
+        assert_eq!(eval_bounded(script, redefine), ["'(3 3)"; 2]);
+        for result in eval_bounded("(set! y 1)", redefine) {
+            assert!(result.ends_with("Undefined variable: y"), "{result}");
+        }
+    }
+
+    #[test]
+    fn local_definitions_are_not_redefined() {
+        let redefine = |i: Interpreter| i.with_top_level_redefinition(true);
+        for script in [
+            "(define f (lambda () (begin (define y 1) (define y 2) y))) (f)",
+            "(let () (define y 1) (define y 2) y)",
+        ] {
+            for result in eval_bounded(script, redefine) {
+                assert!(result.ends_with("Already defined: y"), "{result}");
+            }
+        }
+    }
+
+    #[test]
+    fn strings_have_escapes_and_procedures() {
+        let script = r#"
+            (list "a\"b\\c\n\t\u{41}" (string-length "h\u{e9}llo") (string-append "ab" "" "cd")
+                  (substring "hello" 1 3) (string=? "a" "a") (string=? "a" "b")
+                  (string->symbol "x") (symbol->string 'y) (number->string 42)
+                  (string->number "17") (string->number "x"))"#;
+        assert_eq!(
+            eval(script),
+            [r#"'("a\"b\\c\n\tA" 5 "abcd" "el" #t #f x "y" "42" 17 #f)"#; 2]
+        );
+        for result in eval(r#"(substring "abc" 2 5)"#) {
+            assert!(result.ends_with("Invalid arguments"), "{result}");
+        }
+    }
+
+    #[test]
+    fn optional_and_rest_parameters() {
+        let script = "
+            (define n 10)
+            (define f (lambda (a #!optional (b (* a 2)) c . rest) (list a b c rest)))
+            (list (f 1) (f 1 5) (f 1 5 6) (f 1 5 6 7 8) ((lambda args args) 1 2)
+                  ((lambda args args)) ((lambda (n #!optional (m (+ n 1))) m) 1))";
+        assert_eq!(
+            eval(script),
+            ["'((1 2 #<void> ()) (1 5 #<void> ()) (1 5 6 ()) (1 5 6 (7 8)) (1 2) () 2)"; 2]
+        );
+        for script in [
+            "((lambda (a #!optional b) a))",
+            "((lambda (a b . rest) rest) 1)",
+            "((lambda (a #!optional b) a) 1 2 3)",
+        ] {
+            for result in eval(script) {
+                assert!(result.ends_with("Arity mismatch"), "{result}");
+            }
+        }
+    }
+
+    #[test]
+    fn local_bindings_are_scoped() {
+        let script = "
+            (define x 1)
+            (define loop 5)
+            (list (let ((x 2) (y x)) y)
+                  (let* ((x 2) (y x)) y)
+                  (let* ((x 2) (x (+ x 1))) x)
+                  (letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                           (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+                    (ev? 100))
+                  (letrec* ((a 1) (b (+ a 1))) b)
+                  (let loop ((i 0) (acc '())) (if (= i 3) acc (loop (+ i 1) (cons i acc))))
+                  (let loop ((y loop)) y)
+                  x
+                  loop)";
+        assert_eq!(eval(script), ["'(1 2 3 #t 2 (2 1 0) 5 1 5)"; 2]);
+        for result in eval("(begin (let ((z 1)) z) z)") {
+            assert!(result.ends_with("Undefined variable: z"), "{result}");
+        }
+    }
+
+    #[test]
+    fn named_let_in_tail_position() {
+        let script = "(let loop ((n 1000000)) (if (= n 0) 'done (loop (- n 1))))";
+        assert_eq!(eval(script), ["'done"; 2]);
+    }
+
+    #[test]
+    fn conditionals_choose_their_clause() {
+        let script = "
+            (list (cond ((car '(5)) => (lambda (v) (* v 2))) (else 0))
+                  (cond (#f 1) (else 2 3))
+                  (cond (7))
+                  (cond (#f 1))
+                  (case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite) (else 'other))
+                  (case 'b ((a) 1) ((b c) 2) (else 3))
+                  (case \"s\" ((\"s\") 1) (else 2))
+                  (case 10 ((1) 'one) (else 'other))
+                  (case 10 ((1) 'one))
+                  (when (> 1 0) 'a 'b)
+                  (when #f 'a)
+                  (unless #f 'c)
+                  (unless #t 'c))";
+        assert_eq!(
+            eval(script),
+            ["'(10 3 7 #<void> composite 2 1 other #<void> b #<void> c #<void>)"; 2]
+        );
+        for result in eval("(cond ((= 1 1) => 5))") {
+            assert!(result.ends_with("Not a procedure: 5"), "{result}");
+        }
+    }
+
+    #[test]
+    fn conditionals_in_tail_position() {
+        let script = "
+            (list (let loop ((n 100000)) (cond ((= n 0) 'done) (else (loop (- n 1)))))
+                  (let loop ((n 100000)) (case n ((0) 'done) (else (loop (- n 1)))))
+                  (let loop ((n 100000)) (if (= n 0) 'done (when #t (loop (- n 1)))))
+                  (let loop ((n 100000)) (if (= n 0) 'done (unless #f (loop (- n 1))))))";
+        assert_eq!(eval(script), ["'(done done done done)"; 2]);
+    }
+
+    #[test]
+    fn quasiquote_levels_and_splicing() {
+        let script = "
+            (define n 3)
+            (define xs '(1 2))
+            (list `(a ,n ,@xs b) `(,@xs) `(1 ,@'() 2) `(a `(b ,(c ,n))) `(a `(b ,,n))
+                  `(a `(b ,@,xs)) `,n `5 (quasiquote (x (unquote n))))";
+        assert_eq!(
+            eval(script),
+            ["'((a 3 1 2 b) (1 2) (1 2) (a (quasiquote (b (unquote (c 3))))) \
+              (a (quasiquote (b (unquote 3)))) (a (quasiquote (b (unquote-splicing (1 2))))) \
+              3 5 (x 3))"; 2]
+        );
+        for result in eval("`,@(list 1)") {
+            assert!(
+                result.ends_with("Bad formed expression: unquote-splicing"),
+                "{result}"
+            );
+        }
+        for script in ["`(a ,@5)", "`(a ,@(cons 1 2))"] {
+            for result in eval(script) {
+                assert!(result.ends_with("Invalid arguments"), "{result}");
+            }
+        }
+    }
+
+    #[test]
+    fn call_cc_is_a_procedure() {
+        let script = "
+            (define cc call/cc)
+            (list (+ 1 (cc (lambda (k) (k 41)))) ((lambda (f) (f (lambda (k) 1))) call/cc))";
+        assert_eq!(eval(script), ["'(42 1)", "'(42 1)"]);
+    }
+
+    #[test]
+    fn dynamic_wind_is_a_procedure() {
+        let script = "
+            (define log '())
+            (define note (lambda (x) (lambda () (set! log (cons x log)))))
+            (define wind dynamic-wind)
+            ((lambda (w) (w (note 'before) (note 'during) (note 'after))) wind)
+            log";
+        assert_eq!(
+            eval(script),
+            ["'(after during before)", "'(after during before)"]
+        );
+    }
+
+    #[test]
+    fn exception_handlers_are_procedures() {
+        let script = "
+            (define handle with-exception-handler)
+            (define raise-again raise-continuable)
+            (handle (lambda (e) (* e 2)) (lambda () (+ 1 (raise-again 20))))";
+        assert_eq!(eval(script), ["41", "41"]);
+    }
+
+    #[test]
+    fn macroexpand_is_a_procedure() {
+        let script = "
+            (define-macro (swap a b) (list 'list b a))
+            (define expand macroexpand-1)
+            (list (expand '(swap 1 2)) ((lambda (f) (f '(swap 3 4))) macroexpand))";
+        assert_eq!(
+            eval(script),
+            ["'((list 2 1) (list 4 3))", "'((list 2 1) (list 4 3))"]
+        );
+    }
+
+    #[test]
+    fn with_output_to_string_is_a_procedure() {
+        let script = "
+            (define capture with-output-to-string)
+            (list (capture (lambda () (display 1))) (capture (lambda () (call/cc display))))";
+        assert_eq!(
+            eval(script),
+            [
+                "'(\"1\" \"#<continuation>\")",
+                "'(\"1\" \"#<continuation>\")"
+            ]
+        );
+    }
+
+    #[test]
+    fn macros_refer_to_the_bindings_where_defined() {
+        let script = "
+            (define-syntax my-inc (syntax-rules () ((_ x) (+ x 1))))
+            (let ((+ -)) (my-inc 5))";
+        assert_eq!(eval(script), ["6", "6"]);
+    }
+
+    #[test]
+    fn macros_refer_to_the_keywords_where_defined() {
+        let script = "
+            (define-syntax my-or
+              (syntax-rules ()
+                ((_) #f)
+                ((_ e) e)
+                ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+            (list (let ((if list)) (my-or #f 7)) (let ((t 5)) (my-or #f t)))";
+        assert_eq!(eval(script), ["'(7 5)", "'(7 5)"]);
+    }
+
+    #[test]
+    fn local_macros_refer_to_the_bindings_where_defined() {
+        let script = "
+            (define f
+              (lambda (y)
+                (let-syntax ((gety (syntax-rules () ((_) y))))
+                  (let ((y 3)) (list y (gety))))))
+            (f 9)";
+        assert_eq!(eval(script), ["'(3 9)", "'(3 9)"]);
+    }
+
+    #[test]
+    fn closures_keep_the_lambdas_passed_to_them() {
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let mut interpreter: Interpreter = Interpreter::new().with_backend(backend);
+            let stash = Rc::new(RefCell::new(Value::Void()));
+            let kept = stash.clone();
+            interpreter.register("stash!", move |v: Value| *kept.borrow_mut() = v);
+            interpreter.register("unstash", move || stash.borrow().clone());
+            let script = "
+                (define mk (lambda (x) (begin (stash! (lambda () x)) 1)))
+                (mk 42)
+                ((unstash))";
+            let r = interpreter.eval_str(script).map(|v| interpreter.print(v));
+            assert_eq!(r.ok().as_deref(), Some("42"));
+        }
+    }
+
+    #[test]
+    fn rooted_values_outlive_the_collections() {
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let mut interpreter: Interpreter = Interpreter::new()
+                .with_backend(backend)
+                .with_gc_threshold(1);
+            let script = "
+                (define app (lambda (f) (f)))
+                ((lambda (x) (lambda () x)) 42)";
+            let f = interpreter.eval_str(script).map(|v| interpreter.root(v));
+            let f = f.ok().unwrap();
+            let churn = "(let loop ((i 0)) (if (< i 100) (loop (+ ((lambda (y) y) i) 1)) i))";
+            assert!(interpreter.eval_str(churn).is_ok());
+            interpreter.collect_garbage();
+            let r = interpreter.call("app", vec![f.get()]);
+            assert_eq!(r.map(|v| interpreter.print(v)).ok().as_deref(), Some("42"));
+            drop(f);
+            interpreter.collect_garbage();
+            assert_eq!(interpreter.gc_stats().live, 1);
+        }
+    }
+
+    #[test]
+    fn closures_root_the_values_they_keep() {
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let mut interpreter: Interpreter = Interpreter::new()
+                .with_backend(backend)
+                .with_gc_threshold(1);
+            let roots = interpreter.roots();
+            let stash = Rc::new(RefCell::new(Vec::new()));
+            let kept = stash.clone();
+            interpreter.register("stash!", move |v: Value| {
+                kept.borrow_mut().push(roots.root(v))
+            });
+            interpreter.register("unstash", move || stash.borrow_mut().remove(0).get());
+            let script = "
+                (define mk (lambda (x) (stash! (lambda () x))))
+                (mk 42)
+                (let loop ((i 0)) (if (< i 100) (loop (+ ((lambda (y) y) i) 1)) i))
+                ((unstash))";
+            let r = interpreter.eval_str(script).map(|v| interpreter.print(v));
+            assert_eq!(r.ok().as_deref(), Some("42"));
+            assert!(interpreter.roots().is_empty());
+        }
+    }
+    #[test]
+    fn long_and_deep_lists_are_dropped() {
+        let script = "
+            (define build (lambda (n l) (if (= n 0) l (build (- n 1) (cons n l)))))
+            (define nest (lambda (n l) (if (= n 0) l (nest (- n 1) (list l)))))
+            (define long (build 1000000 '()))
+            (define deep (nest 1000000 '()))
+            (set! long (length long))
+            (define kept (build 1000000 '()))
+            (list long (pair? (car deep)))";
+        assert_eq!(eval(script), ["'(1000000 #t)", "'(1000000 #t)"]);
+    }
+
+    #[test]
+    fn nested_quotes_are_quote_forms() {
+        let script = "(list (car ''a) (car (cdr ''a)) ''a (car '('b)) (quote 'c))";
+        assert_eq!(
+            eval(script),
+            ["'(quote a (quote a) (quote b) (quote c))"; 2]
+        );
+    }
+
+    #[test]
+    fn dotted_data_are_pairs() {
+        let script = "
+            (list '(1 2 . 3) (cdr (cdr '(1 2 . 3))) (pair? (cdr (cdr '(1 2 . 3))))
+                  '(1 . (2 3)) '((a . b) . c) (quote (d . e)))";
+        assert_eq!(
+            eval(script),
+            ["'((1 2 . 3) 3 #f (1 2 3) ((a . b) . c) (d . e))"; 2]
+        );
+        for result in eval("(length '(1 2 . 3))") {
+            assert!(result.ends_with("Invalid arguments"), "{result}");
+        }
+    }
+
+    #[test]
+    fn pairs_are_evaluated_as_data() {
+        let script = "
+            (list ((eval (list 'quote (lambda () 1))))
+                  (eval (list 'car (list 'quote (cons 1 2))))
+                  ((eval (list 'lambda (cons 'a 'r) 'r)) 1 2 3))";
+        assert_eq!(eval(script), ["'(1 1 (2 3))"; 2]);
+        for result in eval("(eval (cons '+ 1))") {
+            assert!(result.ends_with("Invalid arguments"), "{result}");
+        }
+    }
+
+    #[test]
+    fn large_quoted_lists_are_taken_apart_in_linear_time() {
+        let ns: Vec<String> = (0..200_000).map(|n| n.to_string()).collect();
+        let script = format!(
+            "
+            (define l '({}))
+            (define build (lambda (n l) (if (= n 0) l (build (- n 1) (cons n l)))))
+            (list (length l) (length (reverse l)) (length (eval (list 'quote (build 200000 '())))))",
+            ns.join(" ")
+        );
+        assert_eq!(
+            eval(&script),
+            ["'(200000 200000 200000)", "'(200000 200000 200000)"]
+        );
+    }
+
+    #[test]
+    fn recursive_lambdas_outlive_their_scope() {
+        let script = "
+            (define mk (lambda () (letrec ((f (lambda (n) (if (= n 0) 'done (f (- n 1)))))) f)))
+            (define loop (lambda () (let loop ((i 0)) (if (< i 2) (loop (+ i 1)) loop))))
+            (list ((mk) 3) ((loop) 5))";
+        assert_eq!(
+            eval(script),
+            ["'(done #<procedure>)", "'(done #<procedure>)"]
+        );
+    }
+
+    #[test]
+    fn fuel_is_taken_alike_by_both_backends() {
+        let script = "
+            (define f (lambda (n) (if (= n 0) 'done (f (- n 1)))))
+            (f 100)";
+        assert_eq!(
+            eval_bounded(script, |i| i.with_fuel(405)),
+            ["'done", "'done"]
+        );
+        assert_eq!(
+            eval_bounded(script, |i| i.with_fuel(404)),
+            [
+                "<input>:2:39: Fuel exhausted",
+                "<input>:2:39: Fuel exhausted"
+            ]
+        );
+    }
+
+    #[test]
+    fn guard_does_not_catch_the_limits() {
+        let script = "
+            (define spin (lambda () (spin)))
+            (guard (e (#t 'caught))
+              (with-exception-handler (lambda (e) 'handled) spin))";
+        let fueled = eval_bounded(script, |i| i.with_fuel(1000));
+        assert_eq!(
+            fueled,
+            [
+                "<input>:2:37: Fuel exhausted",
+                "<input>:2:37: Fuel exhausted"
+            ]
+        );
+        let timed = eval_bounded(script, |i| i.with_timeout(Duration::from_millis(10)));
+        assert!(
+            timed.iter().all(|r| r.ends_with("Deadline exceeded")),
+            "{timed:?}"
+        );
+        let cancel = Arc::new(AtomicBool::new(true));
+        let cancelled = eval_bounded(script, |i| i.with_cancellation(cancel.clone()));
+        assert!(
+            cancelled.iter().all(|r| r.ends_with("Cancelled")),
+            "{cancelled:?}"
+        );
+    }
+
+    #[test]
+    fn cancellation_stops_until_the_flag_is_unset() {
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let cancel = Arc::new(AtomicBool::new(false));
+            let mut interpreter: Interpreter = Interpreter::new()
+                .with_backend(backend)
+                .with_cancellation(cancel.clone());
+            let flag = cancel.clone();
+            interpreter.register("cancel!", move || flag.store(true, Ordering::Relaxed));
+            let script = "
+                (define n 0)
+                (define count
+                  (lambda () (begin (set! n (+ n 1)) (if (= n 5000) (cancel!) n) (count))))
+                (count)";
+            let r = interpreter
+                .eval_str(script)
+                .map_err(|err| interpreter.print(err));
+            assert!(
+                r.as_ref().is_err_and(|err| err.ends_with("Cancelled")),
+                "{r:?}"
+            );
+            let n = interpreter.get_global("n").map(|v| interpreter.print(v));
+            assert!(n
+                .and_then(|n| n.parse::<usize>().ok())
+                .is_some_and(|n| n >= 5000));
+            assert!(interpreter.eval_str("(+ 1 2)").is_err());
+            cancel.store(false, Ordering::Relaxed);
+            let r = interpreter
+                .eval_str("(+ 1 2)")
+                .map(|v| interpreter.print(v));
+            assert_eq!(r.ok().as_deref(), Some("3"));
         }
     }
 }
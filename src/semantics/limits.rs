@@ -0,0 +1,82 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use super::err::RuntimeError;
+
+/// The number of steps between checks of the deadline and the cancellation
+/// flag, which are more expensive than counting the fuel.
+const CHECK_INTERVAL: usize = 1024;
+
+/// Bounds on the evaluation of untrusted scripts, which is stopped once it
+/// takes more steps than its fuel, reaches a deadline or is cancelled from
+/// another thread, with an error that exception handlers cannot catch.
+///
+/// The `Evaluator` and the `Vm` take a step for each list that is evaluated,
+/// as an application or a special form, so that the same fuel bounds them alike.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    fuel: Option<usize>,
+    deadline: Option<Instant>,
+    cancel: Option<Arc<AtomicBool>>,
+    steps: usize,
+}
+
+impl Limits {
+    /// Creates `Limits` that do not bound the evaluation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of steps that the evaluation can take.
+    pub fn with_fuel(mut self, fuel: usize) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Sets the instant after which the evaluation fails.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets a flag that cancels the evaluation once set, until it is unset.
+    pub fn with_cancellation(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Returns the number of steps that the evaluation can still take, if bounded.
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel
+    }
+
+    /// Takes a step of the evaluation, unless it runs out of fuel, reaches the
+    /// deadline or is cancelled, where the last two are checked every few steps.
+    pub(crate) fn step<Symb, Val>(&mut self) -> Result<(), RuntimeError<Symb, Val>> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(RuntimeError::FuelExhausted());
+            }
+
+            *fuel -= 1;
+        }
+
+        if self.steps.is_multiple_of(CHECK_INTERVAL) {
+            if matches!(&self.cancel, Some(cancel) if cancel.load(Ordering::Relaxed)) {
+                return Err(RuntimeError::Cancelled());
+            }
+
+            if matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+                return Err(RuntimeError::DeadlineExceeded());
+            }
+        }
+
+        self.steps = self.steps.wrapping_add(1);
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc};
+
+use crate::syntax::{exp::Exp, span::Span, symb::Symbols};
+
+use super::{syntax_rules::SyntaxRules, val::Val};
+
+/// The auxiliary syntax that is recognized by its name wherever it appears,
+/// which is not renamed when introduced by the template of a macro.
+pub(crate) const KEYWORDS: &[&str] = &[
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "syntax-rules",
+    ".",
+    "#!optional",
+];
+
+/// A macro, which rewrites the forms whose operator it is into expressions to
+/// be evaluated in their place.
+#[derive(Clone)]
+pub enum Macro<Bool, Numb, Symb, Env, BuiltIn> {
+    /// A hygienic macro defined with `syntax-rules`, with the environment where
+    /// it is defined, which is the root one for the virtual machine, as its
+    /// local macros are only known when compiling.
+    Rules(SyntaxRules<Bool, Numb, Symb>, Env),
+    /// A non hygienic macro defined with `define-macro` or `defmacro`, as a
+    /// procedure that returns the expression from the unevaluated operands.
+    Procedure(Val<Bool, Numb, Symb, Env, BuiltIn>),
+}
+
+/// The symbols that the expansions of macros renamed, which are shared by the
+/// clones, each with the symbol of the template that it renames and where the
+/// macro is defined, so that a symbol that the expansion does not bind refers
+/// to what the macro sees there.
+pub struct Renames<Symb, At> {
+    renames: Rc<RefCell<Origins<Symb, At>>>,
+}
+
+struct Origins<Symb, At> {
+    count: usize,
+    origins: HashMap<Symb, (Symb, At)>,
+}
+
+impl<Symb: Eq + Hash + Copy, At: Clone> Renames<Symb, At> {
+    /// Creates `Renames` without any renamed symbol.
+    pub fn new() -> Self {
+        Self {
+            renames: Rc::new(RefCell::new(Origins {
+                count: 0,
+                origins: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns the symbol that a macro renamed into the given one and where
+    /// the macro is defined, if known.
+    pub(crate) fn origin(&self, x: Symb) -> Option<(Symb, At)> {
+        self.renames.borrow().origins.get(&x).cloned()
+    }
+}
+
+impl<Symb: Eq + Hash + Copy, At: Clone> Default for Renames<Symb, At> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Symb, At> Clone for Renames<Symb, At> {
+    fn clone(&self) -> Self {
+        Self {
+            renames: self.renames.clone(),
+        }
+    }
+}
+
+/// Rewrites the reversed operands of a form with the rules of a macro defined
+/// at the given place, renaming the symbols introduced by its template other
+/// than auxiliary syntax, so that the variables they bind do not capture those
+/// of the form, and the variables of the form do not capture them.
+pub(crate) fn expand<Bool: PartialEq + Clone, Numb: PartialEq + Clone, Symbs: Symbols, At: Clone>(
+    rules: &SyntaxRules<Bool, Numb, Symbs::Symb>,
+    ls: Vec<Exp<Bool, Numb, Symbs::Symb>>,
+    span: Option<Span>,
+    symbols: &mut Symbs,
+    renames: &Renames<Symbs::Symb, At>,
+    at: At,
+) -> Option<Exp<Bool, Numb, Symbs::Symb>>
+where
+    Symbs::Symb: Eq + Hash + Copy,
+{
+    let mut renames = renames.renames.borrow_mut();
+    let Origins { count, origins } = &mut *renames;
+    let mut renamed = HashMap::new();
+    let mut rename = |x: Symbs::Symb| {
+        *renamed.entry(x).or_insert_with(|| {
+            let name = match symbols.resolve(x) {
+                Some(name) if !KEYWORDS.contains(&name) && !rules.literals.contains(&x) => {
+                    name.to_string()
+                }
+                _ => return x,
+            };
+
+            // As symbols cannot contain spaces, the fresh symbol cannot be
+            // read, and it cannot be one stored before.
+            loop {
+                *count += 1;
+                let fresh = format!("#{{{name} {count}}}");
+                if symbols.get(&fresh).is_none() {
+                    let fresh = symbols.get_or_store(&fresh);
+                    origins.insert(fresh, (x, at.clone()));
+                    return fresh;
+                }
+            }
+        })
+    };
+
+    rules.expand(ls, span, &mut rename)
+}
+
+/// Returns the symbol that a macro renamed into the given one, if any, as
+/// written in its name.
+pub(crate) fn unrenamed<Symbs: Symbols>(x: Symbs::Symb, symbols: &Symbs) -> Option<Symbs::Symb> {
+    let name = symbols.resolve(x)?.strip_prefix("#{")?.strip_suffix('}')?;
+    symbols.get(name.rsplit_once(' ')?.0)
+}
+
+/// Returns the reversed operands of a form as data in order, as the arguments
+/// of a procedural macro.
+pub(crate) fn data<Bool: Clone, Numb: Clone, Symb: Clone, Env, BuiltIn>(
+    ls: Vec<Exp<Bool, Numb, Symb>>,
+) -> Vec<Val<Bool, Numb, Symb, Env, BuiltIn>> {
+    ls.into_iter().rev().map(Val::quoted).collect()
+}
@@ -0,0 +1,95 @@
+use crate::syntax::{exp::Exp, symb::Symbols};
+
+/// The parameters of a lambda, as in `(a #!optional (b 0) . rest)`.
+#[derive(Clone)]
+pub struct Params<Bool, Numb, Symb> {
+    /// The parameters that must be given.
+    pub required: Vec<Symb>,
+    /// The parameters that can be omitted, with the expression of their
+    /// default value, if any, to be evaluated in the invocation environment.
+    pub optional: Vec<(Symb, Option<Exp<Bool, Numb, Symb>>)>,
+    /// The parameter that collects the remaining arguments as a list.
+    pub rest: Option<Symb>,
+}
+
+impl<Bool: Clone, Numb: Clone, Symb: Copy> Params<Bool, Numb, Symb> {
+    /// Parses the parameters of a lambda from either a list, which can contain
+    /// `#!optional` and `.` markers or be a dotted list, or a single symbol that
+    /// collects all the arguments.
+    pub fn parse<Symbs: Symbols<Symb = Symb>>(
+        exp: Exp<Bool, Numb, Symb>,
+        symbols: &Symbs,
+    ) -> Option<Self> {
+        let ls = match exp {
+            Exp::Symb(s, _) => {
+                return Some(Self {
+                    required: Vec::new(),
+                    optional: Vec::new(),
+                    rest: Some(s),
+                })
+            }
+            Exp::List(ls, _) => ls,
+            Exp::Dotted(ls, rest, _) => {
+                return match (Self::parse(Exp::List(ls, None), symbols)?, *rest) {
+                    (params @ Self { rest: None, .. }, Exp::Symb(s, _)) => Some(Self {
+                        rest: Some(s),
+                        ..params
+                    }),
+                    _ => None,
+                }
+            }
+            _ => return None,
+        };
+
+        let mut params = Self {
+            required: Vec::new(),
+            optional: Vec::new(),
+            rest: None,
+        };
+
+        let mut optional = false;
+        let mut ls = ls.iter().rev().cloned();
+        while let Some(e) = ls.next() {
+            match e {
+                Exp::Symb(s, _) => match symbols.resolve(s) {
+                    Some("#!optional") if !optional => optional = true,
+                    Some(".") => {
+                        return match (ls.next(), ls.next()) {
+                            (Some(Exp::Symb(s, _)), None) => {
+                                params.rest = Some(s);
+                                Some(params)
+                            }
+                            _ => None,
+                        }
+                    }
+                    Some("#!optional") => return None,
+                    _ if optional => params.optional.push((s, None)),
+                    _ => params.required.push(s),
+                },
+                Exp::List(ls, _) if optional => match &ls[..] {
+                    [e, Exp::Symb(s, _)] => params.optional.push((*s, Some(e.clone()))),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+
+        Some(params)
+    }
+
+    /// Whether the parameters accept the given number of arguments.
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.required.len()
+            && (self.rest.is_some() || n <= self.required.len() + self.optional.len())
+    }
+
+    /// Returns the number of variables that the parameters bind.
+    pub fn len(&self) -> usize {
+        self.required.len() + self.optional.len() + usize::from(self.rest.is_some())
+    }
+
+    /// Whether the parameters bind no variable.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+/// An output port where scripts write with `display`, `write` and the like,
+/// which is shared by its clones.
+#[derive(Clone)]
+pub struct OutputPort(Rc<RefCell<Sink>>);
+
+/// The destination of the output of a port.
+enum Sink {
+    Writer(Box<dyn Write>),
+    String(String),
+}
+
+impl OutputPort {
+    /// Creates a port that writes to the given writer.
+    pub fn new(writer: impl Write + 'static) -> Self {
+        Self(Rc::new(RefCell::new(Sink::Writer(Box::new(writer)))))
+    }
+
+    /// Creates a port that writes to the standard output.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// Creates a port that accumulates its output in a string.
+    pub fn string() -> Self {
+        Self(Rc::new(RefCell::new(Sink::String(String::new()))))
+    }
+
+    /// Returns the output accumulated so far by a string port, or `None` if
+    /// the port writes to a writer.
+    pub fn contents(&self) -> Option<String> {
+        match &*self.0.borrow() {
+            Sink::Writer(_) => None,
+            Sink::String(s) => Some(s.clone()),
+        }
+    }
+
+    /// Writes a string to the port.
+    pub fn write_str(&self, s: &str) -> io::Result<()> {
+        match &mut *self.0.borrow_mut() {
+            Sink::Writer(w) => w.write_all(s.as_bytes()),
+            Sink::String(buffer) => {
+                buffer.push_str(s);
+                Ok(())
+            }
+        }
+    }
+
+    /// Flushes the output written to the port so far.
+    pub fn flush(&self) -> io::Result<()> {
+        match &mut *self.0.borrow_mut() {
+            Sink::Writer(w) => w.flush(),
+            Sink::String(_) => Ok(()),
+        }
+    }
+
+    /// Whether both ports are the same, rather than writing to the same
+    /// destination.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Default for OutputPort {
+    fn default() -> Self {
+        Self::stdout()
+    }
+}
@@ -2,15 +2,24 @@ use std::{
     fmt::Display,
     iter::{Product, Sum},
     ops::Sub,
+    rc::Rc,
+    str::FromStr,
 };
 
 use crate::syntax::{
+    exp::Exp,
     print::{PrintError, PrintWithSymbols},
     symb::Symbols,
 };
 
 use super::{
-    built_in::EvalBuiltIn, env::Environments, err::RuntimeError, res::EvalResult, val::Val,
+    built_in::{Arity, Control, EvalBuiltIn},
+    condition::Condition,
+    env::Environments,
+    err::RuntimeError,
+    port::OutputPort,
+    res::EvalResult,
+    val::Val,
 };
 
 impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb, Symb, Env, Symbs> {
@@ -20,9 +29,19 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
         symbols: &mut Symbs,
     ) -> Result<(), &'a str>
     where
-        Bool: From<bool> + Into<bool> + PartialEq,
-        Numb: Sum + Product + Sub<Output = Numb> + PartialEq + PartialOrd + Display,
+        Bool: From<bool> + Into<bool> + PartialEq + Clone,
+        Numb: Sum
+            + Product
+            + Sub<Output = Numb>
+            + PartialEq
+            + PartialOrd
+            + Display
+            + TryFrom<usize>
+            + TryInto<usize>
+            + FromStr
+            + Clone,
         Symb: Copy + PartialEq,
+        Env: Clone,
     {
         for (x, v) in [
             ("true", Val::Bool(Bool::from(true))),
@@ -37,10 +56,146 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
             ("<=", Val::BuiltIn(EvalBuiltIn::new(Self::lte))),
             ("not", Val::BuiltIn(EvalBuiltIn::new(Self::not))),
             ("eq?", Val::BuiltIn(EvalBuiltIn::new(Self::eq))),
-            ("newline", Val::BuiltIn(EvalBuiltIn::new(Self::newline))),
-            ("display", Val::BuiltIn(EvalBuiltIn::new(Self::display))),
+            (
+                "newline",
+                Val::BuiltIn(EvalBuiltIn::with_port(Self::newline)),
+            ),
+            (
+                "display",
+                Val::BuiltIn(EvalBuiltIn::with_port(Self::display)),
+            ),
+            ("write", Val::BuiltIn(EvalBuiltIn::with_port(Self::write))),
+            (
+                "write-string",
+                Val::BuiltIn(EvalBuiltIn::with_port(Self::write_string)),
+            ),
+            (
+                "flush-output",
+                Val::BuiltIn(EvalBuiltIn::with_port(Self::flush_output)),
+            ),
+            (
+                "current-output-port",
+                Val::BuiltIn(EvalBuiltIn::with_port(Self::current_output_port)),
+            ),
+            ("cons", Val::BuiltIn(EvalBuiltIn::new(Self::cons))),
+            ("car", Val::BuiltIn(EvalBuiltIn::new(Self::car))),
+            ("cdr", Val::BuiltIn(EvalBuiltIn::new(Self::cdr))),
+            ("list", Val::BuiltIn(EvalBuiltIn::new(Self::list))),
+            ("null?", Val::BuiltIn(EvalBuiltIn::new(Self::is_null))),
+            ("pair?", Val::BuiltIn(EvalBuiltIn::new(Self::is_pair))),
+            ("length", Val::BuiltIn(EvalBuiltIn::new(Self::length))),
+            ("append", Val::BuiltIn(EvalBuiltIn::new(Self::append))),
+            ("reverse", Val::BuiltIn(EvalBuiltIn::new(Self::reverse))),
+            (
+                "string-length",
+                Val::BuiltIn(EvalBuiltIn::new(Self::string_length)),
+            ),
+            (
+                "string-append",
+                Val::BuiltIn(EvalBuiltIn::new(Self::string_append)),
+            ),
+            ("substring", Val::BuiltIn(EvalBuiltIn::new(Self::substring))),
+            ("string=?", Val::BuiltIn(EvalBuiltIn::new(Self::string_eq))),
+            (
+                "string->symbol",
+                Val::BuiltIn(EvalBuiltIn::new(Self::string_to_symbol)),
+            ),
+            (
+                "symbol->string",
+                Val::BuiltIn(EvalBuiltIn::new(Self::symbol_to_string)),
+            ),
+            (
+                "number->string",
+                Val::BuiltIn(EvalBuiltIn::new(Self::number_to_string)),
+            ),
+            (
+                "string->number",
+                Val::BuiltIn(EvalBuiltIn::new(Self::string_to_number)),
+            ),
+            ("error", Val::BuiltIn(EvalBuiltIn::new(Self::error))),
+            ("raise", Val::BuiltIn(EvalBuiltIn::new(Self::raise))),
+            (
+                "error-object?",
+                Val::BuiltIn(EvalBuiltIn::new(Self::is_error_object)),
+            ),
+            (
+                "error-object-kind",
+                Val::BuiltIn(EvalBuiltIn::new(Self::error_object_kind)),
+            ),
+            (
+                "error-object-message",
+                Val::BuiltIn(EvalBuiltIn::new(Self::error_object_message)),
+            ),
+            (
+                "error-object-irritants",
+                Val::BuiltIn(EvalBuiltIn::new(Self::error_object_irritants)),
+            ),
+            (
+                "call/cc",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "call/cc",
+                    Arity::Exactly(1),
+                    Control::CallCc(),
+                )),
+            ),
+            (
+                "call-with-current-continuation",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "call-with-current-continuation",
+                    Arity::Exactly(1),
+                    Control::CallCc(),
+                )),
+            ),
+            (
+                "dynamic-wind",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "dynamic-wind",
+                    Arity::Exactly(3),
+                    Control::DynamicWind(),
+                )),
+            ),
+            (
+                "raise-continuable",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "raise-continuable",
+                    Arity::Exactly(1),
+                    Control::RaiseContinuable(),
+                )),
+            ),
+            (
+                "with-exception-handler",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "with-exception-handler",
+                    Arity::Exactly(2),
+                    Control::WithExceptionHandler(),
+                )),
+            ),
+            (
+                "macroexpand",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "macroexpand",
+                    Arity::Exactly(1),
+                    Control::Macroexpand(true),
+                )),
+            ),
+            (
+                "macroexpand-1",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "macroexpand-1",
+                    Arity::Exactly(1),
+                    Control::Macroexpand(false),
+                )),
+            ),
+            (
+                "with-output-to-string",
+                Val::BuiltIn(EvalBuiltIn::with_control(
+                    "with-output-to-string",
+                    Arity::Exactly(1),
+                    Control::WithOutputToString(),
+                )),
+            ),
         ] {
-            if let Err(_) = env.define(env.root(), symbols.get_or_store(x), v) {
+            if env.define(env.root(), symbols.get_or_store(x), v).is_err() {
                 return Err(x);
             }
         }
@@ -50,7 +205,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn add(
         vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Numb: Sum,
@@ -63,7 +218,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn mul(
         vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Numb: Product,
@@ -76,7 +231,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn sub(
         vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Numb: Sub<Output = Numb>,
@@ -95,7 +250,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn et(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: From<bool>,
@@ -112,7 +267,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn gt(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: From<bool>,
@@ -129,7 +284,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn lt(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: From<bool>,
@@ -146,7 +301,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn gte(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: From<bool>,
@@ -163,7 +318,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn lte(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: From<bool>,
@@ -180,7 +335,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn not(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: From<bool> + Into<bool>,
@@ -196,7 +351,7 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
 
     fn eq(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        _symbols: &mut Symbs,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self>
     where
         Bool: PartialEq + From<bool>,
@@ -208,11 +363,23 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
                 (Val::Void(), Val::Void()) => Ok(Val::Bool(Bool::from(true))),
                 (Val::Bool(l), Val::Bool(r)) => Ok(Val::Bool(Bool::from(l == r))),
                 (Val::Numb(l), Val::Numb(r)) => Ok(Val::Bool(Bool::from(l == r))),
+                (Val::Str(l), Val::Str(r)) => Ok(Val::Bool(Bool::from(l == r))),
                 (Val::Quot(l), Val::Quot(r)) => Ok(Val::Bool(Bool::from(l == r))),
+                (Val::Pair(l, _), Val::Pair(r, _)) => Ok(Val::Bool(Bool::from(Rc::ptr_eq(&l, &r)))),
+                (Val::Cond(l), Val::Cond(r)) => Ok(Val::Bool(Bool::from(Rc::ptr_eq(&l, &r)))),
+                (Val::Port(l), Val::Port(r)) => Ok(Val::Bool(Bool::from(l.ptr_eq(&r)))),
                 (Val::BuiltIn(_), _) => Err(RuntimeError::InvalidArguments()),
                 (_, Val::BuiltIn(_)) => Err(RuntimeError::InvalidArguments()),
-                (Val::Lamb(_, _, _), _) => Err(RuntimeError::InvalidArguments()),
-                (_, Val::Lamb(_, _, _)) => Err(RuntimeError::InvalidArguments()),
+                (Val::Lamb(_, _), _) => Err(RuntimeError::InvalidArguments()),
+                (_, Val::Lamb(_, _)) => Err(RuntimeError::InvalidArguments()),
+                (Val::Cont(_), _) => Err(RuntimeError::InvalidArguments()),
+                (_, Val::Cont(_)) => Err(RuntimeError::InvalidArguments()),
+                (Val::Closure(_, _), _) => Err(RuntimeError::InvalidArguments()),
+                (_, Val::Closure(_, _)) => Err(RuntimeError::InvalidArguments()),
+                (Val::VmCont(_), _) => Err(RuntimeError::InvalidArguments()),
+                (_, Val::VmCont(_)) => Err(RuntimeError::InvalidArguments()),
+                (Val::Macro(_), _) => Err(RuntimeError::InvalidArguments()),
+                (_, Val::Macro(_)) => Err(RuntimeError::InvalidArguments()),
                 _ => Ok(Val::Bool(Bool::from(false))),
             },
             _ => Err(RuntimeError::ArityMismatch()),
@@ -220,36 +387,509 @@ impl<Bool, Numb, Symb, Env, Symbs: Symbols<Symb = Symb>> EvalBuiltIn<Bool, Numb,
     }
 
     fn newline(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+        output: &OutputPort,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match (vs.pop(), vs.pop()) {
+            (None, None) => Self::write_to(output, "\n"),
+            (Some(port), None) => Self::write_to(&Self::port(port)?, "\n"),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    /// Writes a value as `write` would, except for strings, which are written
+    /// as is.
+    fn display(
         vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
-        _symbols: &Symbs,
+        symbols: &mut Symbs,
+        output: &OutputPort,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Into<bool> + Clone,
+        Numb: Display + Clone,
+        Symb: Copy,
+        Env: Clone,
+        Symbs: Symbols<Symb = Symb>,
+    {
+        match Self::port_args(vs, output)? {
+            (Val::Str(s), port) => Self::write_to(&port, &s),
+            (v, port) => Self::write_to(&port, &Self::print(v, symbols)?),
+        }
+    }
+
+    fn write(
+        vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        symbols: &mut Symbs,
+        output: &OutputPort,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Into<bool> + Clone,
+        Numb: Display + Clone,
+        Symb: Copy,
+        Env: Clone,
+        Symbs: Symbols<Symb = Symb>,
+    {
+        let (v, port) = Self::port_args(vs, output)?;
+        Self::write_to(&port, &Self::print(v, symbols)?)
+    }
+
+    fn write_string(
+        vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+        output: &OutputPort,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match Self::port_args(vs, output)? {
+            (Val::Str(s), port) => Self::write_to(&port, &s),
+            _ => Err(RuntimeError::InvalidArguments()),
+        }
+    }
+
+    fn flush_output(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+        output: &OutputPort,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        let port = match (vs.pop(), vs.pop()) {
+            (None, None) => output.clone(),
+            (Some(port), None) => Self::port(port)?,
+            _ => return Err(RuntimeError::ArityMismatch()),
+        };
+
+        match port.flush() {
+            Ok(()) => Ok(Val::Void()),
+            Err(err) => Err(RuntimeError::OutputFailed(err.to_string())),
+        }
+    }
+
+    fn current_output_port(
+        vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+        output: &OutputPort,
     ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
         if vs.is_empty() {
-            println!("");
-            Ok(Val::Void())
+            Ok(Val::Port(output.clone()))
         } else {
             Err(RuntimeError::ArityMismatch())
         }
     }
 
-    fn display(
+    /// Returns the value to write and the port to write it to, which is the
+    /// current output port unless given after the value.
+    fn port_args(
         mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        output: &OutputPort,
+    ) -> Result<
+        (Val<Bool, Numb, Symb, Env, Self>, OutputPort),
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, Self>>,
+    > {
+        match (vs.pop(), vs.pop(), vs.pop()) {
+            (Some(v), None, None) => Ok((v, output.clone())),
+            (Some(port), Some(v), None) => Ok((v, Self::port(port)?)),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn port(
+        v: Val<Bool, Numb, Symb, Env, Self>,
+    ) -> Result<OutputPort, RuntimeError<Symb, Val<Bool, Numb, Symb, Env, Self>>> {
+        match v {
+            Val::Port(port) => Ok(port),
+            _ => Err(RuntimeError::InvalidArguments()),
+        }
+    }
+
+    fn print(
+        v: Val<Bool, Numb, Symb, Env, Self>,
         symbols: &Symbs,
-    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    ) -> Result<String, RuntimeError<Symb, Val<Bool, Numb, Symb, Env, Self>>>
     where
-        Bool: Into<bool>,
-        Numb: Display,
+        Bool: Into<bool> + Clone,
+        Numb: Display + Clone,
         Symb: Copy,
+        Env: Clone,
         Symbs: Symbols<Symb = Symb>,
+    {
+        v.print_with(symbols).map_err(|err| match err {
+            PrintError::UnknownSymbol(s) => RuntimeError::UnknownSymbol(s),
+        })
+    }
+
+    fn write_to(output: &OutputPort, s: &str) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match output.write_str(s) {
+            Ok(()) => Ok(Val::Void()),
+            Err(err) => Err(RuntimeError::OutputFailed(err.to_string())),
+        }
+    }
+
+    fn cons(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match (vs.pop(), vs.pop(), vs.pop()) {
+            (Some(r), Some(l), None) => Ok(Val::cons(l, r)),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn car(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+        Env: Clone,
     {
         match (vs.pop(), vs.pop()) {
-            (Some(v), None) => match v.print_with(symbols) {
-                Ok(s) => {
-                    print!("{s}");
-                    Ok(Val::Void())
+            (Some(v), None) => match v.uncons() {
+                Ok((car, _)) => Ok(car),
+                Err(_) => Err(RuntimeError::InvalidArguments()),
+            },
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn cdr(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+        Env: Clone,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => match v.uncons() {
+                Ok((_, cdr)) => Ok(cdr),
+                Err(_) => Err(RuntimeError::InvalidArguments()),
+            },
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn list(
+        vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        Ok(Val::list(vs))
+    }
+
+    fn is_null(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: From<bool>,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => Ok(Val::Bool(Bool::from(v.is_null()))),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn is_pair(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: From<bool>,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => Ok(Val::Bool(Bool::from(v.is_pair()))),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn length(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Clone,
+        Numb: Clone + TryFrom<usize>,
+        Symb: Clone,
+        Env: Clone,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => match v.into_vec().ok().map(|vs| Numb::try_from(vs.len())) {
+                Some(Ok(n)) => Ok(Val::Numb(n)),
+                _ => Err(RuntimeError::InvalidArguments()),
+            },
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn append(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+        Env: Clone,
+    {
+        let mut result = vs.pop().unwrap_or_else(Val::null);
+        while let Some(v) = vs.pop() {
+            match v.into_vec() {
+                Ok(ls) => {
+                    result = ls
+                        .into_iter()
+                        .rev()
+                        .fold(result, |cdr, car| Val::cons(car, cdr))
                 }
-                Err(PrintError::UnknownSymbol(s)) => Err(RuntimeError::UnknownSymbol(s)),
+                Err(_) => return Err(RuntimeError::InvalidArguments()),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn reverse(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+        Env: Clone,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => match v.into_vec() {
+                Ok(ls) => Ok(ls
+                    .into_iter()
+                    .fold(Val::null(), |cdr, car| Val::cons(car, cdr))),
+                Err(_) => Err(RuntimeError::InvalidArguments()),
             },
             _ => Err(RuntimeError::ArityMismatch()),
         }
     }
+
+    fn string_length(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Numb: TryFrom<usize>,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Str(s)), None) => match Numb::try_from(s.chars().count()) {
+                Ok(n) => Ok(Val::Numb(n)),
+                Err(_) => Err(RuntimeError::InvalidArguments()),
+            },
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn string_append(
+        vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match vs
+            .into_iter()
+            .map(Val::str)
+            .collect::<Option<Vec<Rc<str>>>>()
+        {
+            None => Err(RuntimeError::InvalidArguments()),
+            Some(ss) => Ok(Val::Str(ss.concat().into())),
+        }
+    }
+
+    fn substring(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Numb: TryInto<usize>,
+    {
+        let (s, start, end) = match (vs.pop(), vs.pop(), vs.pop(), vs.pop()) {
+            (Some(end), Some(start), Some(s), None) => (s, start, Some(end)),
+            (Some(start), Some(s), None, None) => (s, start, None),
+            _ => return Err(RuntimeError::ArityMismatch()),
+        };
+
+        let index = |v: Val<Bool, Numb, Symb, Env, Self>| v.numb().and_then(|n| n.try_into().ok());
+        match (s.str(), index(start), end.map(index)) {
+            (Some(s), Some(start), end) => {
+                let len = s.chars().count();
+                match end.unwrap_or(Some(len)) {
+                    Some(end) if start <= end && end <= len => Ok(Val::Str(
+                        s.chars()
+                            .skip(start)
+                            .take(end - start)
+                            .collect::<String>()
+                            .into(),
+                    )),
+                    _ => Err(RuntimeError::InvalidArguments()),
+                }
+            }
+            _ => Err(RuntimeError::InvalidArguments()),
+        }
+    }
+
+    fn string_eq(
+        vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: From<bool>,
+    {
+        match vs
+            .into_iter()
+            .map(Val::str)
+            .collect::<Option<Vec<Rc<str>>>>()
+        {
+            None => Err(RuntimeError::InvalidArguments()),
+            Some(ss) if ss.is_empty() => Err(RuntimeError::ArityMismatch()),
+            Some(ss) => Ok(Val::Bool(Bool::from(ss.windows(2).all(|w| w[0] == w[1])))),
+        }
+    }
+
+    fn string_to_symbol(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Str(s)), None) => Ok(Val::Quot(Exp::Symb(symbols.get_or_store(&s), None))),
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn symbol_to_string(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Symb: Copy,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Quot(Exp::Symb(s, _))), None) => match symbols.resolve(s) {
+                Some(s) => Ok(Val::Str(s.into())),
+                None => Err(RuntimeError::UnknownSymbol(s)),
+            },
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn number_to_string(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Numb: Display,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Numb(n)), None) => Ok(Val::Str(n.to_string().into())),
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn string_to_number(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: From<bool>,
+        Numb: FromStr,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Str(s)), None) => match s.parse() {
+                Ok(n) => Ok(Val::Numb(n)),
+                Err(_) => Ok(Val::Bool(Bool::from(false))),
+            },
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn error(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        if vs.is_empty() {
+            return Err(RuntimeError::ArityMismatch());
+        }
+
+        match vs.remove(0) {
+            Val::Str(message) => Err(RuntimeError::Raised(Val::Cond(Rc::new(Condition {
+                kind: symbols.get_or_store("error"),
+                message,
+                irritants: Val::list(vs),
+            })))),
+            _ => Err(RuntimeError::InvalidArguments()),
+        }
+    }
+
+    fn raise(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => Err(RuntimeError::Raised(v)),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn is_error_object(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: From<bool>,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(v), None) => Ok(Val::Bool(Bool::from(matches!(v, Val::Cond(_))))),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn error_object_kind(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Symb: Copy,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Cond(c)), None) => Ok(Val::Quot(Exp::Symb(c.kind, None))),
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn error_object_message(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self> {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Cond(c)), None) => Ok(Val::Str(c.message.clone())),
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    fn error_object_irritants(
+        mut vs: Vec<Val<Bool, Numb, Symb, Env, Self>>,
+        _symbols: &mut Symbs,
+    ) -> EvalResult<Bool, Numb, Symb, Env, Self>
+    where
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+        Env: Clone,
+    {
+        match (vs.pop(), vs.pop()) {
+            (Some(Val::Cond(c)), None) => Ok(c.irritants.clone()),
+            (Some(_), None) => Err(RuntimeError::InvalidArguments()),
+            _ => Err(RuntimeError::ArityMismatch()),
+        }
+    }
 }
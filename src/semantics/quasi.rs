@@ -0,0 +1,149 @@
+use std::rc::Rc;
+
+use crate::syntax::{exp::Exp, symb::Symbols};
+
+use super::{err::RuntimeError, res::EvalResult, val::Val};
+
+/// The forms that are handled within a `quasiquote`.
+pub(crate) enum Quasi {
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+}
+
+/// Returns the form of `quasiquote` that an expression is, if any.
+pub(crate) fn quasi<Bool, Numb, Symbs: Symbols>(
+    e: &Exp<Bool, Numb, Symbs::Symb>,
+    symbols: &Symbs,
+) -> Option<(Quasi, Symbs::Symb)>
+where
+    Symbs::Symb: Copy,
+{
+    match e {
+        Exp::List(ls, _) if ls.len() == 2 => match ls[1] {
+            Exp::Symb(s, _) => match symbols.resolve(s)? {
+                "quasiquote" => Some((Quasi::Quasiquote, s)),
+                "unquote" => Some((Quasi::Unquote, s)),
+                "unquote-splicing" => Some((Quasi::UnquoteSplicing, s)),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Collects the expressions unquoted within a template of `quasiquote` at
+/// the given level of nesting, which are the ones to evaluate at level 1.
+pub(crate) fn unquoted<'e, Bool, Numb, Symbs: Symbols>(
+    e: &'e Exp<Bool, Numb, Symbs::Symb>,
+    depth: usize,
+    symbols: &Symbs,
+    es: &mut Vec<&'e Exp<Bool, Numb, Symbs::Symb>>,
+) where
+    Symbs::Symb: Copy,
+{
+    match (e, quasi(e, symbols)) {
+        (Exp::List(ls, _), Some((Quasi::Quasiquote, _))) => {
+            unquoted(&ls[0], depth + 1, symbols, es)
+        }
+        (Exp::List(ls, _), Some(_)) if depth == 1 => es.push(&ls[0]),
+        (Exp::List(ls, _), Some(_)) => unquoted(&ls[0], depth - 1, symbols, es),
+        (Exp::List(ls, _), None) => ls
+            .iter()
+            .rev()
+            .for_each(|e| unquoted(e, depth, symbols, es)),
+        (Exp::Dotted(ls, tail, _), _) => {
+            ls.iter()
+                .rev()
+                .for_each(|e| unquoted(e, depth, symbols, es));
+            unquoted(tail, depth, symbols, es);
+        }
+        (Exp::Quot(e, _), _) => unquoted(e, depth, symbols, es),
+        _ => {}
+    }
+}
+
+/// Builds a template of `quasiquote` at the given level of nesting with the
+/// values of its unquoted expressions, keeping quoted the parts without any.
+pub(crate) fn build<Bool: Clone, Numb: Clone, Symbs: Symbols, Env: Clone, BuiltIn: Clone>(
+    e: Exp<Bool, Numb, Symbs::Symb>,
+    depth: usize,
+    symbols: &mut Symbs,
+    vs: &mut impl Iterator<Item = Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>>,
+) -> EvalResult<Bool, Numb, Symbs::Symb, Env, BuiltIn>
+where
+    Symbs::Symb: Copy,
+{
+    let mut es = Vec::new();
+    unquoted(&e, depth, symbols, &mut es);
+    if es.is_empty() {
+        return Ok(Val::quoted(e));
+    }
+
+    let symbol = |s| Val::Quot(Exp::Symb(s, None));
+    let form = quasi(&e, symbols);
+    match (e, form) {
+        (Exp::List(ls, _), Some((q, s))) => {
+            let e = ls[0].clone();
+            match q {
+                Quasi::Unquote if depth == 1 => vs.next().ok_or(RuntimeError::InvalidArguments()),
+                Quasi::UnquoteSplicing if depth == 1 => Err(RuntimeError::BadFormedExpression(s)),
+                Quasi::Quasiquote => Ok(Val::list(vec![
+                    symbol(s),
+                    build(e, depth + 1, symbols, vs)?,
+                ])),
+                _ => Ok(Val::list(vec![
+                    symbol(s),
+                    build(e, depth - 1, symbols, vs)?,
+                ])),
+            }
+        }
+        (Exp::List(ls, _), None) => Ok(Val::list(build_items(ls, depth, symbols, vs)?)),
+        (Exp::Dotted(ls, tail, _), _) => {
+            let items = build_items(ls, depth, symbols, vs)?;
+            let tail = build(*tail, depth, symbols, vs)?;
+            Ok(items
+                .into_iter()
+                .rev()
+                .fold(tail, |cdr, car| Val::cons(car, cdr)))
+        }
+        (Exp::Quot(e, _), _) => {
+            let quote = symbols.get_or_store("quote");
+            Ok(Val::list(vec![
+                symbol(quote),
+                build(*e, depth, symbols, vs)?,
+            ]))
+        }
+        (e, _) => Ok(Val::quoted(e)),
+    }
+}
+
+/// Builds the reversed items of a list within a template of `quasiquote`,
+/// splicing the lists of the expressions unquoted with `unquote-splicing`.
+fn build_items<Bool: Clone, Numb: Clone, Symbs: Symbols, Env: Clone, BuiltIn: Clone>(
+    ls: Rc<Vec<Exp<Bool, Numb, Symbs::Symb>>>,
+    depth: usize,
+    symbols: &mut Symbs,
+    vs: &mut impl Iterator<Item = Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>>,
+) -> Result<
+    Vec<Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>>,
+    RuntimeError<Symbs::Symb, Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>>,
+>
+where
+    Symbs::Symb: Copy,
+{
+    let mut ls = Rc::unwrap_or_clone(ls);
+    let mut items = Vec::with_capacity(ls.len());
+    while let Some(e) = ls.pop() {
+        match quasi(&e, symbols) {
+            Some((Quasi::UnquoteSplicing, _)) if depth == 1 => match vs.next().map(Val::into_vec) {
+                Some(Ok(spliced)) => items.extend(spliced),
+                _ => return Err(RuntimeError::InvalidArguments()),
+            },
+            _ => items.push(build(e, depth, symbols, vs)?),
+        }
+    }
+
+    Ok(items)
+}
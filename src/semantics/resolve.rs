@@ -0,0 +1,695 @@
+use std::rc::Rc;
+
+use crate::syntax::{exp::Exp, span::Span, symb::Symbols};
+
+use super::{eval::parse_bindings, params::Params};
+
+/// A pass that resolves the symbols of an expression that refer to local
+/// variables into their position in the environments that the `Evaluator`
+/// pushes for lambdas and binding forms, so that they are not looked up by
+/// name in every environment up to the one that binds them.
+///
+/// A symbol is left to be looked up by name if a variable with its name
+/// could be defined in between by an expression that is not known before
+/// evaluation, such as the expansion of a macro or the data given to `eval`.
+pub(crate) struct Resolver<'a, Symbs, Global> {
+    symbols: &'a Symbs,
+    /// Whether a global variable is bound to a macro, if it is bound at all.
+    global: Global,
+}
+
+/// The variables of an environment, as known before evaluation.
+struct Scope<Symb> {
+    /// The variables bound when the environment is pushed, in order.
+    vars: Vec<Symb>,
+    /// The variables that may be defined afterwards with `define`.
+    defined: Vec<Symb>,
+    /// The macros that may be defined with `define-syntax` and the like.
+    macros: Vec<Symb>,
+    /// Whether other variables may be defined in the environment.
+    open: bool,
+}
+
+impl<Symb> Scope<Symb> {
+    fn new(vars: Vec<Symb>) -> Self {
+        Self {
+            vars,
+            defined: Vec::new(),
+            macros: Vec::new(),
+            open: false,
+        }
+    }
+}
+
+/// How the operator of a form is bound.
+enum Head<'a> {
+    /// The operator is a variable or an expression, whose value is applied.
+    Variable(),
+    Macro(),
+    /// The operator is not bound, so that it is a special form if it is the
+    /// name of one.
+    Unbound(Option<&'a str>),
+}
+
+impl<'a, Symbs, Global> Resolver<'a, Symbs, Global>
+where
+    Symbs: Symbols,
+    Symbs::Symb: Eq + Copy,
+    Global: Fn(Symbs::Symb) -> Option<bool>,
+{
+    pub(crate) fn new(symbols: &'a Symbs, global: Global) -> Self {
+        Self { symbols, global }
+    }
+
+    /// Resolves the references to the local variables bound within an
+    /// expression, which can then be evaluated in any environment.
+    pub(crate) fn resolve<Bool: Clone, Numb: Clone>(
+        &self,
+        exp: Exp<Bool, Numb, Symbs::Symb>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        self.exp(exp, &mut Vec::new())
+    }
+
+    fn exp<Bool: Clone, Numb: Clone>(
+        &self,
+        exp: Exp<Bool, Numb, Symbs::Symb>,
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        match exp {
+            Exp::Symb(x, span) => match locate(x, scopes) {
+                Some((depth, index)) => Exp::Local(x, depth, index, span),
+                None => Exp::Symb(x, span),
+            },
+            Exp::List(ls, span) => self.list(ls, span, scopes),
+            e => e,
+        }
+    }
+
+    fn list<Bool: Clone, Numb: Clone>(
+        &self,
+        ls: Rc<Vec<Exp<Bool, Numb, Symbs::Symb>>>,
+        span: Option<Span>,
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        let name = match self.head(&ls, scopes) {
+            // The operands of a macro are not expressions until expanded.
+            Head::Macro() => return Exp::List(ls, span),
+            Head::Variable() | Head::Unbound(None) => {
+                return map(&ls, span, |_, e| self.exp(e.clone(), scopes))
+            }
+            Head::Unbound(Some(name)) => name,
+        };
+
+        match (name, &ls[..]) {
+            (
+                "quote" | "quasiquote" | "define-syntax" | "define-macro" | "defmacro"
+                | "let-syntax" | "letrec-syntax",
+                _,
+            ) => Exp::List(ls.clone(), span),
+            ("define" | "set!", [_, _, _]) => map(&ls, span, |i, e| match i {
+                2 => self.exp(e.clone(), scopes),
+                _ => e.clone(),
+            }),
+            ("lambda", [b, ps, _]) => match Params::parse(ps.clone(), self.symbols) {
+                Some(ps) => {
+                    let mut vars = ps.required.clone();
+                    vars.extend(ps.optional.iter().map(|(x, _)| *x));
+                    vars.extend(ps.rest);
+                    // The default values of the optional parameters are left
+                    // unresolved, but they are evaluated in the same environment.
+                    scopes.push(Scope::new(vars));
+                    for e in ps.optional.iter().filter_map(|(_, e)| e.as_ref()) {
+                        self.scan(e, scopes);
+                    }
+
+                    let b = self.scoped(std::slice::from_ref(b), scopes);
+                    scopes.pop();
+                    rebuild(b, &ls[1..], span)
+                }
+                None => Exp::List(ls.clone(), span),
+            },
+            ("let", [body @ .., bs, Exp::Symb(name, _), _]) => match parse_bindings(bs.clone()) {
+                Some(xs) => {
+                    let bs = self.bindings(bs, scopes);
+                    // The lambda of the loop is bound in its own environment.
+                    scopes.push(Scope::new(vec![*name]));
+                    scopes.push(Scope::new(xs.into_iter().rev().map(|(x, _)| x).collect()));
+                    let body = self.scoped(body, scopes);
+                    scopes.truncate(scopes.len() - 2);
+                    rebuild(
+                        body,
+                        &[bs, ls[ls.len() - 2].clone(), ls[ls.len() - 1].clone()],
+                        span,
+                    )
+                }
+                None => Exp::List(ls.clone(), span),
+            },
+            ("let", [body @ .., bs, _]) => match parse_bindings(bs.clone()) {
+                Some(xs) => {
+                    let bs = self.bindings(bs, scopes);
+                    scopes.push(Scope::new(xs.into_iter().rev().map(|(x, _)| x).collect()));
+                    let body = self.scoped(body, scopes);
+                    scopes.pop();
+                    rebuild(body, &[bs, ls[ls.len() - 1].clone()], span)
+                }
+                None => Exp::List(ls.clone(), span),
+            },
+            ("let*", [body @ .., bs, _]) => match (parse_bindings(bs.clone()), bs) {
+                (Some(xs), Exp::List(bs, bs_span)) => {
+                    // Each binding is in a new environment, where the next one
+                    // is evaluated.
+                    let mut resolved = Vec::with_capacity(bs.len());
+                    for (i, (b, (x, e))) in bs.iter().zip(&xs).rev().enumerate() {
+                        if i > 0 {
+                            self.scan(e, scopes);
+                        }
+
+                        resolved.push(self.binding(b, scopes));
+                        scopes.push(Scope::new(vec![*x]));
+                    }
+
+                    if xs.is_empty() {
+                        scopes.push(Scope::new(Vec::new()));
+                    }
+
+                    let body = self.scoped(body, scopes);
+                    scopes.truncate(scopes.len() - xs.len().max(1));
+                    resolved.reverse();
+                    let bs = Exp::List(Rc::new(resolved), bs_span.clone());
+                    rebuild(body, &[bs, ls[ls.len() - 1].clone()], span)
+                }
+                _ => Exp::List(ls.clone(), span),
+            },
+            ("letrec" | "letrec*", [body @ .., bs, _]) => match parse_bindings(bs.clone()) {
+                Some(xs) => {
+                    // The bindings are evaluated within the new environment.
+                    scopes.push(Scope::new(xs.iter().rev().map(|(x, _)| *x).collect()));
+                    for (_, e) in xs.iter().rev() {
+                        self.scan(e, scopes);
+                    }
+
+                    let bs = self.bindings(bs, scopes);
+                    let body = self.scoped(body, scopes);
+                    scopes.pop();
+                    rebuild(body, &[bs, ls[ls.len() - 1].clone()], span)
+                }
+                None => Exp::List(ls.clone(), span),
+            },
+            ("cond", _) => map(&ls, span, |i, e| match i {
+                0 => e.clone(),
+                _ => self.clause(e, 0, scopes),
+            }),
+            ("case", _) => map(&ls, span, |i, e| match i {
+                0 => e.clone(),
+                1 => self.exp(e.clone(), scopes),
+                _ => self.clause(e, 1, scopes),
+            }),
+            ("guard", [.., Exp::List(clauses, clauses_span), _]) => match clauses.last() {
+                Some(&Exp::Symb(x, _)) => {
+                    // The clauses are evaluated in a new environment where the
+                    // variable is bound to the raised value.
+                    scopes.push(Scope::new(vec![x]));
+                    for clause in clauses.iter().rev() {
+                        if let Exp::List(clause, _) = clause {
+                            clause.iter().rev().for_each(|e| self.scan(e, scopes));
+                        }
+                    }
+
+                    let clauses = map(clauses, clauses_span.clone(), |i, e| match i {
+                        0 => e.clone(),
+                        _ => self.clause(e, 0, scopes),
+                    });
+                    scopes.pop();
+                    map(&ls, span, |i, e| match i {
+                        0 => e.clone(),
+                        1 => clauses.clone(),
+                        _ => self.exp(e.clone(), scopes),
+                    })
+                }
+                _ => Exp::List(ls.clone(), span),
+            },
+            _ => map(&ls, span, |_, e| self.exp(e.clone(), scopes)),
+        }
+    }
+
+    /// Returns how the operator of a reversed form is bound.
+    fn head<Bool, Numb>(
+        &self,
+        ls: &[Exp<Bool, Numb, Symbs::Symb>],
+        scopes: &[Scope<Symbs::Symb>],
+    ) -> Head<'a> {
+        let s = match ls.last() {
+            Some(&Exp::Symb(s, _)) => s,
+            _ => return Head::Variable(),
+        };
+
+        for scope in scopes.iter().rev() {
+            if scope.vars.contains(&s) || scope.defined.contains(&s) {
+                return Head::Variable();
+            } else if scope.macros.contains(&s) {
+                return Head::Macro();
+            }
+        }
+
+        match (self.global)(s) {
+            Some(true) => Head::Macro(),
+            Some(false) => Head::Variable(),
+            None => Head::Unbound(self.symbols.resolve(s)),
+        }
+    }
+
+    /// Resolves the reversed body of a binding form within the innermost
+    /// scope, where it is evaluated.
+    fn scoped<Bool: Clone, Numb: Clone>(
+        &self,
+        body: &[Exp<Bool, Numb, Symbs::Symb>],
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) -> Vec<Exp<Bool, Numb, Symbs::Symb>> {
+        for e in body.iter().rev() {
+            self.scan(e, scopes);
+        }
+
+        body.iter().map(|e| self.exp(e.clone(), scopes)).collect()
+    }
+
+    /// Resolves the expressions of a list of `(x e)` bindings.
+    fn bindings<Bool: Clone, Numb: Clone>(
+        &self,
+        bs: &Exp<Bool, Numb, Symbs::Symb>,
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        match bs {
+            Exp::List(bs, span) => map(bs, span.clone(), |_, b| self.binding(b, scopes)),
+            bs => bs.clone(),
+        }
+    }
+
+    fn binding<Bool: Clone, Numb: Clone>(
+        &self,
+        b: &Exp<Bool, Numb, Symbs::Symb>,
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        match b {
+            Exp::List(b, span) => map(b, span.clone(), |i, e| match i {
+                1 => self.exp(e.clone(), scopes),
+                _ => e.clone(),
+            }),
+            b => b.clone(),
+        }
+    }
+
+    /// Resolves the expressions of a clause of `cond` or `case` after the
+    /// given number of elements.
+    fn clause<Bool: Clone, Numb: Clone>(
+        &self,
+        clause: &Exp<Bool, Numb, Symbs::Symb>,
+        skip: usize,
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        match clause {
+            Exp::List(clause, span) => map(clause, span.clone(), |i, e| {
+                if i < skip {
+                    e.clone()
+                } else {
+                    self.exp(e.clone(), scopes)
+                }
+            }),
+            clause => clause.clone(),
+        }
+    }
+
+    /// Records in the innermost scope what an expression evaluated in its
+    /// environment may define there.
+    fn scan<Bool: Clone, Numb: Clone>(
+        &self,
+        exp: &Exp<Bool, Numb, Symbs::Symb>,
+        scopes: &mut Vec<Scope<Symbs::Symb>>,
+    ) {
+        let ls = match exp {
+            Exp::List(ls, _) if !scopes.is_empty() => ls,
+            _ => return,
+        };
+
+        let name = match self.head(ls, scopes) {
+            Head::Variable() => {
+                ls.iter().rev().for_each(|e| self.scan(e, scopes));
+                return;
+            }
+            Head::Macro() | Head::Unbound(None) => "",
+            Head::Unbound(Some(name)) => name,
+        };
+
+        let scope = scopes.len() - 1;
+        match (name, &ls[..]) {
+            ("quote" | "lambda" | "letrec" | "letrec*" | "let-syntax" | "letrec-syntax", _) => (),
+            ("define", [e, Exp::Symb(x, _), _]) => {
+                if !scopes[scope].defined.contains(x) {
+                    scopes[scope].defined.push(*x);
+                }
+
+                self.scan(e, scopes);
+            }
+            ("set!", [e, _, _]) => self.scan(e, scopes),
+            ("define-syntax", [_, Exp::Symb(x, _), _]) | ("defmacro", [.., Exp::Symb(x, _), _]) => {
+                scopes[scope].macros.push(*x)
+            }
+            ("define-macro", [.., Exp::List(header, _), _]) => {
+                if let Some(&Exp::Symb(x, _)) = header.last() {
+                    scopes[scope].macros.push(x);
+                }
+            }
+            ("let", [.., Exp::List(bs, _), Exp::Symb(_, _), _] | [.., Exp::List(bs, _), _]) => {
+                for b in bs.iter().rev() {
+                    if let Exp::List(b, _) = b {
+                        b.iter().rev().for_each(|e| self.scan(e, scopes));
+                    }
+                }
+            }
+            ("let*", [.., Exp::List(bs, _), _]) => {
+                if let Some(Exp::List(b, _)) = bs.last() {
+                    b.iter().rev().for_each(|e| self.scan(e, scopes));
+                }
+            }
+            ("cond", [clauses @ .., _]) => {
+                for clause in clauses.iter().rev() {
+                    if let Exp::List(clause, _) = clause {
+                        clause.iter().rev().for_each(|e| self.scan(e, scopes));
+                    }
+                }
+            }
+            ("case", [clauses @ .., key, _]) => {
+                self.scan(key, scopes);
+                for clause in clauses.iter().rev() {
+                    if let Exp::List(clause, _) = clause {
+                        clause
+                            .iter()
+                            .rev()
+                            .skip(1)
+                            .for_each(|e| self.scan(e, scopes));
+                    }
+                }
+            }
+            ("guard", [body @ .., _, _]) => body.iter().rev().for_each(|e| self.scan(e, scopes)),
+            ("if" | "begin" | "when" | "unless" | "and" | "or", [operands @ .., _]) => {
+                operands.iter().rev().for_each(|e| self.scan(e, scopes))
+            }
+            // The expansion of a macro, the data given to `eval` or an operator
+            // that may be bound to a macro later may define any variable.
+            _ => scopes[scope].open = true,
+        }
+    }
+}
+
+/// Returns the number of environments to go up and the position of the
+/// variable that a symbol refers to, unless it may be another variable.
+fn locate<Symb: Eq + Copy>(x: Symb, scopes: &[Scope<Symb>]) -> Option<(usize, usize)> {
+    for (depth, scope) in scopes.iter().rev().enumerate() {
+        if let Some(i) = scope.vars.iter().position(|&y| y == x) {
+            return Some((depth, i));
+        }
+
+        // The position of a defined variable depends on the order of the
+        // definitions, so that it is only a hint.
+        if let Some(i) = scope.defined.iter().position(|&y| y == x) {
+            return Some((depth, scope.vars.len() + i));
+        }
+
+        if scope.open || scope.macros.contains(&x) {
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Maps the elements of a reversed list, given with their position in order.
+fn map<Bool, Numb, Symb>(
+    ls: &[Exp<Bool, Numb, Symb>],
+    span: Option<Span>,
+    mut f: impl FnMut(usize, &Exp<Bool, Numb, Symb>) -> Exp<Bool, Numb, Symb>,
+) -> Exp<Bool, Numb, Symb> {
+    let n = ls.len();
+    let ls = ls
+        .iter()
+        .enumerate()
+        .map(|(i, e)| f(n - 1 - i, e))
+        .collect();
+    Exp::List(Rc::new(ls), span)
+}
+
+/// Returns a reversed list with the given reversed body followed by the
+/// reversed elements that precede it.
+fn rebuild<Bool: Clone, Numb: Clone, Symb: Copy>(
+    mut body: Vec<Exp<Bool, Numb, Symb>>,
+    rest: &[Exp<Bool, Numb, Symb>],
+    span: Option<Span>,
+) -> Exp<Bool, Numb, Symb> {
+    body.extend_from_slice(rest);
+    Exp::List(Rc::new(body), span)
+}
+
+/// Returns an expression with its resolved symbols back to plain ones, as
+/// when they are the operands of a macro.
+pub(crate) fn unresolved<Bool: Clone, Numb: Clone, Symb: Copy>(
+    exp: Exp<Bool, Numb, Symb>,
+) -> Exp<Bool, Numb, Symb> {
+    match exp {
+        Exp::Local(x, _, _, span) => Exp::Symb(x, span),
+        Exp::List(ls, span) if ls.iter().any(is_resolved) => {
+            Exp::List(Rc::new(ls.iter().cloned().map(unresolved).collect()), span)
+        }
+        e => e,
+    }
+}
+
+fn is_resolved<Bool, Numb, Symb>(exp: &Exp<Bool, Numb, Symb>) -> bool {
+    match exp {
+        Exp::Local(_, _, _, _) => true,
+        Exp::List(ls, _) => ls.iter().any(is_resolved),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::{
+        interpreter::Value,
+        semantics::{
+            built_in::EvalBuiltIn,
+            env::{Environments, GcStats, Tracer},
+            env_frames::{EnvironmentFrames, FrameId},
+            eval::Evaluator,
+            val::Val,
+        },
+        syntax::{exp::Exp, parse::Parser, symb::Symbols, symb_interner::SymbolsInterner},
+    };
+
+    use super::Resolver;
+
+    type Symb = <SymbolsInterner as Symbols>::Symb;
+
+    /// Environments that check that the variable found at a resolved position
+    /// is the one that looking up its name finds.
+    struct Checked {
+        frames: EnvironmentFrames<Symb, Value>,
+        checked: Cell<usize>,
+    }
+
+    impl Environments<Symb, Value> for Checked {
+        type Env = FrameId;
+
+        fn root(&self) -> FrameId {
+            self.frames.root()
+        }
+
+        fn drop(&mut self, at: FrameId) {
+            self.frames.drop(at)
+        }
+
+        fn push(&mut self, at: FrameId, capacity: usize) -> Option<FrameId> {
+            self.frames.push(at, capacity)
+        }
+
+        fn get(&self, at: FrameId, x: &Symb) -> Option<&Value> {
+            self.frames.get(at, x)
+        }
+
+        fn get_at(&self, at: FrameId, x: &Symb, depth: usize, index: usize) -> Option<&Value> {
+            let v = self.frames.get_at(at, x, depth, index);
+            let found = (v, self.frames.get(at, x));
+            assert!(
+                matches!(found, (Some(v), Some(w)) if std::ptr::eq(v, w)),
+                "resolved to {depth} {index}"
+            );
+            self.checked.set(self.checked.get() + 1);
+            v
+        }
+
+        fn define(&mut self, at: FrameId, x: Symb, v: Value) -> Result<(), (Symb, Value)> {
+            self.frames.define(at, x, v)
+        }
+
+        fn set(&mut self, at: FrameId, x: &Symb, v: Value) -> Result<(), Value> {
+            self.frames.set(at, x, v)
+        }
+
+        fn within(&self, at: FrameId, ancestor: FrameId) -> bool {
+            self.frames.within(at, ancestor)
+        }
+
+        fn should_collect(&self) -> bool {
+            self.frames.should_collect()
+        }
+
+        fn collect(&mut self, tracer: Tracer<FrameId>) {
+            self.frames.collect(tracer)
+        }
+
+        fn stats(&self) -> GcStats {
+            self.frames.stats()
+        }
+    }
+
+    /// Appends the names and positions of the resolved symbols of an
+    /// expression, in the order they are read.
+    fn locals(
+        exp: &Exp<bool, i64, Symb>,
+        symbols: &SymbolsInterner,
+        out: &mut Vec<(String, usize, usize)>,
+    ) {
+        match exp {
+            Exp::Local(x, depth, index, _) => {
+                out.push((symbols.resolve(*x).unwrap().to_string(), *depth, *index))
+            }
+            Exp::List(ls, _) => ls.iter().rev().for_each(|e| locals(e, symbols, out)),
+            _ => {}
+        }
+    }
+
+    /// Evaluates a script whose value is a number, returning the positions of
+    /// the symbols resolved in its forms along with the number, once each
+    /// variable found at a resolved position is checked.
+    fn resolve(script: &str) -> (Vec<(String, usize, usize)>, i64) {
+        let mut symbols = SymbolsInterner::new();
+        let mut env = Checked {
+            frames: EnvironmentFrames::empty(0),
+            checked: Cell::new(0),
+        };
+        let _ = EvalBuiltIn::load_prelude(&mut env, &mut symbols);
+        let program = Parser::new(&mut symbols)
+            .parse_all_exps::<bool, i64>(script)
+            .unwrap();
+
+        let mut resolved = Vec::new();
+        let mut v = Val::Void();
+        for exp in program {
+            let root = env.root();
+            let global = |x| env.get(root, &x).map(|v| matches!(v, Val::Macro(_)));
+            locals(
+                &Resolver::new(&symbols, global).resolve(exp.clone()),
+                &symbols,
+                &mut resolved,
+            );
+            v = Evaluator::new(&mut symbols, &mut env).eval(exp).unwrap();
+        }
+
+        assert!(env.checked.get() > 0);
+        match v {
+            Val::Numb(n) => (resolved, n),
+            _ => panic!("not a number"),
+        }
+    }
+
+    fn positions(ls: &[(&str, usize, usize)]) -> Vec<(String, usize, usize)> {
+        ls.iter()
+            .map(|&(x, depth, index)| (x.to_string(), depth, index))
+            .collect()
+    }
+
+    #[test]
+    fn shadowing() {
+        let script = "((lambda (x y) ((lambda (x) (+ x y)) (* x 10))) 1 2)";
+        let (resolved, v) = resolve(script);
+        assert_eq!(
+            resolved,
+            positions(&[("x", 0, 0), ("y", 1, 1), ("x", 0, 0)])
+        );
+        assert_eq!(v, 12);
+    }
+
+    #[test]
+    fn internal_defines() {
+        let script = "
+            ((lambda (a)
+               (begin
+                 (define b (+ a 1))
+                 (define f (lambda () (* b c)))
+                 (define c (+ b 1))
+                 (f)))
+             1)";
+        let (resolved, v) = resolve(script);
+        let expected = [
+            ("a", 0, 0),
+            ("b", 1, 1),
+            ("c", 1, 3),
+            ("b", 0, 1),
+            ("f", 0, 2),
+        ];
+        assert_eq!(resolved, positions(&expected));
+        assert_eq!(v, 6);
+    }
+
+    #[test]
+    fn assignments() {
+        let script = "
+            ((lambda (n)
+               (begin
+                 (define add! (lambda (k) (set! n (+ n k))))
+                 (add! 2)
+                 (set! n (* n 10))
+                 n))
+             1)";
+        let (resolved, v) = resolve(script);
+        let expected = [
+            ("n", 1, 0),
+            ("k", 0, 0),
+            ("add!", 0, 1),
+            ("n", 0, 0),
+            ("n", 0, 0),
+        ];
+        assert_eq!(resolved, positions(&expected));
+        assert_eq!(v, 30);
+    }
+
+    #[test]
+    fn eval_leaves_the_names_it_may_define() {
+        let script = "
+            ((lambda (x)
+               (begin
+                 (eval '(define y 2))
+                 ((lambda () (+ x y)))))
+             1)";
+        let (resolved, v) = resolve(script);
+        assert_eq!(resolved, positions(&[("x", 1, 0)]));
+        assert_eq!(v, 3);
+    }
+
+    #[test]
+    fn macros_leave_the_names_they_may_bind() {
+        let script = "
+            (define-syntax define-double
+              (syntax-rules () ((_ x v) (define x (* 2 v)))))
+            (define-syntax with
+              (syntax-rules () ((_ x v body) ((lambda (x) body) v))))
+            ((lambda (a)
+               (begin
+                 (define-double b a)
+                 (+ a b ((lambda () a)) (with c (+ a b) (+ a b c)))))
+             1)";
+        let (resolved, v) = resolve(script);
+        assert_eq!(resolved, positions(&[("a", 0, 0), ("a", 1, 0)]));
+        assert_eq!(v, 10);
+    }
+}
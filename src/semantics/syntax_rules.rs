@@ -0,0 +1,292 @@
+use std::{collections::HashMap, hash::Hash, rc::Rc};
+
+use crate::syntax::{exp::Exp, span::Span, symb::Symbols};
+
+/// The rules of a macro defined with `syntax-rules`, which rewrite a form that
+/// matches a pattern into the corresponding template.
+#[derive(Clone)]
+pub struct SyntaxRules<Bool, Numb, Symb> {
+    /// The symbols that only match themselves in the patterns.
+    pub literals: Vec<Symb>,
+    /// The symbol that follows a subpattern or subtemplate that repeats, which
+    /// is `...` unless given otherwise.
+    pub ellipsis: Symb,
+    /// The symbol that matches anything without binding it, which is `_`.
+    pub wildcard: Symb,
+    /// The patterns, without the keyword of the macro, and their templates.
+    pub rules: Vec<(Exp<Bool, Numb, Symb>, Exp<Bool, Numb, Symb>)>,
+}
+
+/// The expressions matched by a pattern variable, at each level of ellipsis.
+enum Binding<'a, Bool, Numb, Symb> {
+    One(&'a Exp<Bool, Numb, Symb>),
+    Many(Vec<Self>),
+}
+
+impl<Bool: PartialEq + Clone, Numb: PartialEq + Clone, Symb: Eq + Hash + Copy>
+    SyntaxRules<Bool, Numb, Symb>
+{
+    /// Parses `(syntax-rules (literal ...) (pattern template) ...)`, which can
+    /// also give its own ellipsis symbol before the literals.
+    pub fn parse<Symbs: Symbols<Symb = Symb>>(
+        exp: Exp<Bool, Numb, Symb>,
+        symbols: &mut Symbs,
+    ) -> Option<Self> {
+        let mut ls = match exp {
+            Exp::List(ls, _) => Rc::unwrap_or_clone(ls),
+            _ => return None,
+        };
+
+        match ls.pop() {
+            Some(Exp::Symb(s, _)) if symbols.resolve(s) == Some("syntax-rules") => {}
+            _ => return None,
+        }
+
+        let ellipsis = match ls.last() {
+            Some(&Exp::Symb(s, _)) => {
+                ls.pop();
+                s
+            }
+            _ => symbols.get_or_store("..."),
+        };
+
+        let literals = match ls.pop() {
+            Some(Exp::List(xs, _)) => xs.iter().cloned().rev().map(Exp::symb).collect(),
+            _ => None,
+        }?;
+
+        let rules = ls
+            .into_iter()
+            .rev()
+            .map(|rule| match rule {
+                Exp::List(rule, _) => match &rule[..] {
+                    [t, Exp::List(p, span)] => {
+                        let (_, p) = p.split_last()?;
+                        Some((Exp::List(Rc::new(p.to_vec()), span.clone()), t.clone()))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            literals,
+            ellipsis,
+            wildcard: symbols.get_or_store("_"),
+            rules,
+        })
+    }
+
+    /// Rewrites the reversed operands of a form with the template of the first
+    /// matching rule, where the symbols that the template introduces are
+    /// renamed and the introduced lists take the given `Span`.
+    pub fn expand(
+        &self,
+        operands: Vec<Exp<Bool, Numb, Symb>>,
+        span: Option<Span>,
+        rename: &mut impl FnMut(Symb) -> Symb,
+    ) -> Option<Exp<Bool, Numb, Symb>> {
+        let form = Exp::List(Rc::new(operands), None);
+        self.rules.iter().find_map(|(p, t)| {
+            let mut bs = HashMap::new();
+            if self.matches(p, &form, &mut bs) {
+                let bs = bs.iter().map(|(&x, b)| (x, b)).collect();
+                self.instantiate(t, &bs, &span, rename, false)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn matches<'a>(
+        &self,
+        p: &Exp<Bool, Numb, Symb>,
+        f: &'a Exp<Bool, Numb, Symb>,
+        bs: &mut HashMap<Symb, Binding<'a, Bool, Numb, Symb>>,
+    ) -> bool {
+        match p {
+            Exp::Symb(s, _) if *s == self.wildcard => true,
+            Exp::Symb(s, _) if *s == self.ellipsis => false,
+            Exp::Symb(s, _) if self.literals.contains(s) => matches!(f, Exp::Symb(x, _) if x == s),
+            Exp::Symb(s, _) => {
+                bs.insert(*s, Binding::One(f));
+                true
+            }
+            Exp::List(ps, _) => match f {
+                Exp::List(fs, _) => self.matches_list(ps, fs, bs),
+                _ => false,
+            },
+            p => p == f,
+        }
+    }
+
+    /// Matches reversed patterns, which can contain a subpattern followed by
+    /// an ellipsis, against reversed forms.
+    fn matches_list<'a>(
+        &self,
+        ps: &[Exp<Bool, Numb, Symb>],
+        fs: &'a [Exp<Bool, Numb, Symb>],
+        bs: &mut HashMap<Symb, Binding<'a, Bool, Numb, Symb>>,
+    ) -> bool {
+        let ps: Vec<_> = ps.iter().rev().collect();
+        let fs: Vec<_> = fs.iter().rev().collect();
+        let i = match ps.iter().position(|p| self.is_ellipsis(p)) {
+            None => {
+                return ps.len() == fs.len()
+                    && ps.iter().zip(&fs).all(|(p, f)| self.matches(p, f, bs))
+            }
+            Some(0) => return false,
+            Some(i) => i,
+        };
+
+        let (before, repeated, after) = (&ps[..i - 1], ps[i - 1], &ps[i + 1..]);
+        if fs.len() < before.len() + after.len() {
+            return false;
+        }
+
+        let n = fs.len() - after.len();
+        if !before.iter().zip(&fs).all(|(p, f)| self.matches(p, f, bs))
+            || !after
+                .iter()
+                .zip(&fs[n..])
+                .all(|(p, f)| self.matches(p, f, bs))
+        {
+            return false;
+        }
+
+        let mut vars = Vec::new();
+        self.vars(repeated, &mut vars);
+        let mut many: Vec<_> = vars.iter().map(|_| Vec::new()).collect();
+        for f in &fs[before.len()..n] {
+            let mut inner = HashMap::new();
+            if !self.matches(repeated, f, &mut inner) {
+                return false;
+            }
+
+            for (x, m) in vars.iter().zip(&mut many) {
+                m.extend(inner.remove(x));
+            }
+        }
+
+        bs.extend(vars.into_iter().zip(many.into_iter().map(Binding::Many)));
+        true
+    }
+
+    fn instantiate(
+        &self,
+        t: &Exp<Bool, Numb, Symb>,
+        bs: &HashMap<Symb, &Binding<Bool, Numb, Symb>>,
+        span: &Option<Span>,
+        rename: &mut impl FnMut(Symb) -> Symb,
+        quoted: bool,
+    ) -> Option<Exp<Bool, Numb, Symb>> {
+        match t {
+            Exp::Symb(s, _) => match bs.get(s) {
+                Some(Binding::One(e)) => Some((*e).clone()),
+                Some(Binding::Many(_)) => None,
+                None if quoted => Some(Exp::Symb(*s, span.clone())),
+                None => Some(Exp::Symb(rename(*s), span.clone())),
+            },
+            Exp::Quot(e, _) => self
+                .instantiate(e, bs, span, rename, true)
+                .map(|e| Exp::Quot(Box::new(e), span.clone())),
+            Exp::List(ts, _) => {
+                let ts: Vec<_> = ts.iter().rev().collect();
+                let mut out = Vec::with_capacity(ts.len());
+                let mut i = 0;
+                while i < ts.len() {
+                    let depth = ts[i + 1..]
+                        .iter()
+                        .take_while(|t| self.is_ellipsis(t))
+                        .count();
+                    if depth == 0 {
+                        out.push(self.instantiate(ts[i], bs, span, rename, quoted)?);
+                    } else {
+                        self.instantiate_many(ts[i], bs, depth, span, rename, quoted, &mut out)?;
+                    }
+
+                    i += 1 + depth;
+                }
+
+                out.reverse();
+                Some(Exp::List(Rc::new(out), span.clone()))
+            }
+            Exp::Dotted(ts, t, _) => {
+                let ts = ts
+                    .iter()
+                    .map(|t| self.instantiate(t, bs, span, rename, quoted))
+                    .collect::<Option<_>>()?;
+                let t = self.instantiate(t, bs, span, rename, quoted)?;
+                Some(Exp::Dotted(Rc::new(ts), Box::new(t), span.clone()))
+            }
+            t => Some(t.clone()),
+        }
+    }
+
+    /// Instantiates a subtemplate followed by a number of ellipses once for
+    /// each of the expressions matched by its pattern variables.
+    #[allow(clippy::too_many_arguments)]
+    fn instantiate_many(
+        &self,
+        t: &Exp<Bool, Numb, Symb>,
+        bs: &HashMap<Symb, &Binding<Bool, Numb, Symb>>,
+        depth: usize,
+        span: &Option<Span>,
+        rename: &mut impl FnMut(Symb) -> Symb,
+        quoted: bool,
+        out: &mut Vec<Exp<Bool, Numb, Symb>>,
+    ) -> Option<()> {
+        let mut vars = Vec::new();
+        self.vars(t, &mut vars);
+        let iterated: Vec<_> = vars
+            .into_iter()
+            .filter_map(|x| match bs.get(&x) {
+                Some(Binding::Many(m)) => Some((x, m)),
+                _ => None,
+            })
+            .collect();
+
+        let n = iterated.first()?.1.len();
+        if iterated.iter().any(|(_, m)| m.len() != n) {
+            return None;
+        }
+
+        for k in 0..n {
+            let mut inner = bs.clone();
+            for (x, m) in &iterated {
+                inner.insert(*x, &m[k]);
+            }
+
+            if depth > 1 {
+                self.instantiate_many(t, &inner, depth - 1, span, rename, quoted, out)?;
+            } else {
+                out.push(self.instantiate(t, &inner, span, rename, quoted)?);
+            }
+        }
+
+        Some(())
+    }
+
+    /// Collects the symbols of a pattern that can be bound.
+    fn vars(&self, p: &Exp<Bool, Numb, Symb>, vars: &mut Vec<Symb>) {
+        match p {
+            Exp::Symb(s, _)
+                if *s != self.wildcard && *s != self.ellipsis && !self.literals.contains(s) =>
+            {
+                vars.push(*s)
+            }
+            Exp::List(ps, _) => ps.iter().for_each(|p| self.vars(p, vars)),
+            Exp::Dotted(ps, p, _) => {
+                ps.iter().for_each(|p| self.vars(p, vars));
+                self.vars(p, vars);
+            }
+            Exp::Quot(p, _) => self.vars(p, vars),
+            _ => {}
+        }
+    }
+
+    fn is_ellipsis(&self, e: &Exp<Bool, Numb, Symb>) -> bool {
+        matches!(e, Exp::Symb(s, _) if *s == self.ellipsis)
+    }
+}
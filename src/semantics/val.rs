@@ -1,11 +1,26 @@
-use std::fmt::Display;
+use std::{
+    fmt::{Debug, Display},
+    mem,
+    rc::Rc,
+};
 
 use crate::syntax::{
-    exp::Exp,
+    exp::{escape_str, Exp},
     print::{PrintError, PrintWithSymbols},
     symb::Symbols,
 };
 
+use super::{
+    bytecode::{Code, FrameRef},
+    condition::Condition,
+    cont::Continuation,
+    env::{Trace, Tracer},
+    macros::Macro,
+    params::Params,
+    port::OutputPort,
+    vm,
+};
+
 /// A value that can result from the evaluation of an `Exp`.
 #[derive(Clone)]
 pub enum Val<Bool, Numb, Symb, Env, BuiltIn> {
@@ -15,12 +30,70 @@ pub enum Val<Bool, Numb, Symb, Env, BuiltIn> {
     Bool(Bool),
     /// A numeric value of type `Numb`.
     Numb(Numb),
+    /// An immutable string.
+    Str(Rc<str>),
     /// A quoted expression.
     Quot(Exp<Bool, Numb, Symb>),
-    /// A lambda with a reference to its environment.
-    Lamb(Vec<Symb>, Exp<Bool, Numb, Symb>, Env),
+    /// A lambda with a reference to its environment, whose parameters and body
+    /// are shared by its clones, and kept out of the value to keep it small.
+    Lamb(Rc<(Params<Bool, Numb, Symb>, Exp<Bool, Numb, Symb>)>, Env),
     /// A built-in procedure.
     BuiltIn(BuiltIn),
+    /// A continuation captured by `call/cc`, which can be invoked as a procedure.
+    Cont(Rc<Continuation<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// A lambda compiled to bytecode, with the frame of its enclosing scope.
+    Closure(
+        Rc<Code<Bool, Numb, Symb, Env, BuiltIn>>,
+        Option<FrameRef<Bool, Numb, Symb, Env, BuiltIn>>,
+    ),
+    /// A continuation captured by `call/cc` in the virtual machine.
+    VmCont(Rc<vm::Continuation<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// A macro, which rewrites the forms whose operator it is.
+    Macro(Rc<Macro<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// An output port.
+    Port(OutputPort),
+    /// A condition object describing an error.
+    Cond(Rc<Condition<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// A pair of values, which is a list when its chain of second values ends
+    /// with the empty list, and whether the chain holds any lambda.
+    Pair(Rc<Cons<Bool, Numb, Symb, Env, BuiltIn>>, bool),
+}
+
+/// The first and second values of a pair.
+///
+/// The pairs that only it references are dropped in a loop along both values,
+/// as lists can be longer or deeper than what recursion allows.
+#[derive(Clone)]
+pub struct Cons<Bool, Numb, Symb, Env, BuiltIn>(
+    pub Val<Bool, Numb, Symb, Env, BuiltIn>,
+    pub Val<Bool, Numb, Symb, Env, BuiltIn>,
+);
+
+impl<Bool, Numb, Symb, Env, BuiltIn> Drop for Cons<Bool, Numb, Symb, Env, BuiltIn> {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        take_pairs(self, &mut pending);
+        while let Some(p) = pending.pop() {
+            if let Ok(mut p) = Rc::try_unwrap(p) {
+                take_pairs(&mut p, &mut pending);
+            }
+        }
+    }
+}
+
+/// Takes the values of a pair that are pairs only referenced there, leaving
+/// void in their place.
+fn take_pairs<Bool, Numb, Symb, Env, BuiltIn>(
+    p: &mut Cons<Bool, Numb, Symb, Env, BuiltIn>,
+    pending: &mut Vec<Rc<Cons<Bool, Numb, Symb, Env, BuiltIn>>>,
+) {
+    for v in [&mut p.0, &mut p.1] {
+        if matches!(v, Val::Pair(p, _) if Rc::strong_count(p) == 1) {
+            if let Val::Pair(p, _) = mem::replace(v, Val::Void()) {
+                pending.push(p);
+            }
+        }
+    }
 }
 
 impl<Bool, Numb, Symb, Env, BuiltIn> Val<Bool, Numb, Symb, Env, BuiltIn> {
@@ -41,30 +114,325 @@ impl<Bool, Numb, Symb, Env, BuiltIn> Val<Bool, Numb, Symb, Env, BuiltIn> {
             _ => None,
         }
     }
+
+    /// Returns the underlying string of a value if it
+    /// corresponds to a string.
+    pub fn str(self) -> Option<Rc<str>> {
+        match self {
+            Val::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a quoted expression, which is the underlying number,
+    /// boolean or string if it corresponds to one, and pairs for a dotted list.
+    pub fn quoted(exp: Exp<Bool, Numb, Symb>) -> Self
+    where
+        Bool: Clone,
+        Numb: Clone,
+        Symb: Clone,
+    {
+        match exp {
+            Exp::Numb(n, _) => Val::Numb(n),
+            Exp::Bool(b, _) => Val::Bool(b),
+            Exp::Str(s, _) => Val::Str(s),
+            // The pairs are built from the last one, as the list is reversed.
+            Exp::Dotted(ls, tail, _) => Rc::unwrap_or_clone(ls)
+                .into_iter()
+                .fold(Self::quoted(*tail), |cdr, car| {
+                    Self::cons(Self::quoted(car), cdr)
+                }),
+            e => Val::Quot(e),
+        }
+    }
+
+    /// Returns the empty list.
+    pub fn null() -> Self {
+        Val::Quot(Exp::List(Rc::new(Vec::new()), None))
+    }
+
+    /// Returns a pair of the given values.
+    pub fn cons(car: Self, cdr: Self) -> Self {
+        let has_lamb = car.has_lamb() || cdr.has_lamb();
+        Val::Pair(Rc::new(Cons(car, cdr)), has_lamb)
+    }
+
+    /// Returns a list with the given values.
+    pub fn list(vs: Vec<Self>) -> Self {
+        vs.into_iter()
+            .rev()
+            .fold(Self::null(), |cdr, car| Self::cons(car, cdr))
+    }
+
+    /// Whether the value is the empty list.
+    pub fn is_null(&self) -> bool {
+        matches!(self, Val::Quot(Exp::List(ls, _)) if ls.is_empty())
+    }
+
+    /// Whether the value is a pair, including non empty quoted lists.
+    pub fn is_pair(&self) -> bool {
+        match self {
+            Val::Pair(_, _) => true,
+            Val::Quot(Exp::List(ls, _)) => !ls.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Whether the value is a lambda or a continuation, or contains one within
+    /// its pairs, which matters because they hold references to environments.
+    pub fn has_lamb(&self) -> bool {
+        match self {
+            Val::Lamb(_, _) | Val::Cont(_) | Val::Closure(_, _) | Val::VmCont(_) => true,
+            Val::Pair(_, has_lamb) => *has_lamb,
+            Val::Cond(c) => c.irritants.has_lamb(),
+            Val::Macro(m) => matches!(&**m, Macro::Procedure(f) if f.has_lamb()),
+            _ => false,
+        }
+    }
+}
+
+impl<Bool: Clone, Numb: Clone, Symb: Clone, Env: Clone, BuiltIn: Clone>
+    Val<Bool, Numb, Symb, Env, BuiltIn>
+{
+    /// Returns the first and second values of a pair, including non empty quoted
+    /// lists, or the given value back otherwise.
+    pub fn uncons(self) -> Result<(Self, Self), Self> {
+        match self {
+            Val::Pair(p, _) => match Rc::try_unwrap(p) {
+                Ok(mut p) => Ok((
+                    mem::replace(&mut p.0, Val::Void()),
+                    mem::replace(&mut p.1, Val::Void()),
+                )),
+                Err(p) => Ok((p.0.clone(), p.1.clone())),
+            },
+            // The expressions are only copied if shared, so that the rest of
+            // a list is taken without copying them again.
+            Val::Quot(Exp::List(ls, _)) => {
+                let mut ls = Rc::unwrap_or_clone(ls);
+                match ls.pop() {
+                    Some(car) => Ok((Self::quoted(car), Val::Quot(Exp::List(Rc::new(ls), None)))),
+                    None => Err(Val::null()),
+                }
+            }
+            v => Err(v),
+        }
+    }
+
+    /// Returns the values of a list, or the given value back if it is not a list.
+    pub fn into_vec(self) -> Result<Vec<Self>, Self> {
+        let mut vs = Vec::new();
+        let mut current = self;
+        loop {
+            if current.is_null() {
+                return Ok(vs);
+            }
+
+            match current.uncons() {
+                Ok((car, cdr)) => {
+                    vs.push(car);
+                    current = cdr;
+                }
+                Err(v) => {
+                    return Err(vs
+                        .into_iter()
+                        .rev()
+                        .fold(v, |cdr, car| Self::cons(car, cdr)))
+                }
+            }
+        }
+    }
+
+    /// Returns the expression that a value represents as data, if any, which
+    /// is not the case of procedures and void, and is a dotted list for pairs
+    /// that do not end with the empty list.
+    pub fn into_exp(self) -> Option<Exp<Bool, Numb, Symb>> {
+        match self {
+            Val::Numb(n) => Some(Exp::Numb(n, None)),
+            Val::Bool(b) => Some(Exp::Bool(b, None)),
+            Val::Str(s) => Some(Exp::Str(s, None)),
+            Val::Quot(e) => Some(e),
+            Val::Pair(_, _) => {
+                let mut ls = Vec::new();
+                let mut current = self;
+                loop {
+                    if current.is_null() {
+                        ls.reverse();
+                        return Some(Exp::List(Rc::new(ls), None));
+                    }
+
+                    match current.uncons() {
+                        Ok((car, cdr)) => {
+                            ls.push(car.into_exp()?);
+                            current = cdr;
+                        }
+                        Err(tail) => {
+                            ls.reverse();
+                            let tail = Box::new(tail.into_exp()?);
+                            return Some(Exp::Dotted(Rc::new(ls), tail, None));
+                        }
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
 }
 
-impl<Bool: Into<bool>, Numb, Symb, Env, BuiltIn> Into<bool>
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env> for Val<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Traces the environment of lambdas, and those of the continuations, conditions,
+    /// macros, pairs and frames of the virtual machine that hold any.
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        // The second values of a list are traced in a loop, as lists can be
+        // longer than what recursion allows.
+        let mut current = self;
+        loop {
+            match current {
+                Val::Lamb(_, at) => tracer.env(*at),
+                Val::Cont(k) => k.trace(tracer),
+                Val::Closure(_, frame) => frame.trace(tracer),
+                Val::VmCont(k) => k.trace(tracer),
+                Val::Cond(c) if tracer.visit(c) => c.irritants.trace(tracer),
+                Val::Macro(m) => match &**m {
+                    Macro::Rules(_, at) => tracer.env(*at),
+                    Macro::Procedure(f) => f.trace(tracer),
+                },
+                Val::Pair(p, true) if tracer.visit(p) => {
+                    p.0.trace(tracer);
+                    current = &p.1;
+                    continue;
+                }
+                _ => (),
+            }
+
+            return;
+        }
+    }
+}
+
+impl<Bool: Debug, Numb: Debug, Symb: Debug, Env: Debug, BuiltIn> Debug
     for Val<Bool, Numb, Symb, Env, BuiltIn>
 {
-    fn into(self) -> bool {
+    /// Formats data as they are, and procedures, macros, ports and conditions
+    /// without their contents, as they can only be printed with the symbols.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Val::Void() => f.write_str("Void()"),
+            Val::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Val::Numb(n) => f.debug_tuple("Numb").field(n).finish(),
+            Val::Str(s) => f.debug_tuple("Str").field(s).finish(),
+            Val::Quot(e) => f.debug_tuple("Quot").field(e).finish(),
+            Val::Lamb(_, at) => f.debug_tuple("Lamb").field(at).finish_non_exhaustive(),
+            Val::BuiltIn(_) => f.debug_tuple("BuiltIn").finish_non_exhaustive(),
+            Val::Cont(_) => f.debug_tuple("Cont").finish_non_exhaustive(),
+            Val::Closure(_, _) => f.debug_tuple("Closure").finish_non_exhaustive(),
+            Val::VmCont(_) => f.debug_tuple("VmCont").finish_non_exhaustive(),
+            Val::Macro(_) => f.debug_tuple("Macro").finish_non_exhaustive(),
+            Val::Port(_) => f.debug_tuple("Port").finish_non_exhaustive(),
+            Val::Cond(c) => f
+                .debug_tuple("Cond")
+                .field(&c.kind)
+                .field(&c.message)
+                .finish_non_exhaustive(),
+            Val::Pair(p, _) => {
+                // The first values of a list are formatted in a loop, as lists
+                // can be longer than what recursion allows.
+                let mut firsts = vec![&p.0];
+                let mut rest = &p.1;
+                while let Val::Pair(p, _) = rest {
+                    firsts.push(&p.0);
+                    rest = &p.1;
+                }
+
+                f.debug_tuple("Pair").field(&firsts).field(rest).finish()
+            }
+        }
+    }
+}
+
+impl<Bool: Into<bool>, Numb, Symb, Env, BuiltIn> From<Val<Bool, Numb, Symb, Env, BuiltIn>>
+    for bool
+{
+    fn from(val: Val<Bool, Numb, Symb, Env, BuiltIn>) -> Self {
+        match val {
             Val::Bool(b) => b.into(),
             _ => true,
         }
     }
 }
 
-impl<Bool: Into<bool>, Numb: Display, Symb: Copy, Symbs: Symbols<Symb = Symb>, Env, BuiltIn>
-    PrintWithSymbols<Symbs> for Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>
+impl<
+        Bool: Into<bool> + Clone,
+        Numb: Display + Clone,
+        Symb: Copy,
+        Symbs: Symbols<Symb = Symb>,
+        Env: Clone,
+        BuiltIn: Clone,
+    > PrintWithSymbols<Symbs> for Val<Bool, Numb, Symbs::Symb, Env, BuiltIn>
 {
     fn print_with(self, symbols: &Symbs) -> Result<String, PrintError<Symbs::Symb>> {
         match self {
-            Val::Void() => Ok(format!("#<void>")),
-            Val::Bool(b) => Ok(format!("{}", if b.into() { "#t" } else { "#f" })),
+            Val::Void() => Ok("#<void>".to_string()),
+            Val::Bool(b) => Ok((if b.into() { "#t" } else { "#f" }).to_string()),
             Val::Numb(n) => Ok(format!("{n}")),
-            Val::Lamb(_, _, _) => Ok(format!("#<procedure>")),
-            Val::BuiltIn(_) => Ok(format!("#<procedure>")),
+            Val::Str(s) => Ok(escape_str(&s)),
+            Val::Lamb(_, _) => Ok("#<procedure>".to_string()),
+            Val::BuiltIn(_) => Ok("#<procedure>".to_string()),
+            Val::Closure(_, _) => Ok("#<procedure>".to_string()),
+            Val::Cont(_) | Val::VmCont(_) => Ok("#<continuation>".to_string()),
+            Val::Macro(_) => Ok("#<macro>".to_string()),
+            Val::Port(_) => Ok("#<output-port>".to_string()),
+            Val::Cond(c) => {
+                let kind = symbols
+                    .resolve(c.kind)
+                    .ok_or(PrintError::UnknownSymbol(c.kind))?;
+                let mut ss = vec![format!("#<{kind}: {}", c.message)];
+                for v in c.irritants.clone().into_vec().unwrap_or_default() {
+                    ss.push(v.print_with(symbols)?);
+                }
+
+                Ok(format!("{}>", ss.join(" ")))
+            }
             Val::Quot(e) => e.print_with(symbols).map(|s| format!("'{s}")),
+            Val::Pair(p, _) => print_pair(&p, symbols).map(|s| format!("'{s}")),
+        }
+    }
+}
+
+/// Prints the values of a pair as in a quoted list, so that no quote is
+/// printed for the inner values.
+fn print_pair<
+    Bool: Into<bool> + Clone,
+    Numb: Display + Clone,
+    Symb: Copy,
+    Symbs: Symbols<Symb = Symb>,
+    Env: Clone,
+    BuiltIn: Clone,
+>(
+    p: &Cons<Bool, Numb, Symb, Env, BuiltIn>,
+    symbols: &Symbs,
+) -> Result<String, PrintError<Symb>> {
+    let print_datum = |v: &Val<Bool, Numb, Symb, Env, BuiltIn>| match v {
+        Val::Quot(e) => e.clone().print_with(symbols),
+        Val::Pair(p, _) => print_pair(p, symbols),
+        v => v.clone().print_with(symbols),
+    };
+
+    let mut ss = vec![print_datum(&p.0)?];
+    let mut current = &p.1;
+    loop {
+        match current {
+            Val::Pair(p, _) => {
+                ss.push(print_datum(&p.0)?);
+                current = &p.1;
+            }
+            Val::Quot(Exp::List(ls, _)) => {
+                for e in ls.iter().rev() {
+                    ss.push(e.clone().print_with(symbols)?);
+                }
+
+                return Ok(format!("({})", ss.join(" ")));
+            }
+            v => return Ok(format!("({} . {})", ss.join(" "), print_datum(v)?)),
         }
     }
 }
@@ -0,0 +1,1099 @@
+use std::{hash::Hash, marker::PhantomData, mem, rc::Rc};
+
+use crate::syntax::{exp::Exp, span::Span, symb::Symbols};
+
+use super::{
+    built_in::{Control, EvalBuiltIn},
+    bytecode::{Code, FrameRef, Instr},
+    compile::Scope,
+    condition::raised,
+    cont::{transitions, Catch, Handler, Handlers, Winder, Winders},
+    convert::IntoBuiltIn,
+    env::{Environments, Trace, Tracer},
+    err::RuntimeError,
+    eval::{is_datum, is_false, quoted_value, DEFAULT_MAX_DEPTH},
+    limits::Limits,
+    macros::{data, expand, unrenamed, Macro, Renames},
+    port::OutputPort,
+    quasi::build,
+    res::EvalResult,
+    val::Val,
+};
+
+/// A virtual machine that compiles Risp expressions to bytecode to execute
+/// them, with the capability of mutating some symbols and an environment,
+/// whose root holds the global variables.
+///
+/// It is an alternative to the `Evaluator`, with the same values, built-in
+/// procedures and prelude.
+pub struct Vm<'a, Val, Symbs: Symbols, Envs: Environments<Symbs::Symb, Val>> {
+    pub(super) symbols: &'a mut Symbs,
+    pub(super) environment: &'a mut Envs,
+    redefine_top_level: bool,
+    max_depth: usize,
+    limits: Limits,
+    pub(super) renames: Renames<Symbs::Symb, usize>,
+    output: OutputPort,
+    val: PhantomData<Val>,
+}
+
+/// The rest of a computation of the virtual machine, as captured by `call/cc`,
+/// which can be resumed any number of times.
+#[derive(Clone)]
+pub struct Continuation<Bool, Numb, Symb, Env, BuiltIn> {
+    stack: Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    calls: Vec<Record<Bool, Numb, Symb, Env, BuiltIn>>,
+    winders: Winders<Bool, Numb, Symb, Env, BuiltIn>,
+    handlers: Handlers<Bool, Numb, Symb, Env, BuiltIn>,
+    output: OutputPort,
+}
+
+/// A record of the call stack, which awaits the value returned by the code
+/// or procedure above it.
+///
+/// The variants that are larger than the common ones are boxed, as a record is
+/// kept for each level of non tail recursion.
+#[derive(Clone)]
+enum Record<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Resumes some code at an instruction within a frame, pushing the value.
+    Code {
+        code: Rc<Code<Bool, Numb, Symb, Env, BuiltIn>>,
+        pc: usize,
+        frame: Option<FrameRef<Bool, Numb, Symb, Env, BuiltIn>>,
+    },
+    /// Discards the value of the `before` thunk of a `dynamic-wind`, to then
+    /// call its thunk and finally its `after` thunk.
+    WindBefore(
+        Box<(
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+        )>,
+    ),
+    /// Takes the value of the thunk of a `dynamic-wind`, to then call its `after` thunk.
+    WindAfter(Val<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Discards the value of a thunk called when resuming a continuation, to
+    /// then call the remaining reversed thunks, each with its winders, and
+    /// finally return a value with the winders of the continuation.
+    Wind(
+        Vec<(
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        )>,
+        Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        Box<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    ),
+    /// Takes the value of the thunk of `with-exception-handler`, to then
+    /// uninstall its handler.
+    WithHandler(),
+    /// Discards the value of a handler called for a non continuable error, to
+    /// then raise the error again to the enclosing handlers.
+    Reraise(Box<RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>),
+    /// Takes the value of a handler called by `raise-continuable`, to then
+    /// install again the handlers.
+    Continue(Handlers<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Takes the value of the body of a `guard`, whose clauses are the given
+    /// procedure, to then uninstall its handler. The body is abandoned down to
+    /// the given height of the operand stack if it raises an error.
+    Guard(Val<Bool, Numb, Symb, Env, BuiltIn>, usize),
+    /// Takes the value raised within the body of a `guard`, to then call its
+    /// clauses with it.
+    Catch(
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        Box<RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>,
+    ),
+    /// Takes the value of the clauses of a `guard`, which raise the error
+    /// again if none applies.
+    Caught(Box<RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>>),
+    /// Restores an output port after the thunk of `with-output-to-string`,
+    /// to return what the thunk wrote instead of its value.
+    Output(OutputPort),
+    /// Discards the value to return the given one.
+    Value(Val<Bool, Numb, Symb, Env, BuiltIn>),
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env>
+    for Continuation<Bool, Numb, Symb, Env, BuiltIn>
+{
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        self.stack.trace(tracer);
+        self.calls.trace(tracer);
+        self.winders.trace(tracer);
+        self.handlers.trace(tracer);
+    }
+}
+
+impl<Bool, Numb, Symb, Env: Copy, BuiltIn> Trace<Env> for Record<Bool, Numb, Symb, Env, BuiltIn> {
+    fn trace(&self, tracer: &mut Tracer<Env>) {
+        match self {
+            Record::Code { frame, .. } => frame.trace(tracer),
+            Record::WindBefore(thunks) => {
+                let (before, thunk, after) = &**thunks;
+                before.trace(tracer);
+                thunk.trace(tracer);
+                after.trace(tracer);
+            }
+            Record::WindAfter(v) | Record::Guard(v, _) | Record::Value(v) => v.trace(tracer),
+            Record::Wind(thunks, winders, v) => {
+                for (thunk, winders) in thunks {
+                    thunk.trace(tracer);
+                    winders.trace(tracer);
+                }
+
+                winders.trace(tracer);
+                v.trace(tracer);
+            }
+            Record::Continue(handlers) => handlers.trace(tracer),
+            Record::Catch(v, err) => {
+                v.trace(tracer);
+                err.trace(tracer);
+            }
+            Record::Reraise(err) | Record::Caught(err) => err.trace(tracer),
+            Record::WithHandler() | Record::Output(_) => (),
+        }
+    }
+}
+
+/// The state of an execution, which is the code being executed with its next
+/// instruction and its frame, the stack of operands and the call stack.
+struct Machine<Bool, Numb, Symb, Env, BuiltIn> {
+    code: Rc<Code<Bool, Numb, Symb, Env, BuiltIn>>,
+    pc: usize,
+    frame: Option<FrameRef<Bool, Numb, Symb, Env, BuiltIn>>,
+    stack: Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    calls: Vec<Record<Bool, Numb, Symb, Env, BuiltIn>>,
+    winders: Winders<Bool, Numb, Symb, Env, BuiltIn>,
+    handlers: Handlers<Bool, Numb, Symb, Env, BuiltIn>,
+}
+
+/// What the virtual machine does next.
+enum Step<Bool, Numb, Symb, Env, BuiltIn> {
+    /// Executes the current code from its next instruction.
+    Run(),
+    /// Returns a value to the innermost record of the call stack.
+    Return(Val<Bool, Numb, Symb, Env, BuiltIn>),
+    /// Applies a procedure to some values in place of the current code.
+    Apply(
+        Val<Bool, Numb, Symb, Env, BuiltIn>,
+        Vec<Val<Bool, Numb, Symb, Env, BuiltIn>>,
+    ),
+    /// Fails with an error, which is raised if there is any handler.
+    Fail(RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>),
+    /// Passes an error to the innermost handler, which may return for a
+    /// continuable one.
+    Raise(
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>,
+        bool,
+    ),
+}
+
+impl<
+        'a,
+        Bool: Into<bool> + From<bool> + PartialEq + Clone,
+        Numb: PartialEq + Clone,
+        Symb: Eq + Hash + Copy,
+        Env: Eq + Copy,
+        Symbs: Symbols<Symb = Symb>,
+        Envs: Environments<
+            Symb,
+            Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+            Env = Env,
+        >,
+    > Vm<'a, Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>, Symbs, Envs>
+{
+    /// Creates a new `Vm` with the given symbols and environment.
+    pub fn new(symbols: &'a mut Symbs, environment: &'a mut Envs) -> Self {
+        Self {
+            symbols,
+            environment,
+            redefine_top_level: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            limits: Limits::new(),
+            renames: Renames::new(),
+            output: OutputPort::stdout(),
+            val: PhantomData,
+        }
+    }
+
+    /// Allows or forbids `define` to overwrite an existing global variable,
+    /// which is forbidden by default.
+    pub fn with_top_level_redefinition(mut self, allow: bool) -> Self {
+        self.redefine_top_level = allow;
+        self
+    }
+
+    /// Sets the maximum number of records in the call stack, beyond which the
+    /// evaluation fails, which is `DEFAULT_MAX_DEPTH` by default.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the port where `display` and the like write, which is the
+    /// standard output by default.
+    pub fn with_output(mut self, output: OutputPort) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Shares the symbols that the expansions of macros renamed with other
+    /// virtual machines, which are known by the number of scopes where the
+    /// macro is defined.
+    pub fn with_renames(mut self, renames: Renames<Symb, usize>) -> Self {
+        self.renames = renames;
+        self
+    }
+
+    /// Sets the bounds of the executions, which are unbounded by default,
+    /// where the fuel is shared by the executions of the `Vm` and counts the
+    /// procedures that are applied.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns the bounds of the executions, with the fuel that remains.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Returns the symbols of the virtual machine, which can grow during
+    /// compilation and evaluation.
+    pub fn symbols(&self) -> &Symbs {
+        self.symbols
+    }
+
+    /// Defines a built-in procedure in the root environment from a Rust
+    /// function, whose arguments and result are converted from and into values.
+    pub fn register<Args>(
+        &mut self,
+        name: &str,
+        f: impl IntoBuiltIn<Args, Bool, Numb, Symb, Env, Symbs>,
+    ) -> Result<
+        (),
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    > {
+        let x = self.symbols.get_or_store(name);
+        let v = Val::BuiltIn(f.into_built_in(name));
+        self.define(x, v)
+    }
+
+    /// Tries to compile an `Exp` and execute it into a `Val`.
+    pub fn eval(
+        &mut self,
+        exp: Exp<Bool, Numb, Symb>,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        // The forms of a `begin` at the top level are compiled and executed in
+        // order, so that each of them can use the macros defined by the previous ones.
+        if let Some(mut forms) = self.top_level_begin(&exp) {
+            let mut v = Val::Void();
+            while let Some(e) = forms.pop() {
+                v = self.eval(e)?;
+            }
+
+            return Ok(v);
+        }
+
+        let code = self.compile(exp, Vec::new());
+        self.run(Machine::new(code), Step::Run())
+    }
+
+    /// Tries to apply a procedure to some values.
+    pub fn apply(
+        &mut self,
+        f: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        self.run(Machine::new(Rc::new(Code::empty())), Step::Apply(f, args))
+    }
+
+    /// Returns the reversed forms of an expression if it is a `begin`.
+    fn top_level_begin(&self, exp: &Exp<Bool, Numb, Symb>) -> Option<Vec<Exp<Bool, Numb, Symb>>> {
+        match exp {
+            Exp::List(ls, _) => match ls.split_last() {
+                Some((&Exp::Symb(s, _), forms))
+                    if self.global(s).is_none() && self.symbols.resolve(s) == Some("begin") =>
+                {
+                    Some(forms.to_vec())
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Takes steps from the first one until the result is known.
+    fn run(
+        &mut self,
+        mut m: Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        mut step: Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        // The output is redirected only until the evaluation ends, even if it
+        // ends with an error.
+        let output = self.output.clone();
+        loop {
+            step = match step {
+                Step::Run() => self.exec(&mut m),
+                Step::Return(v) => match m.calls.pop() {
+                    Some(record) => self.resume(&mut m, record, v),
+                    None => {
+                        self.output = output;
+                        return Ok(v);
+                    }
+                },
+                Step::Apply(f, args) => self.apply_procedure(&mut m, f, args),
+                Step::Fail(err) => {
+                    let err = err.located(m.span());
+                    if m.handlers.is_none() || err.is_limit() {
+                        self.output = output;
+                        return Err(err);
+                    }
+
+                    Step::Raise(err, false)
+                }
+                Step::Raise(err, continuable) => match m.handlers.clone() {
+                    Some(handler) => self.raise(&mut m, &handler, err, continuable),
+                    None => {
+                        self.output = output;
+                        return Err(err.located(m.span()));
+                    }
+                },
+            }
+        }
+    }
+
+    /// Executes the instructions of the current code until it calls, returns
+    /// or fails.
+    fn exec(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let code = m.code.clone();
+        loop {
+            let instr = &code.instrs[m.pc];
+            m.pc += 1;
+            match instr {
+                Instr::Const(v) => m.stack.push(v.clone()),
+                Instr::Local(depth, i, x) => match m.local(*depth, *i) {
+                    Some(v) => m.stack.push(v),
+                    None => return Step::Fail(RuntimeError::UndefinedVariable(*x)),
+                },
+                Instr::Global(x) => match self.global(*x) {
+                    Some(v) => m.stack.push(v.clone()),
+                    None => return Step::Fail(RuntimeError::UndefinedVariable(*x)),
+                },
+                Instr::Named(x) => match m.named(*x) {
+                    Some(v) => m.stack.push(v),
+                    None => match self.global(*x) {
+                        Some(v) => m.stack.push(v.clone()),
+                        None => return Step::Fail(RuntimeError::UndefinedVariable(*x)),
+                    },
+                },
+                Instr::Operator(x, operands, to) if operands.named && m.named(*x).is_some() => {
+                    m.stack.extend(m.named(*x));
+                }
+                Instr::Operator(x, operands, to) => match self.global(*x) {
+                    Some(Val::Macro(mac)) => {
+                        // A macro defined after the code was compiled is
+                        // expanded when the application is evaluated.
+                        let mac = mac.clone();
+                        m.pc = *to;
+                        let (ls, span) = (operands.ls.clone(), operands.span.clone());
+                        return match self.expansion(&mac, *x, ls, span, 0) {
+                            Ok(v) => self.eval_data(m, v, &operands.scopes),
+                            Err(err) => Step::Fail(err),
+                        };
+                    }
+                    Some(v) => m.stack.push(v.clone()),
+                    None => return Step::Fail(RuntimeError::UnknownExpression(*x)),
+                },
+                Instr::Step() => {
+                    if let Err(err) = self.limits.step() {
+                        return Step::Fail(err);
+                    }
+                }
+                Instr::DefineLocal(i, x) => {
+                    let v = m.pop();
+                    if !m.define(*i, v) {
+                        return Step::Fail(RuntimeError::AlreadyDefined(*x));
+                    }
+
+                    m.stack.push(Val::Void());
+                }
+                Instr::DefineGlobal(x) => {
+                    let v = m.pop();
+                    if let Err(err) = self.define(*x, v) {
+                        return Step::Fail(err);
+                    }
+
+                    m.stack.push(Val::Void());
+                }
+                Instr::SetLocal(depth, i, x) => {
+                    let v = m.pop();
+                    if !m.set(*depth, *i, v) {
+                        return Step::Fail(RuntimeError::UndefinedVariable(*x));
+                    }
+
+                    m.stack.push(Val::Void());
+                }
+                Instr::DefineNamed(x) => {
+                    let v = m.pop();
+                    if !m.frame.as_ref().is_some_and(|f| f.define_named(*x, v)) {
+                        return Step::Fail(RuntimeError::AlreadyDefined(*x));
+                    }
+
+                    m.stack.push(Val::Void());
+                }
+                Instr::SetNamed(x) => {
+                    let v = m.pop();
+                    let set = match m.frame.as_ref().and_then(|f| f.named(*x)) {
+                        Some((depth, i)) => m.set(depth, i, v),
+                        None => self.set(*x, v).is_ok(),
+                    };
+                    if !set {
+                        return Step::Fail(RuntimeError::UndefinedVariable(*x));
+                    }
+
+                    m.stack.push(Val::Void());
+                }
+                Instr::SetGlobal(x) => {
+                    let v = m.pop();
+                    if self.set(*x, v).is_err() {
+                        return Step::Fail(RuntimeError::UndefinedVariable(*x));
+                    }
+
+                    m.stack.push(Val::Void());
+                }
+                Instr::Lambda(code) => m.stack.push(Val::Closure(code.clone(), m.frame.clone())),
+                Instr::Applicable(n) => match m.stack.last() {
+                    Some(Val::Closure(code, _)) if !code.accepts(*n) => {
+                        return Step::Fail(RuntimeError::ArityMismatch())
+                    }
+                    Some(Val::BuiltIn(f)) if !f.arity().accepts(*n) => {
+                        return Step::Fail(RuntimeError::ArityMismatch())
+                    }
+                    Some(Val::Closure(_, _) | Val::BuiltIn(_) | Val::VmCont(_)) => {}
+                    _ => return Step::Fail(RuntimeError::NotAProcedure(m.pop())),
+                },
+                Instr::Call(n) => {
+                    let args = m.stack.split_off(m.stack.len() - n);
+                    let f = m.pop();
+                    // Built-in procedures are applied right away, as they do
+                    // not need a record to return, unless they act on the
+                    // continuation.
+                    match &f {
+                        Val::BuiltIn(f) if f.control().is_none() => {
+                            match f.apply(args, self.symbols, &self.output) {
+                                Ok(v) => m.stack.push(v),
+                                Err(err) => return Step::Fail(err),
+                            }
+
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    if let Err(err) = m.suspend(self.max_depth) {
+                        return Step::Fail(err);
+                    }
+
+                    return Step::Apply(f, args);
+                }
+                Instr::TailCall(n) => {
+                    let args = m.stack.split_off(m.stack.len() - n);
+                    return Step::Apply(m.pop(), args);
+                }
+                Instr::Return() => return Step::Return(m.pop()),
+                Instr::Pop() => {
+                    m.stack.pop();
+                }
+                Instr::Swap() => {
+                    let n = m.stack.len();
+                    m.stack.swap(n - 1, n - 2);
+                }
+                Instr::Jump(to) => m.pc = *to,
+                Instr::JumpIfFalse(to) => {
+                    if is_false(&m.pop()) {
+                        m.pc = *to;
+                    }
+                }
+                Instr::JumpIfFalseOrPop(to) => {
+                    if m.stack.last().is_some_and(is_false) {
+                        m.pc = *to;
+                    } else {
+                        m.stack.pop();
+                    }
+                }
+                Instr::JumpIfTrueOrPop(to) => {
+                    if m.stack.last().is_some_and(|v| !is_false(v)) {
+                        m.pc = *to;
+                    } else {
+                        m.stack.pop();
+                    }
+                }
+                Instr::KeepOrJump(to) => {
+                    if m.stack.last().is_some_and(is_false) {
+                        m.stack.pop();
+                        m.pc = *to;
+                    }
+                }
+                Instr::Default(i, to) => {
+                    if m.local(0, *i).is_some() {
+                        m.pc = *to;
+                    }
+                }
+                Instr::Enter(size, n) => {
+                    let vs = m.stack.split_off(m.stack.len() - n);
+                    m.frame = Some(FrameRef::new(*size, vs, m.frame.take()));
+                }
+                Instr::Leave(k) => {
+                    for _ in 0..*k {
+                        m.frame = m.frame.as_ref().and_then(|f| f.parent.clone());
+                    }
+                }
+                Instr::Quasiquote(e, n) => {
+                    let vs = m.stack.split_off(m.stack.len() - n);
+                    match build(e.clone(), 1, self.symbols, &mut vs.into_iter()) {
+                        Ok(v) => m.stack.push(v),
+                        Err(err) => return Step::Fail(err),
+                    }
+                }
+                Instr::Case(data, to) => {
+                    if m.stack
+                        .last()
+                        .is_some_and(|key| data.iter().any(|d| is_datum(key, d)))
+                    {
+                        m.stack.pop();
+                    } else {
+                        m.pc = *to;
+                    }
+                }
+                Instr::Macro() => {
+                    let f = m.pop();
+                    m.stack.push(Val::Macro(Rc::new(Macro::Procedure(f))));
+                }
+                Instr::Expand(depth, i, x, operands) => match m.local(*depth, *i) {
+                    Some(Val::Macro(mac)) => {
+                        let (ls, span) = (operands.ls.clone(), operands.span.clone());
+                        return match self.expansion(&mac, *x, ls, span, 0) {
+                            Ok(v) => self.eval_data(m, v, &operands.scopes),
+                            Err(err) => Step::Fail(err),
+                        };
+                    }
+                    _ => return Step::Fail(RuntimeError::UndefinedVariable(*x)),
+                },
+                Instr::Eval(scopes) => {
+                    let v = m.pop();
+                    return self.eval_data(m, v, scopes);
+                }
+                Instr::Macroexpand(repeat, scopes) => {
+                    let v = m.pop();
+                    match self.macroexpand(v, *repeat, scopes, m.frame.as_ref()) {
+                        Ok(v) => m.stack.push(v),
+                        Err(err) => return Step::Fail(err),
+                    }
+                }
+                Instr::Guard(body) => {
+                    let clauses = m.pop();
+                    if let Err(err) = m.suspend(self.max_depth) {
+                        return Step::Fail(err);
+                    }
+
+                    m.handlers = Some(Rc::new(Handler {
+                        catch: Catch::Guard(m.calls.len(), m.winders.clone()),
+                        parent: m.handlers.take(),
+                    }));
+
+                    m.calls.push(Record::Guard(clauses, m.stack.len()));
+                    (m.code, m.pc) = (body.clone(), 0);
+                    return Step::Run();
+                }
+                Instr::Reraise() => match m.calls.pop() {
+                    Some(Record::Caught(err)) => return Step::Fail(*err),
+                    _ => return Step::Fail(RuntimeError::InvalidArguments()),
+                },
+                Instr::Fail(err) => return Step::Fail(err.clone()),
+            }
+        }
+    }
+
+    /// Resumes the execution awaiting in a record with the returned value.
+    fn resume(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        record: Record<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match record {
+            Record::Code { code, pc, frame } => {
+                (m.code, m.pc, m.frame) = (code, pc, frame);
+                m.stack.push(v);
+                Step::Run()
+            }
+            Record::WindBefore(thunks) => {
+                let (before, thunk, after) = *thunks;
+                let depth = m.winders.as_ref().map_or(0, |w| w.depth) + 1;
+                m.winders = Some(Rc::new(Winder {
+                    before,
+                    after: after.clone(),
+                    parent: m.winders.take(),
+                    depth,
+                }));
+
+                m.calls.push(Record::WindAfter(after));
+                Step::Apply(thunk, Vec::new())
+            }
+            Record::WindAfter(after) => {
+                m.winders = m.winders.as_ref().and_then(|w| w.parent.clone());
+                m.calls.push(Record::Value(v));
+                Step::Apply(after, Vec::new())
+            }
+            Record::Wind(thunks, to, v) => m.rewind(thunks, to, *v),
+            Record::WithHandler() | Record::Guard(_, _) => {
+                m.handlers = m.handlers.as_ref().and_then(|h| h.parent.clone());
+                Step::Return(v)
+            }
+            Record::Reraise(err) => Step::Fail(*err),
+            Record::Continue(to) => {
+                m.handlers = to;
+                Step::Return(v)
+            }
+            Record::Catch(clauses, err) => {
+                m.calls.push(Record::Caught(err));
+                Step::Apply(clauses, vec![v])
+            }
+            Record::Caught(_) => Step::Return(v),
+            Record::Output(port) => {
+                let s = self.output.contents().unwrap_or_default();
+                self.output = port;
+                Step::Return(Val::Str(s.into()))
+            }
+            Record::Value(v) => Step::Return(v),
+        }
+    }
+
+    /// Applies a procedure to some values in place of the current code.
+    fn apply_procedure(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        f: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match f {
+            Val::BuiltIn(f) => match f.control() {
+                Some(control) => self.apply_control(m, control, args),
+                None => match f.apply(args, self.symbols, &self.output) {
+                    Ok(v) => Step::Return(v),
+                    Err(err) => Step::Fail(err),
+                },
+            },
+            Val::Closure(code, frame) => match FrameRef::invoke(&code, frame, args) {
+                Ok(frame) => {
+                    (m.code, m.pc, m.frame) = (code, 0, Some(frame));
+                    Step::Run()
+                }
+                Err(err) => Step::Fail(err),
+            },
+            Val::VmCont(k) => match <[_; 1]>::try_from(args) {
+                Ok([v]) => {
+                    // The current execution is abandoned for the one of the continuation.
+                    m.stack.clone_from(&k.stack);
+                    m.calls.clone_from(&k.calls);
+                    m.handlers.clone_from(&k.handlers);
+                    self.output = k.output.clone();
+                    m.wind(k.winders.clone(), v)
+                }
+                Err(_) => Step::Fail(RuntimeError::ArityMismatch()),
+            },
+            v => Step::Fail(RuntimeError::NotAProcedure(v)),
+        }
+    }
+
+    /// Applies a built-in procedure that acts on the continuation.
+    fn apply_control(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        control: Control,
+        mut args: Vec<Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match (control, args.pop(), args.pop(), args.pop(), args.pop()) {
+            (Control::CallCc(), Some(f), None, None, None) => {
+                let k = Continuation {
+                    stack: m.stack.clone(),
+                    calls: m.calls.clone(),
+                    winders: m.winders.clone(),
+                    handlers: m.handlers.clone(),
+                    output: self.output.clone(),
+                };
+
+                Step::Apply(f, vec![Val::VmCont(Rc::new(k))])
+            }
+            (Control::DynamicWind(), Some(after), Some(thunk), Some(before), None) => {
+                m.calls
+                    .push(Record::WindBefore(Box::new((before.clone(), thunk, after))));
+                Step::Apply(before, Vec::new())
+            }
+            (Control::RaiseContinuable(), Some(v), None, None, None) => {
+                Step::Raise(RuntimeError::Raised(v), true)
+            }
+            (Control::WithExceptionHandler(), Some(thunk), Some(handler), None, None) => {
+                m.handlers = Some(Rc::new(Handler {
+                    catch: Catch::Procedure(handler),
+                    parent: m.handlers.take(),
+                }));
+
+                m.calls.push(Record::WithHandler());
+                Step::Apply(thunk, Vec::new())
+            }
+            // The local macros are only known when compiling, where a direct
+            // application of `macroexpand` is compiled with them, while the
+            // code keeps them for the other applications.
+            (Control::Macroexpand(repeat), Some(v), None, None, None) => {
+                let code = m.code.clone();
+                match self.macroexpand(v, repeat, code.scopes_before(m.pc), m.frame.as_ref()) {
+                    Ok(v) => Step::Return(v),
+                    Err(err) => Step::Fail(err),
+                }
+            }
+            (Control::WithOutputToString(), Some(thunk), None, None, None) => {
+                let port = mem::replace(&mut self.output, OutputPort::string());
+                m.calls.push(Record::Output(port));
+                Step::Apply(thunk, Vec::new())
+            }
+            _ => Step::Fail(RuntimeError::ArityMismatch()),
+        }
+    }
+
+    /// Passes an error to a handler, which is the innermost one.
+    fn raise(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        handler: &Handler<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        err: RuntimeError<
+            Symb,
+            Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        >,
+        continuable: bool,
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        let v = raised(&err, self.symbols);
+        match &handler.catch {
+            Catch::Procedure(f) => {
+                // The handler is called with the enclosing handlers, and
+                // regardless of the depth limit so that it can handle it.
+                let record = if continuable {
+                    Record::Continue(m.handlers.take())
+                } else {
+                    Record::Reraise(Box::new(err))
+                };
+
+                m.calls.push(record);
+                m.handlers.clone_from(&handler.parent);
+                Step::Apply(f.clone(), vec![v])
+            }
+            Catch::Guard(i, to) => {
+                // The execution is abandoned down to the guard, restoring the
+                // output as it was before the outermost abandoned redirection.
+                for record in m.calls.drain(i + 1..) {
+                    if let Record::Output(port) = record {
+                        self.output = port;
+                        break;
+                    }
+                }
+
+                if let Some(Record::Guard(clauses, height)) = m.calls.pop() {
+                    m.stack.truncate(height);
+                    m.calls.push(Record::Catch(clauses, Box::new(err)));
+                }
+
+                m.handlers.clone_from(&handler.parent);
+                m.wind(to.clone(), v)
+            }
+        }
+    }
+
+    /// Compiles an expression given as data to execute it within the current
+    /// frame, whose scopes are the given ones, to then resume the current code.
+    fn eval_within(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        e: Exp<Bool, Numb, Symb>,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        if let Err(err) = m.suspend(self.max_depth) {
+            return Step::Fail(err);
+        }
+
+        (m.code, m.pc) = (self.compile(e, scopes.to_vec()), 0);
+        Step::Run()
+    }
+
+    /// Evaluates a value given as data as an expression within the current
+    /// frame, whose scopes are the given ones, to then resume the current code
+    /// with its value, which is the value itself if it is not an expression.
+    fn eval_data(
+        &mut self,
+        m: &mut Machine<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+    ) -> Step<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match v {
+            Val::Quot(e) => self.eval_within(m, e, scopes),
+            v @ Val::Pair(_, _) => match quoted_value(&v, self.symbols) {
+                Some(v) => {
+                    m.stack.push(v);
+                    Step::Run()
+                }
+                None => match v.into_exp() {
+                    Some(e) => self.eval_within(m, e, scopes),
+                    None => Step::Fail(RuntimeError::InvalidArguments()),
+                },
+            },
+            v => {
+                m.stack.push(v);
+                Step::Run()
+            }
+        }
+    }
+
+    /// Expands a form given as data once, or until it is no longer the use of
+    /// a macro, to return the expansion as data without evaluating it.
+    fn macroexpand(
+        &mut self,
+        mut v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        repeat: bool,
+        scopes: &[Scope<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>],
+        frame: Option<&FrameRef<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        loop {
+            let (mut ls, span) = match v.clone().into_exp() {
+                Some(Exp::List(ls, span)) => (Rc::unwrap_or_clone(ls), span),
+                _ => return Ok(v),
+            };
+
+            let (s, m, defined) = match ls.last() {
+                Some(&Exp::Symb(s, _)) => match self.macro_of(scopes, frame, s) {
+                    Some((m, defined)) => (s, m, defined),
+                    None => return Ok(v),
+                },
+                _ => return Ok(v),
+            };
+
+            ls.pop();
+            v = self.expansion(&m, s, ls, span, defined)?;
+            if !repeat {
+                return Ok(v);
+            }
+        }
+    }
+
+    /// Expands the reversed operands of a form whose operator is a macro, which
+    /// is defined within the given number of scopes, into the expression to
+    /// evaluate in its place, given as data.
+    pub(super) fn expansion(
+        &mut self,
+        m: &Macro<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+        s: Symb,
+        ls: Vec<Exp<Bool, Numb, Symb>>,
+        span: Option<Span>,
+        defined: usize,
+    ) -> EvalResult<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>> {
+        match m {
+            Macro::Rules(rules, _) => {
+                match expand(rules, ls, span, self.symbols, &self.renames, defined) {
+                    Some(e) => Ok(Val::quoted(e)),
+                    None => Err(RuntimeError::BadFormedExpression(s)),
+                }
+            }
+            Macro::Procedure(f) => self.apply(f.clone(), data(ls)),
+        }
+    }
+
+    /// Defines a global variable, which may overwrite an existing one.
+    fn define(
+        &mut self,
+        x: Symb,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> Result<
+        (),
+        RuntimeError<Symb, Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>>,
+    > {
+        let root = self.environment.root();
+        match self.environment.define(root, x, v) {
+            Ok(()) => Ok(()),
+            Err((x, v)) if self.redefine_top_level => match self.environment.set(root, &x, v) {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RuntimeError::AlreadyDefined(x)),
+            },
+            Err((x, _)) => Err(RuntimeError::AlreadyDefined(x)),
+        }
+    }
+
+    /// Returns the value of a global variable, or of the symbol that a macro
+    /// renamed into it if not defined.
+    pub(super) fn global(
+        &self,
+        x: Symb,
+    ) -> Option<&Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        match self.environment.get(self.environment.root(), &x) {
+            Some(v) => Some(v),
+            None => self.global(unrenamed(x, self.symbols)?),
+        }
+    }
+
+    /// Assigns a value to a global variable, or to the symbol that a macro
+    /// renamed into it if not defined.
+    fn set(
+        &mut self,
+        x: Symb,
+        v: Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>,
+    ) -> Result<(), Val<Bool, Numb, Symb, Env, EvalBuiltIn<Bool, Numb, Symb, Env, Symbs>>> {
+        let root = self.environment.root();
+        match self.environment.set(root, &x, v) {
+            Err(v) => match unrenamed(x, self.symbols) {
+                Some(x) => self.set(x, v),
+                None => Err(v),
+            },
+            ok => ok,
+        }
+    }
+}
+
+impl<Bool: Clone, Numb: Clone, Symb: Copy, Env: Clone, BuiltIn: Clone>
+    Machine<Bool, Numb, Symb, Env, BuiltIn>
+{
+    /// Creates the state of an execution of some code at the top level.
+    fn new(code: Rc<Code<Bool, Numb, Symb, Env, BuiltIn>>) -> Self {
+        Self {
+            code,
+            pc: 0,
+            frame: None,
+            stack: Vec::new(),
+            calls: Vec::new(),
+            winders: None,
+            handlers: None,
+        }
+    }
+
+    /// Returns the location of the last executed instruction, if known.
+    fn span(&self) -> Option<Span> {
+        self.pc
+            .checked_sub(1)
+            .and_then(|i| self.code.spans.get(i).cloned().flatten())
+    }
+
+    /// Pops the value on top of the stack.
+    fn pop(&mut self) -> Val<Bool, Numb, Symb, Env, BuiltIn> {
+        // The compiled code always pushes the values that it pops.
+        self.stack.pop().unwrap_or(Val::Void())
+    }
+
+    /// Returns the value of the variable at a slot of a frame the given number
+    /// of levels up, if defined.
+    fn local(&self, depth: usize, i: usize) -> Option<Val<Bool, Numb, Symb, Env, BuiltIn>> {
+        let frame = self.frame.as_ref()?.up(depth)?;
+        let slots = frame.slots.borrow();
+        slots.get(i)?.clone()
+    }
+
+    /// Returns the value of the innermost variable of a name that the code
+    /// given to `eval` defined in a frame, if any.
+    fn named(&self, x: Symb) -> Option<Val<Bool, Numb, Symb, Env, BuiltIn>>
+    where
+        Symb: Eq,
+    {
+        let (depth, i) = self.frame.as_ref()?.named(x)?;
+        self.local(depth, i)
+    }
+
+    /// Defines the variable at a slot of the current frame, unless already
+    /// defined, which can grow for the definitions of an evaluated expression.
+    fn define(&mut self, i: usize, v: Val<Bool, Numb, Symb, Env, BuiltIn>) -> bool {
+        let Some(frame) = &self.frame else {
+            return false;
+        };
+
+        let mut slots = frame.slots.borrow_mut();
+        if slots.len() <= i {
+            slots.resize_with(i + 1, || None);
+        }
+
+        match &mut slots[i] {
+            Some(_) => false,
+            slot => {
+                *slot = Some(v);
+                true
+            }
+        }
+    }
+
+    /// Assigns a value to the variable at a slot of a frame the given number
+    /// of levels up, if defined.
+    fn set(&mut self, depth: usize, i: usize, v: Val<Bool, Numb, Symb, Env, BuiltIn>) -> bool {
+        let Some(frame) = self.frame.as_ref().and_then(|f| f.up(depth)) else {
+            return false;
+        };
+
+        match frame.slots.borrow_mut().get_mut(i) {
+            Some(Some(old)) => {
+                *old = v;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Pushes a record to resume the current code after a call, unless the
+    /// call stack already has the maximum number of records.
+    fn suspend(
+        &mut self,
+        max_depth: usize,
+    ) -> Result<(), RuntimeError<Symb, Val<Bool, Numb, Symb, Env, BuiltIn>>> {
+        if self.calls.len() >= max_depth {
+            return Err(RuntimeError::DepthLimitExceeded(max_depth));
+        }
+
+        self.calls.push(Record::Code {
+            code: self.code.clone(),
+            pc: self.pc,
+            frame: self.frame.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Calls the `after` thunks of the winders that are left and the `before`
+    /// thunks of the winders that are entered, to return a value with the
+    /// given winders.
+    fn wind(
+        &mut self,
+        to: Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Step<Bool, Numb, Symb, Env, BuiltIn> {
+        let thunks = transitions(&self.winders, &to);
+        self.rewind(thunks, to, v)
+    }
+
+    /// Calls the remaining reversed thunks, each with its winders, to then
+    /// return a value with the given winders.
+    fn rewind(
+        &mut self,
+        mut thunks: Vec<(
+            Val<Bool, Numb, Symb, Env, BuiltIn>,
+            Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        )>,
+        to: Winders<Bool, Numb, Symb, Env, BuiltIn>,
+        v: Val<Bool, Numb, Symb, Env, BuiltIn>,
+    ) -> Step<Bool, Numb, Symb, Env, BuiltIn> {
+        match thunks.pop() {
+            Some((thunk, during)) => {
+                self.winders = during;
+                self.calls.push(Record::Wind(thunks, to, Box::new(v)));
+                Step::Apply(thunk, Vec::new())
+            }
+            None => {
+                self.winders = to;
+                Step::Return(v)
+            }
+        }
+    }
+}
@@ -1,23 +1,39 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
 
 use super::{
     print::{PrintError, PrintWithSymbols},
+    span::Span,
     symb::Symbols,
 };
 
 /// A Risp expression.
-#[derive(Clone, PartialEq, Eq)]
+///
+/// Every expression keeps the `Span` where it was read, if any, which is
+/// ignored when comparing expressions, as is the resolution of symbols.
+#[derive(Clone, Debug)]
 pub enum Exp<Bool, Numb, Symb> {
     /// A number of type `Numb`.
-    Numb(Numb),
+    Numb(Numb, Option<Span>),
     /// A boolean of type `Bool`.
-    Bool(Bool),
+    Bool(Bool, Option<Span>),
+    /// A string.
+    Str(Rc<str>, Option<Span>),
     /// A symbol of type `Symb`.
-    Symb(Symb),
+    Symb(Symb, Option<Span>),
+    /// A symbol that refers to a local variable, resolved before evaluation
+    /// to the number of environments to go up from the current one and the
+    /// position of the variable within that environment.
+    Local(Symb, usize, usize, Option<Span>),
     /// A quoted expression.
-    Quot(Box<Self>),
-    /// A list, assumed to be reversed for the ease of its consumption.
-    List(Vec<Self>),
+    Quot(Box<Self>, Option<Span>),
+    /// A list, assumed to be reversed for the ease of its consumption, whose
+    /// expressions are shared by its clones, so that cloning code such as the
+    /// body of a lambda does not depend on its size.
+    List(Rc<Vec<Self>>, Option<Span>),
+    /// A list of quoted data whose last pair ends with an expression other
+    /// than the empty list, as in `(a b . c)`, with its reversed expressions
+    /// before the dot and the one after it.
+    Dotted(Rc<Vec<Self>>, Box<Self>, Option<Span>),
 }
 
 impl<Bool, Numb, Symb> Exp<Bool, Numb, Symb> {
@@ -25,7 +41,7 @@ impl<Bool, Numb, Symb> Exp<Bool, Numb, Symb> {
     /// corresponds to a number.
     pub fn numb(self) -> Option<Numb> {
         match self {
-            Exp::Numb(n) => Some(n),
+            Exp::Numb(n, _) => Some(n),
             _ => None,
         }
     }
@@ -34,7 +50,7 @@ impl<Bool, Numb, Symb> Exp<Bool, Numb, Symb> {
     /// corresponds to a boolean.
     pub fn bool(self) -> Option<Bool> {
         match self {
-            Exp::Bool(b) => Some(b),
+            Exp::Bool(b, _) => Some(b),
             _ => None,
         }
     }
@@ -43,30 +59,98 @@ impl<Bool, Numb, Symb> Exp<Bool, Numb, Symb> {
     /// corresponds to a symbol.
     pub fn symb(self) -> Option<Symb> {
         match self {
-            Exp::Symb(s) => Some(s),
+            Exp::Symb(s, _) | Exp::Local(s, _, _, _) => Some(s),
             _ => None,
         }
     }
 }
 
-impl<Bool: Into<bool>, Numb: Display, Symb: Copy, Symbs: Symbols<Symb = Symb>>
+impl<Bool, Numb, Symb> Exp<Bool, Numb, Symb> {
+    /// Returns the `Span` where a Risp expression was read, if known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Exp::Numb(_, span)
+            | Exp::Bool(_, span)
+            | Exp::Str(_, span)
+            | Exp::Symb(_, span)
+            | Exp::Local(_, _, _, span)
+            | Exp::Quot(_, span)
+            | Exp::List(_, span)
+            | Exp::Dotted(_, _, span) => span.clone(),
+        }
+    }
+}
+
+impl<Bool: PartialEq, Numb: PartialEq, Symb: PartialEq> PartialEq for Exp<Bool, Numb, Symb> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Exp::Numb(l, _), Exp::Numb(r, _)) => l == r,
+            (Exp::Bool(l, _), Exp::Bool(r, _)) => l == r,
+            (Exp::Str(l, _), Exp::Str(r, _)) => l == r,
+            (
+                Exp::Symb(l, _) | Exp::Local(l, _, _, _),
+                Exp::Symb(r, _) | Exp::Local(r, _, _, _),
+            ) => l == r,
+            (Exp::Quot(l, _), Exp::Quot(r, _)) => l == r,
+            (Exp::List(l, _), Exp::List(r, _)) => l == r,
+            (Exp::Dotted(l, l_tail, _), Exp::Dotted(r, r_tail, _)) => l == r && l_tail == r_tail,
+            _ => false,
+        }
+    }
+}
+
+impl<Bool: Eq, Numb: Eq, Symb: Eq> Eq for Exp<Bool, Numb, Symb> {}
+
+impl<Bool: Into<bool> + Clone, Numb: Display + Clone, Symb: Copy, Symbs: Symbols<Symb = Symb>>
     PrintWithSymbols<Symbs> for Exp<Bool, Numb, Symbs::Symb>
 {
     fn print_with(self, symbols: &Symbs) -> Result<String, PrintError<Symbs::Symb>> {
         match self {
-            Exp::Bool(b) => Ok(format!("{}", if b.into() { "#t" } else { "#f" })),
-            Exp::Numb(n) => Ok(format!("{n}")),
-            Exp::Symb(s) => match symbols.resolve(s) {
+            Exp::Bool(b, _) => Ok((if b.into() { "#t" } else { "#f" }).to_string()),
+            Exp::Numb(n, _) => Ok(format!("{n}")),
+            Exp::Str(s, _) => Ok(escape_str(&s)),
+            Exp::Symb(s, _) | Exp::Local(s, _, _, _) => match symbols.resolve(s) {
                 None => Err(PrintError::UnknownSymbol(s)),
-                Some(s) => Ok(format!("{s}")),
+                Some(s) => Ok(s.to_string()),
             },
-            Exp::Quot(b) => (*b).print_with(symbols).map(|s| format!("'{s}")),
-            Exp::List(ls) => ls
-                .into_iter()
+            Exp::Quot(b, _) => (*b).print_with(symbols).map(|s| format!("'{s}")),
+            Exp::List(ls, _) => ls
+                .iter()
                 .rev()
-                .map(|e| e.print_with(symbols))
+                .map(|e| e.clone().print_with(symbols))
                 .collect::<Result<Vec<String>, _>>()
                 .map(|ss| format!("({})", ss.join(" "))),
+            Exp::Dotted(ls, tail, _) => {
+                let ss = ls
+                    .iter()
+                    .rev()
+                    .map(|e| e.clone().print_with(symbols))
+                    .collect::<Result<Vec<String>, _>>()?;
+                Ok(format!(
+                    "({} . {})",
+                    ss.join(" "),
+                    tail.print_with(symbols)?
+                ))
+            }
+        }
+    }
+}
+
+/// Returns a string literal that reads as the given string.
+pub fn escape_str(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
         }
     }
+
+    escaped.push('"');
+    escaped
 }
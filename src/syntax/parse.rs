@@ -1,85 +1,418 @@
-use std::{cell::RefCell, str::FromStr};
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt::Display,
+    rc::Rc,
+    str::FromStr,
+};
 
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag, take_till, take_while},
-    character::complete::{char, digit1},
-    combinator::{all_consuming, map, map_res, verify},
+    character::complete::char,
+    combinator::{map, verify},
+    error::{ErrorKind, FromExternalError, ParseError as NomParseError},
     multi::{many0, separated_list0},
     sequence::{delimited, pair},
     sequence::{preceded, terminated},
     IResult,
 };
 
-use super::{exp::Exp, symb::Symbols};
+use super::{exp::Exp, span::Span, symb::Symbols};
 
 /// A parser of Risp scripts that uses the `nom` parser combinator library.
 pub struct Parser<'a, Symbs: Symbols> {
     symbols: &'a mut Symbs,
+    file: Rc<str>,
+    line_starts: Vec<usize>,
+    /// The offset and column of the last position located, from which the
+    /// columns of the following positions of its line are counted.
+    last: Cell<(usize, usize)>,
+}
+
+/// Errors that can arise when parsing a Risp script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A list whose opening parenthesis, at the given location, is never closed.
+    UnclosedList(Location),
+    /// A closing parenthesis without a matching opening one.
+    UnexpectedClosingParen(Location),
+    /// A token that starts with a digit but is not a valid number.
+    InvalidNumber(String, Location),
+    /// A quote, quasiquote, unquote or unquote-splicing prefix that is not
+    /// followed by an expression.
+    TrailingQuote(String, Location),
+    /// A string whose opening double quote, at the given location, is never closed.
+    UnclosedString(Location),
+    /// An unknown or malformed escape sequence within a string.
+    InvalidEscape(String, Location),
+    /// Any other input that could not be parsed.
+    UnexpectedInput(Location),
+}
+
+/// The location of a `ParseError` in the parsed input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The name of the parsed file.
+    pub file: String,
+    /// The line, starting from 1.
+    pub line: usize,
+    /// The column in characters, starting from 1.
+    pub column: usize,
+    /// The whole text of the line.
+    pub text: String,
+}
+
+impl ParseError {
+    /// Returns the `Location` of the error.
+    pub fn location(&self) -> &Location {
+        match self {
+            ParseError::UnclosedList(l)
+            | ParseError::UnexpectedClosingParen(l)
+            | ParseError::InvalidNumber(_, l)
+            | ParseError::TrailingQuote(_, l)
+            | ParseError::UnclosedString(l)
+            | ParseError::InvalidEscape(_, l)
+            | ParseError::UnexpectedInput(l) => l,
+        }
+    }
+
+    /// Renders the line of the error with a caret pointing to its column.
+    pub fn snippet(&self) -> String {
+        let location = self.location();
+        format!(
+            "{}\n{}^",
+            location.text,
+            " ".repeat(location.column.saturating_sub(1))
+        )
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ParseError::UnclosedList(_) => "Unclosed list, expected `)`".to_string(),
+            ParseError::UnexpectedClosingParen(_) => "Unexpected `)`".to_string(),
+            ParseError::InvalidNumber(n, _) => format!("Invalid number literal: {n}"),
+            ParseError::TrailingQuote(q, _) if q == "`" => {
+                "Expected an expression after the backquote".to_string()
+            }
+            ParseError::TrailingQuote(q, _) => format!("Expected an expression after `{q}`"),
+            ParseError::UnclosedString(_) => "Unclosed string, expected `\"`".to_string(),
+            ParseError::InvalidEscape(e, _) => format!("Invalid escape sequence: {e}"),
+            ParseError::UnexpectedInput(_) => "Unexpected input".to_string(),
+        }
+    }
 }
 
-impl<'a, Symbs: Symbols> Parser<'a, Symbs> {
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = self.location();
+        write!(
+            f,
+            "{}:{}:{}: {}\n{}",
+            location.file,
+            location.line,
+            location.column,
+            self.message(),
+            self.snippet()
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// The internal `nom` error, pointing to the input where it arose.
+struct Failure<'b> {
+    input: &'b str,
+    kind: FailureKind,
+}
+
+enum FailureKind {
+    Nom,
+    UnclosedList,
+    InvalidNumber(usize),
+    TrailingQuote(usize),
+    UnclosedString,
+    InvalidEscape(usize),
+}
+
+impl<'b> Failure<'b> {
+    fn fail(input: &'b str, kind: FailureKind) -> nom::Err<Self> {
+        nom::Err::Failure(Self { input, kind })
+    }
+}
+
+impl<'b> NomParseError<&'b str> for Failure<'b> {
+    fn from_error_kind(input: &'b str, _kind: ErrorKind) -> Self {
+        Self {
+            input,
+            kind: FailureKind::Nom,
+        }
+    }
+
+    fn append(_input: &'b str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'b, E> FromExternalError<&'b str, E> for Failure<'b> {
+    fn from_external_error(input: &'b str, kind: ErrorKind, _e: E) -> Self {
+        Self::from_error_kind(input, kind)
+    }
+}
+
+type ParseResult<'b, T> = IResult<&'b str, T, Failure<'b>>;
+
+impl<'a, Symbs: Symbols> Parser<'a, Symbs>
+where
+    Symbs::Symb: Copy,
+{
+    /// Creates a parser whose spans refer to an unnamed `<input>` file.
     pub fn new(symbols: &'a mut Symbs) -> Self {
-        Self { symbols }
+        Self::with_file(symbols, "<input>")
+    }
+
+    /// Creates a parser whose spans refer to the given file name.
+    pub fn with_file(symbols: &'a mut Symbs, file: &str) -> Self {
+        Self {
+            symbols,
+            file: Rc::from(file),
+            line_starts: Vec::new(),
+            last: Cell::new((0, 1)),
+        }
     }
 
     /// Parse all the Risp expressions from an input `&str`, consuming it entirely.
-    pub fn parse_all_exps<'b, Bool: From<bool>, Numb: FromStr>(
+    pub fn parse_all_exps<Bool: From<bool> + Clone, Numb: FromStr + Clone>(
         &mut self,
-        input: &'b str,
-    ) -> IResult<&'b str, Vec<Exp<Bool, Numb, Symbs::Symb>>> {
-        all_consuming(preceded(blanks0, |i| self.parse_exps(i)))(input)
+        input: &str,
+    ) -> Result<Vec<Exp<Bool, Numb, Symbs::Symb>>, ParseError> {
+        self.line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        self.last.set((0, 1));
+        let result = preceded(blanks0, |i| self.parse_exps(input, i))(input);
+        match result {
+            Ok(("", exps)) => Ok(exps),
+            Ok((rest, _)) if rest.starts_with(')') => Err(ParseError::UnexpectedClosingParen(
+                self.location(input, rest),
+            )),
+            Ok((rest, _)) => Err(ParseError::UnexpectedInput(self.location(input, rest))),
+            Err(nom::Err::Error(f) | nom::Err::Failure(f)) => Err(self.error(input, f)),
+            Err(nom::Err::Incomplete(_)) => Err(ParseError::UnexpectedInput(
+                self.location(input, &input[input.len()..]),
+            )),
+        }
     }
 
-    fn parse_exp<'b, Bool: From<bool>, Numb: FromStr>(
+    fn error(&self, source: &str, failure: Failure) -> ParseError {
+        let location = self.location(source, failure.input);
+        match failure.kind {
+            FailureKind::Nom => ParseError::UnexpectedInput(location),
+            FailureKind::UnclosedList => ParseError::UnclosedList(location),
+            FailureKind::InvalidNumber(len) => {
+                ParseError::InvalidNumber(failure.input[..len].to_string(), location)
+            }
+            FailureKind::TrailingQuote(len) => {
+                ParseError::TrailingQuote(failure.input[..len].to_string(), location)
+            }
+            FailureKind::UnclosedString => ParseError::UnclosedString(location),
+            FailureKind::InvalidEscape(len) => {
+                ParseError::InvalidEscape(failure.input[..len].to_string(), location)
+            }
+        }
+    }
+
+    fn line_column(&self, source: &str, rest: &str) -> (usize, usize) {
+        let offset = source.len() - rest.len();
+        let line = self.line_starts.partition_point(|&s| s <= offset);
+        let start = self.line_starts[line - 1];
+        // The characters are counted from the last position located when it
+        // is before on the same line, so that long lines are not counted again
+        // for each of their expressions.
+        let (from, column) = match self.last.get() {
+            (last, column) if start <= last && last <= offset => (last, column),
+            _ => (start, 1),
+        };
+        let column = column + source[from..offset].chars().count();
+        self.last.set((offset, column));
+        (line, column)
+    }
+
+    fn location(&self, source: &str, rest: &str) -> Location {
+        let (line, column) = self.line_column(source, rest);
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).map_or(source.len(), |e| e - 1);
+        Location {
+            file: self.file.to_string(),
+            line,
+            column,
+            text: source[start..end].trim_end_matches('\r').to_string(),
+        }
+    }
+
+    fn span(&self, source: &str, rest: &str) -> Span {
+        let (line, column) = self.line_column(source, rest);
+        Span {
+            file: self.file.clone(),
+            line: u32::try_from(line).unwrap_or(u32::MAX),
+            column: u32::try_from(column).unwrap_or(u32::MAX),
+        }
+    }
+
+    fn parse_exp<'b, Bool: From<bool> + Clone, Numb: FromStr + Clone>(
         &mut self,
+        source: &str,
         input: &'b str,
-    ) -> IResult<&'b str, Exp<Bool, Numb, Symbs::Symb>> {
+    ) -> ParseResult<'b, Exp<Bool, Numb, Symbs::Symb>> {
+        let span = Some(self.span(source, input));
         let this = RefCell::new(self);
         let result = alt((
-            (map(|i| this.borrow_mut().parse_list(i), |ls| Exp::List(ls))),
+            (map(
+                |i| this.borrow_mut().parse_list(source, i),
+                |ls| this.borrow_mut().quote_form(ls, span.clone()),
+            )),
+            map(
+                |i| this.borrow_mut().parse_quoted(source, i),
+                |e| Exp::Quot(Box::new(e), span.clone()),
+            ),
             map(
-                |i| this.borrow_mut().parse_quoted(i),
-                |e| Exp::Quot(Box::new(e)),
+                |i| this.borrow_mut().parse_quasiquoted(source, i),
+                |(q, e)| Exp::List(Rc::new(vec![e, Exp::Symb(q, span.clone())]), span.clone()),
             ),
-            map(parse_bool, |b| Exp::Bool(b)),
-            map(parse_numb, |n| Exp::Numb(n)),
+            map(parse_str, |s| Exp::Str(s.into(), span.clone())),
+            map(parse_bool, |b| Exp::Bool(b, span.clone())),
+            map(parse_numb, |n| Exp::Numb(n, span.clone())),
             map(parse_symb, |s| {
-                Exp::Symb(this.borrow_mut().symbols.get_or_store(s))
+                Exp::Symb(this.borrow_mut().symbols.get_or_store(s), span.clone())
             }),
         ))(input);
         result
     }
 
-    fn parse_exps<'b, Bool: From<bool>, Numb: FromStr>(
+    fn parse_exps<'b, Bool: From<bool> + Clone, Numb: FromStr + Clone>(
         &mut self,
+        source: &str,
         input: &'b str,
-    ) -> IResult<&'b str, Vec<Exp<Bool, Numb, Symbs::Symb>>> {
-        many0(terminated(|i| self.parse_exp(i), blanks0))(input)
+    ) -> ParseResult<'b, Vec<Exp<Bool, Numb, Symbs::Symb>>> {
+        many0(terminated(|i| self.parse_exp(source, i), blanks0))(input)
     }
 
-    fn parse_quoted<'b, Bool: From<bool>, Numb: FromStr>(
+    fn parse_quoted<'b, Bool: From<bool> + Clone, Numb: FromStr + Clone>(
         &mut self,
+        source: &str,
         input: &'b str,
-    ) -> IResult<&'b str, Exp<Bool, Numb, Symbs::Symb>> {
-        preceded(char('\''), |i| self.parse_exp(i))(input)
+    ) -> ParseResult<'b, Exp<Bool, Numb, Symbs::Symb>> {
+        let (rest, _) = char('\'')(input)?;
+        match self.parse_exp(source, rest) {
+            Ok((rest, e)) => Ok((rest, self.datum(e))),
+            Err(nom::Err::Error(_)) => Err(Failure::fail(input, FailureKind::TrailingQuote(1))),
+            Err(err) => Err(err),
+        }
     }
 
-    fn parse_list<'b, Bool: From<bool>, Numb: FromStr>(
+    /// Returns a reversed list as an expression, whose operand is read as data
+    /// if it is a `quote` form.
+    fn quote_form<Bool: Clone, Numb: Clone>(
         &mut self,
+        mut ls: Vec<Exp<Bool, Numb, Symbs::Symb>>,
+        span: Option<Span>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        if let [_, Exp::Symb(s, _)] = &ls[..] {
+            if self.symbols.resolve(*s) == Some("quote") {
+                let e = ls.remove(0);
+                ls.insert(0, self.datum(e));
+            }
+        }
+
+        Exp::List(Rc::new(ls), span)
+    }
+
+    /// Returns an expression read as quoted data, where the quoted expressions
+    /// within are `(quote x)` lists and the lists with a `.` before their last
+    /// expression are dotted lists, so that they are all made of pairs.
+    ///
+    /// The quoted expressions within are already read as data.
+    fn datum<Bool: Clone, Numb: Clone>(
+        &mut self,
+        e: Exp<Bool, Numb, Symbs::Symb>,
+    ) -> Exp<Bool, Numb, Symbs::Symb> {
+        match e {
+            Exp::Quot(e, span) => {
+                let quote = self.symbols.get_or_store("quote");
+                Exp::List(Rc::new(vec![*e, Exp::Symb(quote, span.clone())]), span)
+            }
+            Exp::List(ls, span) => {
+                let mut ls: Vec<_> = Rc::unwrap_or_clone(ls)
+                    .into_iter()
+                    .map(|e| self.datum(e))
+                    .collect();
+                match &ls[..] {
+                    [_, Exp::Symb(s, _), _, ..] if self.symbols.resolve(*s) == Some(".") => {
+                        let tail = ls.remove(0);
+                        ls.remove(0);
+                        // A list after the dot continues the list before it.
+                        match tail {
+                            Exp::List(rest, _) => {
+                                ls.splice(0..0, Rc::unwrap_or_clone(rest));
+                                Exp::List(Rc::new(ls), span)
+                            }
+                            Exp::Dotted(rest, tail, _) => {
+                                ls.splice(0..0, Rc::unwrap_or_clone(rest));
+                                Exp::Dotted(Rc::new(ls), tail, span)
+                            }
+                            tail => Exp::Dotted(Rc::new(ls), Box::new(tail), span),
+                        }
+                    }
+                    _ => Exp::List(Rc::new(ls), span),
+                }
+            }
+            e => e,
+        }
+    }
+
+    /// Parses an expression prefixed by `` ` ``, `,` or `,@`, along with the
+    /// symbol of the form that it abbreviates.
+    fn parse_quasiquoted<'b, Bool: From<bool> + Clone, Numb: FromStr + Clone>(
+        &mut self,
+        source: &str,
+        input: &'b str,
+    ) -> ParseResult<'b, (Symbs::Symb, Exp<Bool, Numb, Symbs::Symb>)> {
+        let (rest, prefix) = alt((tag("`"), tag(",@"), tag(",")))(input)?;
+        let form = match prefix {
+            "`" => "quasiquote",
+            ",@" => "unquote-splicing",
+            _ => "unquote",
+        };
+
+        let q = self.symbols.get_or_store(form);
+        match self.parse_exp(source, rest) {
+            Ok((rest, e)) => Ok((rest, (q, e))),
+            Err(nom::Err::Error(_)) => Err(Failure::fail(
+                input,
+                FailureKind::TrailingQuote(prefix.len()),
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn parse_list<'b, Bool: From<bool> + Clone, Numb: FromStr + Clone>(
+        &mut self,
+        source: &str,
         input: &'b str,
-    ) -> IResult<&'b str, Vec<Exp<Bool, Numb, Symbs::Symb>>> {
-        map(
-            delimited(pair(char('('), blanks0), |i| self.parse_exps(i), char(')')),
-            |mut ls| {
+    ) -> ParseResult<'b, Vec<Exp<Bool, Numb, Symbs::Symb>>> {
+        let (rest, _) = pair(char('('), blanks0)(input)?;
+        let (rest, mut ls) = self.parse_exps(source, rest)?;
+        match char::<_, Failure>(')')(rest) {
+            Ok((rest, _)) => {
                 ls.reverse();
-                ls
-            },
-        )(input)
+                Ok((rest, ls))
+            }
+            Err(_) => Err(Failure::fail(input, FailureKind::UnclosedList)),
+        }
     }
 }
 
-fn blanks0(input: &str) -> IResult<&str, Vec<&str>> {
+fn blanks0(input: &str) -> ParseResult<'_, Vec<&str>> {
     delimited(
         take_while(is_blank),
         separated_list0(take_while(is_blank), comment),
@@ -87,7 +420,7 @@ fn blanks0(input: &str) -> IResult<&str, Vec<&str>> {
     )(input)
 }
 
-fn comment(input: &str) -> IResult<&str, &str> {
+fn comment(input: &str) -> ParseResult<'_, &str> {
     preceded(char(';'), take_till(is_line_break))(input)
 }
 
@@ -99,17 +432,234 @@ fn is_blank(chr: char) -> bool {
     chr == ' ' || chr == '\t' || is_line_break(chr)
 }
 
-fn parse_bool<Bool: From<bool>>(input: &str) -> IResult<&str, Bool> {
+fn parse_bool<Bool: From<bool>>(input: &str) -> ParseResult<'_, Bool> {
     alt((
-        map(tag("#t"), |_| Bool::from(true)),
-        map(tag("#f"), |_| Bool::from(false)),
+        map(verify(parse_symb, |s: &str| s == "#t"), |_| {
+            Bool::from(true)
+        }),
+        map(verify(parse_symb, |s: &str| s == "#f"), |_| {
+            Bool::from(false)
+        }),
     ))(input)
 }
 
-fn parse_numb<Numb: FromStr>(input: &str) -> IResult<&str, Numb> {
-    map_res(digit1, |n: &str| n.parse())(input)
+fn parse_numb<Numb: FromStr>(input: &str) -> ParseResult<'_, Numb> {
+    let (rest, n) = verify(parse_symb, |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_digit())
+    })(input)?;
+    match n.parse() {
+        Ok(n) => Ok((rest, n)),
+        Err(_) => Err(Failure::fail(input, FailureKind::InvalidNumber(n.len()))),
+    }
 }
 
-fn parse_symb(input: &str) -> IResult<&str, &str> {
-    verify(is_not(" \t\n\r()'"), |s: &str| !s.is_empty())(input)
+fn parse_str(input: &str) -> ParseResult<'_, String> {
+    let (mut rest, _) = char('"')(input)?;
+    let mut s = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            None => return Err(Failure::fail(input, FailureKind::UnclosedString)),
+            Some('"') => return Ok((chars.as_str(), s)),
+            Some('\\') => {
+                let (after, c) = parse_escape(rest)?;
+                s.push(c);
+                rest = after;
+            }
+            Some(c) => {
+                s.push(c);
+                rest = chars.as_str();
+            }
+        }
+    }
+}
+
+fn parse_escape(input: &str) -> ParseResult<'_, char> {
+    let invalid = |len| Failure::fail(input, FailureKind::InvalidEscape(len));
+
+    let mut chars = input.chars().skip(1);
+    let c = match chars.next() {
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('t') => '\t',
+        Some('"') => '"',
+        Some('\\') => '\\',
+        Some('u') => {
+            let rest = &input[2..];
+            let end = match (rest.strip_prefix('{'), rest.find('}')) {
+                (Some(_), Some(end)) => end,
+                _ => return Err(invalid(2)),
+            };
+
+            return match u32::from_str_radix(&rest[1..end], 16)
+                .ok()
+                .and_then(char::from_u32)
+            {
+                Some(c) => Ok((&rest[end + 1..], c)),
+                None => Err(invalid(end + 3)),
+            };
+        }
+        Some(c) => return Err(invalid(1 + c.len_utf8())),
+        None => return Err(invalid(1)),
+    };
+
+    Ok((&input[2..], c))
+}
+
+fn parse_symb(input: &str) -> ParseResult<'_, &str> {
+    verify(is_not(" \t\n\r()'\"`,"), |s: &str| !s.is_empty())(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::syntax::{exp::Exp, symb::Symbols, symb_interner::SymbolsInterner};
+
+    use super::{ParseError, Parser};
+
+    type Symb = <SymbolsInterner as Symbols>::Symb;
+
+    fn parse(input: &str) -> Result<Vec<Exp<bool, i64, Symb>>, ParseError> {
+        let mut symbols = SymbolsInterner::new();
+        Parser::with_file(&mut symbols, "test.rsp").parse_all_exps(input)
+    }
+
+    /// Returns the error of parsing an input along with its line and column.
+    fn error(input: &str) -> (ParseError, usize, usize) {
+        match parse(input) {
+            Ok(_) => panic!("parsed {input}"),
+            Err(err) => {
+                let (line, column) = (err.location().line, err.location().column);
+                (err, line, column)
+            }
+        }
+    }
+
+    #[test]
+    fn unclosed_list() {
+        let (err, line, column) = error("(define x 1)\n(+ x\n  (* 2 3)");
+        assert!(matches!(err, ParseError::UnclosedList(_)), "{err}");
+        assert_eq!((line, column), (2, 1));
+    }
+
+    #[test]
+    fn unexpected_closing_paren() {
+        let (err, line, column) = error("(+ 1 2))");
+        assert!(
+            matches!(err, ParseError::UnexpectedClosingParen(_)),
+            "{err}"
+        );
+        assert_eq!((line, column), (1, 8));
+    }
+
+    #[test]
+    fn invalid_number() {
+        let (err, line, column) = error("(+ 1 2x)");
+        assert!(
+            matches!(&err, ParseError::InvalidNumber(n, _) if n == "2x"),
+            "{err}"
+        );
+        assert_eq!((line, column), (1, 6));
+    }
+
+    #[test]
+    fn trailing_quote() {
+        let (err, line, column) = error("(list 1 ')");
+        assert!(
+            matches!(&err, ParseError::TrailingQuote(q, _) if q == "'"),
+            "{err}"
+        );
+        assert_eq!((line, column), (1, 9));
+        let (err, _, _) = error("`");
+        assert!(
+            matches!(&err, ParseError::TrailingQuote(q, _) if q == "`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unclosed_string() {
+        let (err, line, column) = error("(display \"hello)");
+        assert!(matches!(err, ParseError::UnclosedString(_)), "{err}");
+        assert_eq!((line, column), (1, 10));
+    }
+
+    #[test]
+    fn invalid_escape() {
+        let (err, line, column) = error("(display \"a\\qb\")");
+        assert!(
+            matches!(&err, ParseError::InvalidEscape(e, _) if e == "\\q"),
+            "{err}"
+        );
+        assert_eq!((line, column), (1, 12));
+    }
+
+    #[test]
+    fn errors_render_their_location_and_line() {
+        let (err, _, _) = error("(define x 1)\n  (+ x 1))\n");
+        assert_eq!(
+            err.to_string(),
+            "test.rsp:2:10: Unexpected `)`\n  (+ x 1))\n         ^"
+        );
+    }
+
+    #[test]
+    fn quoted_data_are_made_of_lists() {
+        let mut symbols = SymbolsInterner::new();
+        let exps: Vec<Exp<bool, i64, Symb>> = Parser::with_file(&mut symbols, "test.rsp")
+            .parse_all_exps("''a '(1 . 2) '(1 . (2 3)) (quote (1 2 . 3)) (f . x)")
+            .ok()
+            .unwrap();
+        let quote = symbols.get_or_store("quote");
+        let a = symbols.get_or_store("a");
+        let dot = symbols.get_or_store(".");
+        let f = symbols.get_or_store("f");
+        let x = symbols.get_or_store("x");
+        let numb = |n| Exp::Numb(n, None);
+        let list = |ls: Vec<_>| Exp::List(ls.into(), None);
+        let quot = |e| Exp::Quot(Box::new(e), None);
+        assert_eq!(
+            exps,
+            [
+                quot(list(vec![Exp::Symb(a, None), Exp::Symb(quote, None)])),
+                quot(Exp::Dotted(vec![numb(1)].into(), Box::new(numb(2)), None)),
+                quot(list(vec![numb(3), numb(2), numb(1)])),
+                list(vec![
+                    Exp::Dotted(vec![numb(2), numb(1)].into(), Box::new(numb(3)), None),
+                    Exp::Symb(quote, None)
+                ]),
+                list(vec![
+                    Exp::Symb(x, None),
+                    Exp::Symb(dot, None),
+                    Exp::Symb(f, None)
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_are_recorded_on_all_expressions() {
+        let exps = parse("(f\n  'x \"s\" #t 12)").ok().unwrap();
+        let Exp::List(ls, span) = &exps[0] else {
+            panic!("not a list");
+        };
+
+        let at =
+            |span: Option<_>| span.map(|s: super::Span| (s.file.to_string(), s.line, s.column));
+        assert_eq!(at(span.clone()), Some(("test.rsp".to_string(), 1, 1)));
+        let spans: Vec<_> = ls
+            .iter()
+            .rev()
+            .map(|e| at(e.span()).map(|(_, l, c)| (l, c)))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                Some((1, 2)),
+                Some((2, 3)),
+                Some((2, 6)),
+                Some((2, 10)),
+                Some((2, 13))
+            ]
+        );
+    }
 }
@@ -0,0 +1,22 @@
+use std::{fmt::Display, rc::Rc};
+
+/// A location in a source file, whose name is shared by the spans read from it
+/// rather than stored as a symbol of the language.
+///
+/// The line and column are 32 bits wide, as a span is kept in each expression
+/// and in each frame of the continuation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// The name of the source file.
+    pub file: Rc<str>,
+    /// The line, starting from 1.
+    pub line: u32,
+    /// The column in characters, starting from 1.
+    pub column: u32,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
@@ -6,6 +6,9 @@ pub trait Symbols {
     /// Returns the string that corresponds to a given symbol, if stored.
     fn resolve(&self, symbol: Self::Symb) -> Option<&str>;
 
+    /// Returns the symbol that corresponds to a given string, if stored.
+    fn get(&self, string: &str) -> Option<Self::Symb>;
+
     /// Returns the symbol that corresponds to a given string, by storing it if necessary.
     fn get_or_store(&mut self, string: &str) -> Self::Symb;
 }
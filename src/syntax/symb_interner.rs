@@ -16,6 +16,12 @@ impl SymbolsInterner {
     }
 }
 
+impl Default for SymbolsInterner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Symbols for SymbolsInterner {
     type Symb = SymbolU32;
 
@@ -23,6 +29,10 @@ impl Symbols for SymbolsInterner {
         self.interner.resolve(symbol)
     }
 
+    fn get(&self, string: &str) -> Option<Self::Symb> {
+        self.interner.get(string)
+    }
+
     fn get_or_store(&mut self, string: &str) -> Self::Symb {
         self.interner.get_or_intern(string)
     }
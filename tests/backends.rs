@@ -0,0 +1,55 @@
+//! Runs every example script and every probe on both backends, which must
+//! write the output and end with the value or error expected in the `.out`
+//! file next to the script.
+
+use std::{fs, path::Path};
+
+use risp::{semantics::port::OutputPort, Backend, Interpreter};
+
+/// Runs a script with a backend, returning what it writes followed by its
+/// value or its error.
+fn run(path: &Path, backend: Backend) -> String {
+    let output = OutputPort::string();
+    let mut interpreter: Interpreter = Interpreter::new()
+        .with_backend(backend)
+        .with_output(output.clone());
+    let end = match interpreter.eval_file(path) {
+        Ok(v) => interpreter.print(v),
+        Err(err) => interpreter.print(err),
+    };
+
+    format!("{}\n{end}", output.contents().unwrap_or_default())
+}
+
+/// Runs the scripts of a directory on both backends, comparing the result of
+/// each with the expected one.
+fn run_all(dir: &str) {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rsp"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let expected = fs::read_to_string(path.with_extension("out")).unwrap();
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            assert_eq!(
+                run(&path, backend),
+                expected,
+                "{} with {backend:?}",
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn examples() {
+    run_all("examples");
+}
+
+#[test]
+fn probes() {
+    run_all("tests/probes");
+}